@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use sha2::{Digest, Sha256};
 use sqlx::SqlitePool;
 
@@ -36,7 +36,9 @@ pub struct ApiKeyRecord {
     pub id: String,
     pub name: String,
     pub key_prefix: String,
-    pub raw_key: String,
+    /// The full key; only set on a record opened with `open_raw_key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_key: Option<String>,
     pub enabled: bool,
     pub expires_at: Option<String>,
     pub rate_limit: i32,
@@ -45,6 +47,19 @@ pub struct ApiKeyRecord {
     pub metadata: serde_json::Value,
     pub created_at: String,
     pub last_used_at: Option<String>,
+    /// Sealed `raw_key` column as stored.
+    #[serde(skip)]
+    sealed_key: String,
+}
+
+impl ApiKeyRecord {
+    /// Decrypt the stored full key into `raw_key`, for the admin views that
+    /// show it.
+    pub fn open_raw_key(self) -> Result<Self> {
+        let raw_key = crate::crypto::open(&self.sealed_key)
+            .with_context(|| format!("cannot decrypt API key {}", self.id))?;
+        Ok(Self { raw_key: Some(raw_key), ..self })
+    }
 }
 
 /// Row from SQLite.
@@ -70,7 +85,7 @@ impl From<ApiKeyRow> for ApiKeyRecord {
             id: row.id,
             name: row.name,
             key_prefix: row.key_prefix,
-            raw_key: row.raw_key,
+            raw_key: None,
             enabled: row.enabled,
             expires_at: row.expires_at,
             rate_limit: row.rate_limit,
@@ -79,6 +94,7 @@ impl From<ApiKeyRow> for ApiKeyRecord {
             metadata: serde_json::from_str(&row.metadata).unwrap_or(serde_json::Value::Object(Default::default())),
             created_at: row.created_at,
             last_used_at: row.last_used_at,
+            sealed_key: row.raw_key,
         }
     }
 }
@@ -118,6 +134,7 @@ pub async fn create(
     let id = uuid::Uuid::new_v4().to_string();
     let hash = hash_key(&raw_key);
     let prefix = key_prefix(&raw_key);
+    let sealed_key = crate::crypto::seal(&raw_key)?;
     let scopes_json = serde_json::to_string(scopes)?;
     let metadata_json = serde_json::to_string(metadata)?;
 
@@ -129,7 +146,7 @@ pub async fn create(
     .bind(name)
     .bind(&hash)
     .bind(&prefix)
-    .bind(&raw_key)
+    .bind(&sealed_key)
     .bind(expires_at)
     .bind(rate_limit)
     .bind(daily_quota)
@@ -142,7 +159,7 @@ pub async fn create(
         id,
         name: name.to_string(),
         key_prefix: prefix,
-        raw_key: raw_key.clone(),
+        raw_key: Some(raw_key.clone()),
         enabled: true,
         expires_at: expires_at.map(String::from),
         rate_limit,
@@ -151,6 +168,7 @@ pub async fn create(
         metadata: metadata.clone(),
         created_at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
         last_used_at: None,
+        sealed_key,
     };
 
     Ok((raw_key, record))
@@ -260,7 +278,7 @@ pub async fn regenerate(db: &SqlitePool, id: &str) -> Result<Option<String>> {
     )
     .bind(&hash)
     .bind(&prefix)
-    .bind(&raw_key)
+    .bind(crate::crypto::seal(&raw_key)?)
     .bind(id)
     .execute(db)
     .await?;
@@ -94,13 +94,13 @@ pub async fn api_key_auth(
     };
 
     // Check env-var admin token first
-    if let Some(ref admin_token) = state.config.admin_token {
-        if token == *admin_token {
-            request
-                .extensions_mut()
-                .insert(Caller::Admin { source: AdminSource::EnvToken });
-            return next.run(request).await;
-        }
+    if let Some(ref admin_token) = state.config.admin_token
+        && token == *admin_token
+    {
+        request
+            .extensions_mut()
+            .insert(Caller::Admin { source: AdminSource::EnvToken });
+        return next.run(request).await;
     }
 
     // Look up API key by hash
@@ -134,16 +134,15 @@ pub async fn api_key_auth(
     }
 
     // Check expiry
-    if let Some(ref expires_at) = record.expires_at {
-        if let Ok(exp) = chrono::DateTime::parse_from_rfc3339(expires_at) {
-            if chrono::Utc::now() > exp {
-                return (
-                    StatusCode::FORBIDDEN,
-                    Json(serde_json::json!({ "error": "API key has expired" })),
-                )
-                    .into_response();
-            }
-        }
+    if let Some(ref expires_at) = record.expires_at
+        && let Ok(exp) = chrono::DateTime::parse_from_rfc3339(expires_at)
+        && chrono::Utc::now() > exp
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "API key has expired" })),
+        )
+            .into_response();
     }
 
     // Admin scope key
@@ -203,6 +202,7 @@ fn inject_rate_limit_headers(headers: &mut HeaderMap, rl: &RateLimitResult) {
 }
 
 /// Scope guard: returns 403 if the caller lacks the required scope.
+#[allow(clippy::result_large_err)]
 pub fn require_scope(caller: &Caller, scope: &str) -> Result<(), Response> {
     if caller.has_scope(scope) {
         Ok(())
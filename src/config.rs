@@ -2,6 +2,9 @@ use std::env;
 
 use anyhow::{Context, Result};
 
+use crate::jimeng::client::BaseUrls;
+use crate::jimeng::transport::{TransportKind, TransportPolicy};
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
@@ -28,6 +31,39 @@ pub struct Config {
     pub oidc_client_secret: Option<String>,
     /// OAuth2 redirect URL (callback endpoint on this gateway)
     pub oidc_redirect_url: Option<String>,
+    /// Webhook URL for gateway events (e.g. `session.expiring`)
+    pub events_webhook_url: Option<String>,
+    /// HMAC secret for event webhooks
+    pub events_webhook_secret: Option<String>,
+    /// Send `session.expiring` this many days before a session expires
+    pub session_expiry_warn_days: i64,
+    /// Disable sessions this many hours before they expire
+    pub session_expiry_disable_hours: i64,
+    /// Refresh a session's cookies once they are this old
+    pub cookie_refresh_max_age_mins: u64,
+    /// Max concurrent browser cookie refreshes
+    pub cookie_refresh_concurrency: usize,
+    /// Minimum seconds between two submits on one session (0 = no pacing)
+    pub submit_min_interval_secs: u32,
+    /// Random extra seconds added to each submit interval
+    pub submit_jitter_secs: u32,
+    /// Submits per session per Asia/Shanghai day (0 = unlimited)
+    pub session_daily_submit_cap: u32,
+    /// Upstream jimeng, ImageX and VOD base URLs
+    pub base_urls: BaseUrls,
+    /// Transport fallback chains per endpoint class
+    pub transports: TransportPolicy,
+    /// JSON file of upstream client profiles (built-in profile if not set)
+    pub client_profiles_file: Option<String>,
+    /// JSON model registry (built-in registry if not set)
+    pub models_file: Option<String>,
+    /// How long an uploaded material is reused by later tasks of the same
+    /// session (0 = always upload)
+    pub material_cache_ttl_secs: u64,
+    /// Directory reference files of tasks are spooled to
+    pub upload_dir: String,
+    /// Largest multipart create request, reference files included
+    pub max_upload_mb: u64,
 }
 
 impl Config {
@@ -61,6 +97,84 @@ impl Config {
             oidc_client_id: env::var("OIDC_CLIENT_ID").ok().filter(|s| !s.is_empty()),
             oidc_client_secret: env::var("OIDC_CLIENT_SECRET").ok().filter(|s| !s.is_empty()),
             oidc_redirect_url: env::var("OIDC_REDIRECT_URL").ok().filter(|s| !s.is_empty()),
+            events_webhook_url: env::var("EVENTS_WEBHOOK_URL").ok().filter(|s| !s.is_empty()),
+            events_webhook_secret: env::var("EVENTS_WEBHOOK_SECRET").ok().filter(|s| !s.is_empty()),
+            session_expiry_warn_days: env::var("SESSION_EXPIRY_WARN_DAYS")
+                .unwrap_or_else(|_| "3".into())
+                .parse()
+                .unwrap_or(3),
+            session_expiry_disable_hours: env::var("SESSION_EXPIRY_DISABLE_HOURS")
+                .unwrap_or_else(|_| "6".into())
+                .parse()
+                .unwrap_or(6),
+            cookie_refresh_max_age_mins: env::var("COOKIE_REFRESH_MAX_AGE_MINS")
+                .unwrap_or_else(|_| "120".into())
+                .parse()
+                .unwrap_or(120),
+            cookie_refresh_concurrency: env::var("COOKIE_REFRESH_CONCURRENCY")
+                .unwrap_or_else(|_| "1".into())
+                .parse()
+                .unwrap_or(1),
+            submit_min_interval_secs: env::var("SUBMIT_MIN_INTERVAL_SECS")
+                .unwrap_or_else(|_| "0".into())
+                .parse()
+                .unwrap_or(0),
+            submit_jitter_secs: env::var("SUBMIT_JITTER_SECS")
+                .unwrap_or_else(|_| "0".into())
+                .parse()
+                .unwrap_or(0),
+            session_daily_submit_cap: env::var("SESSION_DAILY_SUBMIT_CAP")
+                .unwrap_or_else(|_| "0".into())
+                .parse()
+                .unwrap_or(0),
+            transports: transport_policy()?,
+            client_profiles_file: env::var("CLIENT_PROFILES_FILE").ok().filter(|s| !s.is_empty()),
+            models_file: env::var("MODELS_FILE").ok().filter(|s| !s.is_empty()),
+            material_cache_ttl_secs: env::var("MATERIAL_CACHE_TTL_SECS")
+                .unwrap_or_else(|_| "86400".into())
+                .parse()
+                .unwrap_or(86400),
+            upload_dir: env::var("UPLOAD_DIR")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| "data/uploads".into()),
+            max_upload_mb: env::var("MAX_UPLOAD_MB")
+                .unwrap_or_else(|_| "200".into())
+                .parse()
+                .unwrap_or(200),
+            base_urls: {
+                let defaults = BaseUrls::default();
+                let url = |key: &str, default: String| env::var(key)
+                    .ok()
+                    .map(|s| s.trim_end_matches('/').to_string())
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or(default);
+                BaseUrls {
+                    jimeng: url("JIMENG_BASE_URL", defaults.jimeng),
+                    imagex: url("IMAGEX_BASE_URL", defaults.imagex),
+                    vod: url("VOD_BASE_URL", defaults.vod),
+                }
+            },
         })
     }
 }
+
+/// `TRANSPORT_{SUBMIT,POLL,UPLOAD,ACCOUNT}` chains, e.g. `curl,reqwest,browser`.
+/// The older `ABOGUS_MODE=browser` still means browser-only submits.
+fn transport_policy() -> Result<TransportPolicy> {
+    let mut policy = TransportPolicy::default();
+    if env::var("ABOGUS_MODE").is_ok_and(|v| v == "browser") {
+        policy.submit = vec![TransportKind::Browser];
+    }
+    for (key, chain) in [
+        ("TRANSPORT_SUBMIT", &mut policy.submit),
+        ("TRANSPORT_POLL", &mut policy.poll),
+        ("TRANSPORT_UPLOAD", &mut policy.upload),
+        ("TRANSPORT_ACCOUNT", &mut policy.account),
+    ] {
+        if let Some(value) = env::var(key).ok().filter(|s| !s.is_empty()) {
+            *chain = TransportPolicy::parse_chain(&value).with_context(|| format!("invalid {key}"))?;
+        }
+    }
+    Ok(policy)
+}
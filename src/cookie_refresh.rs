@@ -0,0 +1,311 @@
+//! Per-session cookie refresh scheduling.
+//!
+//! A session's cookies are re-harvested through the headless browser when
+//! they get old (`COOKIE_REFRESH_MAX_AGE_MINS`) or are about to expire, right
+//! after the session is added, and after a task fails with an auth or 4013
+//! risk-control error. Browser work is bounded by `COOKIE_REFRESH_CONCURRENCY`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{Semaphore, mpsc};
+
+use crate::AppState;
+use crate::pool::SessionInfo;
+
+/// How often sessions are checked for a due refresh.
+const SCAN_INTERVAL: Duration = Duration::from_secs(60);
+/// Delay before the first scan, so startup isn't slowed by browser launches.
+const STARTUP_DELAY: Duration = Duration::from_secs(30);
+/// Refresh this long before the earliest cookie in the jar expires.
+const EXPIRY_LEAD_SECS: i64 = 10 * 60;
+/// Scheduled refreshes of one session are at least this far apart.
+const MIN_INTERVAL_SECS: i64 = 30 * 60;
+/// First retry delay after a failed refresh; doubles per consecutive failure.
+const RETRY_BASE_SECS: i64 = 5 * 60;
+/// Auth-failure triggers are ignored this soon after the previous attempt,
+/// so a burst of failing tasks causes one refresh.
+const TRIGGER_COOLDOWN_SECS: i64 = 5 * 60;
+
+/// Why a refresh was started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    Scheduled,
+    Added,
+    AuthFailure,
+    Manual,
+}
+
+impl Trigger {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Scheduled => "scheduled",
+            Self::Added => "added",
+            Self::AuthFailure => "auth_failure",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+/// Where a session is in the refresh pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshState {
+    Idle,
+    /// Waiting for a browser slot.
+    Queued,
+    Running,
+}
+
+/// Result of a successful refresh.
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshOutcome {
+    /// Cookies returned by the browser.
+    pub harvested: usize,
+    /// Cookies in the session's jar after merging.
+    pub total: usize,
+}
+
+/// Session id and what triggered its refresh.
+type RefreshRequest = (String, Trigger);
+
+#[derive(Clone)]
+pub struct RefreshScheduler {
+    requests: mpsc::UnboundedSender<RefreshRequest>,
+    /// Taken by `run`.
+    receiver: Arc<Mutex<Option<mpsc::UnboundedReceiver<RefreshRequest>>>>,
+    permits: Arc<Semaphore>,
+    states: Arc<Mutex<HashMap<String, RefreshState>>>,
+    max_age_secs: i64,
+}
+
+impl RefreshScheduler {
+    pub fn new(concurrency: usize, max_age_mins: u64) -> Self {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        Self {
+            requests,
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+            states: Arc::new(Mutex::new(HashMap::new())),
+            max_age_secs: (max_age_mins as i64).max(1) * 60,
+        }
+    }
+
+    /// Ask for a refresh of `session_id` as soon as a browser slot is free.
+    pub fn trigger(&self, session_id: &str, trigger: Trigger) {
+        let _ = self.requests.send((session_id.to_string(), trigger));
+    }
+
+    pub fn state_of(&self, session_id: &str) -> RefreshState {
+        self.states
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .copied()
+            .unwrap_or(RefreshState::Idle)
+    }
+
+    /// When the session's next scheduled refresh is due.
+    pub fn next_due(&self, session: &SessionInfo) -> DateTime<Utc> {
+        // The pool keeps the jar sealed; if it doesn't open, only age counts.
+        let opened = session.open_secrets();
+        due_at(opened.as_ref().unwrap_or(session), self.max_age_secs)
+    }
+
+    /// Refresh now and wait for the result (manual harvest).
+    pub async fn refresh_now(&self, state: &AppState, session_id: &str, trigger: Trigger) -> Result<RefreshOutcome> {
+        if !self.claim(session_id) {
+            bail!("cookie refresh already in progress");
+        }
+        self.run_claimed(state, session_id, trigger).await
+    }
+
+    /// Mark a session queued; false if it is already queued or running.
+    fn claim(&self, session_id: &str) -> bool {
+        let mut states = self.states.lock().unwrap();
+        if states.contains_key(session_id) {
+            return false;
+        }
+        states.insert(session_id.to_string(), RefreshState::Queued);
+        true
+    }
+
+    async fn run_claimed(&self, state: &AppState, session_id: &str, trigger: Trigger) -> Result<RefreshOutcome> {
+        let session = state.pool.open_session(session_id).await
+            .and_then(|session| session.ok_or_else(|| anyhow!("session not found")));
+        let session = match session {
+            Ok(session) => session,
+            Err(e) => {
+                self.states.lock().unwrap().remove(session_id);
+                return Err(e);
+            }
+        };
+
+        let result: Result<RefreshOutcome> = async {
+            let _permit = self.permits.acquire().await?;
+            self.states.lock().unwrap().insert(session_id.to_string(), RefreshState::Running);
+            let harvested = state.browser
+                .harvest_cookies(&session.session_id, &session.fingerprint, session.proxy_url.as_deref())
+                .await?;
+            let count = harvested.len();
+            // Merge so cookies set during API calls survive the refresh.
+            let jar = state.pool
+                .merge_cookies(session_id, harvested.into_iter().collect())
+                .await?
+                .ok_or_else(|| anyhow!("session not found"))?;
+            Ok(RefreshOutcome { harvested: count, total: jar.len() })
+        }
+        .await;
+        self.states.lock().unwrap().remove(session_id);
+
+        let outcome = result.as_ref().map(|o| o.harvested).map_err(|e| e.to_string());
+        let outcome = outcome.as_ref().copied().map_err(String::as_str);
+        if let Err(e) = state.pool.record_cookie_refresh(session_id, trigger.as_str(), outcome).await {
+            tracing::warn!(session_id, error = %e, "Failed to record cookie refresh");
+        }
+        match &result {
+            Ok(o) => tracing::info!(session_id, trigger = trigger.as_str(), cookies = o.harvested, "Cookies refreshed"),
+            Err(e) => tracing::warn!(session_id, trigger = trigger.as_str(), error = %e, "Cookie refresh failed"),
+        }
+        result
+    }
+}
+
+/// Background loop: serve triggered refreshes and start scheduled ones.
+pub async fn run(state: Arc<AppState>) {
+    let Some(mut requests) = state.refresh.receiver.lock().unwrap().take() else {
+        return;
+    };
+    let mut scan = tokio::time::interval_at(tokio::time::Instant::now() + STARTUP_DELAY, SCAN_INTERVAL);
+
+    loop {
+        tokio::select! {
+            Some((session_id, trigger)) = requests.recv() => {
+                if trigger == Trigger::AuthFailure {
+                    let sessions = state.pool.list_sessions().await;
+                    let recent = sessions.iter()
+                        .find(|s| s.id == session_id)
+                        .and_then(|s| parse_ts(s.cookie_refresh_attempted_at.as_deref()))
+                        .is_some_and(|t| (Utc::now() - t).num_seconds() < TRIGGER_COOLDOWN_SECS);
+                    if recent {
+                        continue;
+                    }
+                }
+                spawn_refresh(&state, session_id, trigger);
+            }
+            _ = scan.tick() => {
+                let now = Utc::now();
+                for session in state.pool.list_sessions().await {
+                    if session.enabled && state.refresh.next_due(&session) <= now {
+                        spawn_refresh(&state, session.id, Trigger::Scheduled);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn spawn_refresh(state: &Arc<AppState>, session_id: String, trigger: Trigger) {
+    if !state.refresh.claim(&session_id) {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        let _ = state.refresh.run_claimed(&state, &session_id, trigger).await;
+    });
+}
+
+/// Due time: cookie age past `max_age_secs` or the login cookies close to
+/// expiring, but not before the minimum interval (or failure backoff) since
+/// the last attempt.
+fn due_at(session: &SessionInfo, max_age_secs: i64) -> DateTime<Utc> {
+    let mut due = parse_ts(session.cookies_refreshed_at.as_deref())
+        .map_or(DateTime::UNIX_EPOCH, |t| t + chrono::Duration::seconds(max_age_secs));
+
+    let before_expiry = session
+        .cookie_jar
+        .as_ref()
+        .and_then(|jar| jar.auth_expiry())
+        .and_then(|expiry| DateTime::from_timestamp(expiry - EXPIRY_LEAD_SECS, 0));
+    if let Some(before_expiry) = before_expiry {
+        due = due.min(before_expiry);
+    }
+
+    if let Some(attempted) = parse_ts(session.cookie_refresh_attempted_at.as_deref()) {
+        let wait = match session.cookie_refresh_failures {
+            0 => MIN_INTERVAL_SECS,
+            n => (RETRY_BASE_SECS << (n - 1).min(10)).min(max_age_secs),
+        };
+        due = due.max(attempted + chrono::Duration::seconds(wait));
+    }
+    due
+}
+
+fn parse_ts(value: Option<&str>) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value?, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jimeng::cookies::{Cookie, CookieJar};
+
+    fn session(refreshed: Option<&str>, attempted: Option<&str>, failures: i32) -> SessionInfo {
+        SessionInfo {
+            id: "s1".into(),
+            session_id: "tok".into(),
+            enabled: true,
+            healthy: true,
+            cookies_refreshed_at: refreshed.map(Into::into),
+            cookie_refresh_attempted_at: attempted.map(Into::into),
+            cookie_refresh_failures: failures,
+            ..Default::default()
+        }
+    }
+
+    fn ts(s: &str) -> DateTime<Utc> {
+        parse_ts(Some(s)).unwrap()
+    }
+
+    #[test]
+    fn test_due_at() {
+        // Never refreshed: due immediately.
+        assert_eq!(due_at(&session(None, None, 0), 7200), DateTime::UNIX_EPOCH);
+
+        // Refreshed: due after max age.
+        let s = session(Some("2099-01-01 00:00:00"), Some("2099-01-01 00:00:00"), 0);
+        assert_eq!(due_at(&s, 7200), ts("2099-01-01 02:00:00"));
+
+        // Short-lived tracking cookies don't move the refresh.
+        let mut s = s;
+        s.cookie_jar = Some(CookieJar::from_cookies([Cookie {
+            expires: Some(ts("2099-01-01 00:20:00").timestamp()),
+            ..Cookie::new("msToken", "x")
+        }]));
+        assert_eq!(due_at(&s, 7200), ts("2099-01-01 02:00:00"));
+
+        // A login cookie expiring soon pulls it forward, but not before the
+        // minimum interval.
+        s.cookie_jar = Some(CookieJar::from_cookies([
+            Cookie { expires: Some(ts("2099-01-01 00:20:00").timestamp()), ..Cookie::new("msToken", "x") },
+            Cookie { expires: Some(ts("2099-01-01 01:10:00").timestamp()), ..Cookie::new("sid_guard", "x") },
+        ]));
+        assert_eq!(due_at(&s, 7200), ts("2099-01-01 01:00:00"));
+        s.cookie_jar = Some(CookieJar::from_cookies([Cookie {
+            expires: Some(ts("2099-01-01 00:20:00").timestamp()),
+            ..Cookie::new("sessionid", "x")
+        }]));
+        assert_eq!(due_at(&s, 7200), ts("2099-01-01 00:30:00"));
+
+        // Failures back off exponentially from the last attempt.
+        let s = session(Some("2099-01-01 00:00:00"), Some("2099-01-01 03:00:00"), 3);
+        assert_eq!(due_at(&s, 7200), ts("2099-01-01 03:20:00"));
+    }
+}
@@ -0,0 +1,416 @@
+//! Encryption helpers.
+//!
+//! - Passphrase bundles for session export: AES-256-GCM with a key derived
+//!   via PBKDF2-HMAC-SHA256; salt and nonce are stored alongside the ciphertext.
+//! - Envelope encryption at rest: secrets in SQLite are sealed with a data
+//!   key (DEK), and DEKs are stored wrapped by the master key from
+//!   `MASTER_KEY` / `MASTER_KEY_FILE`. Sealed values look like
+//!   `enc:v1:<key id>:<base64 nonce+ciphertext>`; anything else is plaintext.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{Context, bail, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as B64;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+/// PBKDF2 iteration count for newly encrypted bundles.
+const PBKDF2_ITERATIONS: u32 = 600_000;
+/// Iteration counts a bundle may declare: no weaker than ours, and no more
+/// than a bounded amount of work for the server.
+const ACCEPTED_ITERATIONS: std::ops::RangeInclusive<u32> = PBKDF2_ITERATIONS..=10 * PBKDF2_ITERATIONS;
+/// Minimum accepted passphrase length.
+pub const MIN_PASSPHRASE_LEN: usize = 12;
+
+/// An encrypted blob plus everything needed to decrypt it with the passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedBundle {
+    /// Always `"pbkdf2-sha256"`.
+    pub kdf: String,
+    pub iterations: u32,
+    /// Base64 salt.
+    pub salt: String,
+    /// Base64 96-bit AES-GCM nonce.
+    pub nonce: String,
+    /// Base64 ciphertext (with GCM tag).
+    pub ciphertext: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+/// Encrypt `plaintext` under `passphrase`.
+pub fn encrypt_with_passphrase(plaintext: &[u8], passphrase: &str) -> Result<EncryptedBundle> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        bail!("passphrase must be at least {MIN_PASSPHRASE_LEN} characters");
+    }
+    let salt: [u8; 16] = rand::random();
+    let nonce: [u8; 12] = rand::random();
+    let key = derive_key(passphrase, &salt, PBKDF2_ITERATIONS);
+
+    let cipher = Aes256Gcm::new_from_slice(&key)?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+
+    Ok(EncryptedBundle {
+        kdf: "pbkdf2-sha256".to_string(),
+        iterations: PBKDF2_ITERATIONS,
+        salt: B64.encode(salt),
+        nonce: B64.encode(nonce),
+        ciphertext: B64.encode(ciphertext),
+    })
+}
+
+/// Decrypt a bundle produced by [`encrypt_with_passphrase`].
+pub fn decrypt_with_passphrase(bundle: &EncryptedBundle, passphrase: &str) -> Result<Vec<u8>> {
+    if bundle.kdf != "pbkdf2-sha256" {
+        bail!("unsupported kdf '{}'", bundle.kdf);
+    }
+    if !ACCEPTED_ITERATIONS.contains(&bundle.iterations) {
+        bail!(
+            "unsupported kdf iterations {} (expected {} to {})",
+            bundle.iterations,
+            ACCEPTED_ITERATIONS.start(),
+            ACCEPTED_ITERATIONS.end(),
+        );
+    }
+    let salt = B64.decode(&bundle.salt)?;
+    let nonce = B64.decode(&bundle.nonce)?;
+    let ciphertext = B64.decode(&bundle.ciphertext)?;
+    if nonce.len() != 12 {
+        bail!("invalid nonce length");
+    }
+
+    let key = derive_key(passphrase, &salt, bundle.iterations);
+    let cipher = Aes256Gcm::new_from_slice(&key)?;
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| anyhow::anyhow!("wrong passphrase or corrupted bundle"))
+}
+
+// ---------------------------------------------------------------------------
+// Envelope encryption at rest
+// ---------------------------------------------------------------------------
+
+/// Prefix marking a sealed column value.
+const SEALED_PREFIX: &str = "enc:v1:";
+
+/// Columns holding secrets, sealed at rest as `(table, column)`.
+const SEALED_COLUMNS: &[(&str, &str)] = &[
+    ("sessions", "session_id"),
+    ("sessions", "cookie_jar"),
+    ("tasks", "webhook_secret"),
+    ("webhook_deliveries", "webhook_secret"),
+    ("api_keys", "raw_key"),
+];
+
+/// Process-wide keyring, set once at startup by [`init_keyring`].
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// Unwrapped data keys. `active` seals new values; all keys can open.
+pub struct Keyring {
+    active: Option<String>,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl Keyring {
+    fn empty() -> Self {
+        Self { active: None, keys: HashMap::new() }
+    }
+
+    /// Seal a value with the active key (plaintext passthrough when disabled).
+    pub fn seal(&self, plaintext: &str) -> Result<String> {
+        let Some(key_id) = &self.active else {
+            return Ok(plaintext.to_string());
+        };
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = self.keys[key_id]
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+        let mut blob = nonce.to_vec();
+        blob.extend_from_slice(&ciphertext);
+        Ok(format!("{SEALED_PREFIX}{key_id}:{}", B64.encode(blob)))
+    }
+
+    /// Open a sealed value; plaintext values are returned unchanged.
+    pub fn open(&self, value: &str) -> Result<String> {
+        let Some(rest) = value.strip_prefix(SEALED_PREFIX) else {
+            return Ok(value.to_string());
+        };
+        let (key_id, data) = rest.split_once(':').context("malformed sealed value")?;
+        let Some(cipher) = self.keys.get(key_id) else {
+            bail!("value sealed with unknown data key '{key_id}' (is MASTER_KEY set?)");
+        };
+        let blob = B64.decode(data)?;
+        if blob.len() < 12 {
+            bail!("malformed sealed value");
+        }
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&blob[..12]), &blob[12..])
+            .map_err(|_| anyhow::anyhow!("failed to decrypt sealed value"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+/// Parse a 32-byte master key given as base64 or hex.
+pub fn parse_master_key(text: &str) -> Result<[u8; 32]> {
+    let text = text.trim();
+    let bytes = if text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit()) {
+        hex::decode(text)?
+    } else {
+        B64.decode(text).context("master key must be base64 or hex")?
+    };
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("master key must be exactly 32 bytes"))
+}
+
+/// Read a master key from `{prefix}` (inline) or `{prefix}_FILE` (path).
+pub fn master_key_from_env(prefix: &str) -> Result<Option<[u8; 32]>> {
+    if let Some(key) = std::env::var(prefix).ok().filter(|s| !s.is_empty()) {
+        return parse_master_key(&key).map(Some);
+    }
+    if let Some(path) = std::env::var(format!("{prefix}_FILE")).ok().filter(|s| !s.is_empty()) {
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {prefix}_FILE {path}"))?;
+        return parse_master_key(&text).map(Some);
+    }
+    Ok(None)
+}
+
+#[derive(sqlx::FromRow)]
+struct DataKeyRow {
+    id: String,
+    wrapped_key: String,
+}
+
+/// Load (creating on first use) the data keys wrapped by `master`.
+async fn load_keyring(pool: &SqlitePool, master: &[u8; 32]) -> Result<Keyring> {
+    let wrapper = Aes256Gcm::new_from_slice(master)?;
+    let rows = sqlx::query_as::<_, DataKeyRow>(
+        "SELECT id, wrapped_key FROM encryption_keys ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut keyring = Keyring::empty();
+    for row in rows {
+        let blob = B64.decode(&row.wrapped_key)?;
+        if blob.len() < 12 {
+            bail!("malformed wrapped data key {}", row.id);
+        }
+        let dek = wrapper
+            .decrypt(Nonce::from_slice(&blob[..12]), &blob[12..])
+            .map_err(|_| anyhow::anyhow!("master key does not unwrap data key {}", row.id))?;
+        keyring.keys.insert(row.id.clone(), Aes256Gcm::new_from_slice(&dek)?);
+        keyring.active = Some(row.id);
+    }
+
+    if keyring.active.is_none() {
+        let (id, cipher) = create_data_key(pool, master).await?;
+        keyring.keys.insert(id.clone(), cipher);
+        keyring.active = Some(id);
+    }
+    Ok(keyring)
+}
+
+/// Generate a DEK, store it wrapped by `master`, and return it.
+async fn create_data_key<'e, E>(executor: E, master: &[u8; 32]) -> Result<(String, Aes256Gcm)>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+{
+    let dek: [u8; 32] = rand::random();
+    let nonce: [u8; 12] = rand::random();
+    let wrapped = Aes256Gcm::new_from_slice(master)?
+        .encrypt(Nonce::from_slice(&nonce), dek.as_ref())
+        .map_err(|_| anyhow::anyhow!("failed to wrap data key"))?;
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&wrapped);
+
+    let id = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+    sqlx::query("INSERT INTO encryption_keys (id, wrapped_key) VALUES (?, ?)")
+        .bind(&id)
+        .bind(B64.encode(blob))
+        .execute(executor)
+        .await?;
+    Ok((id, Aes256Gcm::new_from_slice(&dek)?))
+}
+
+/// Initialise the process keyring. Without a master key, values are stored
+/// in plaintext and any already-sealed value fails to open.
+pub async fn init_keyring(pool: &SqlitePool, master: Option<[u8; 32]>) -> Result<()> {
+    let keyring = match master {
+        Some(master) => load_keyring(pool, &master).await?,
+        None => {
+            tracing::warn!("MASTER_KEY not set: secrets are stored unencrypted");
+            Keyring::empty()
+        }
+    };
+    KEYRING
+        .set(keyring)
+        .map_err(|_| anyhow::anyhow!("keyring already initialised"))
+}
+
+fn keyring() -> &'static Keyring {
+    KEYRING.get_or_init(Keyring::empty)
+}
+
+/// Seal a secret for storage.
+pub fn seal(plaintext: &str) -> Result<String> {
+    keyring().seal(plaintext)
+}
+
+/// Seal an optional secret for storage.
+pub fn seal_opt(plaintext: Option<&str>) -> Result<Option<String>> {
+    plaintext.map(seal).transpose()
+}
+
+/// Open a stored secret.
+pub fn open(value: &str) -> Result<String> {
+    keyring().open(value)
+}
+
+/// Open an optional stored secret.
+pub fn open_opt(value: Option<&str>) -> Result<Option<String>> {
+    value.map(open).transpose()
+}
+
+#[derive(sqlx::FromRow)]
+struct SealedValueRow {
+    rowid: i64,
+    value: String,
+}
+
+/// Re-seal secret columns: open each value with `read`, seal it with `write`.
+/// With `only_plaintext`, values that are already sealed are left alone.
+async fn reseal_columns(
+    tx: &mut sqlx::SqliteConnection,
+    read: &Keyring,
+    write: &Keyring,
+    only_plaintext: bool,
+) -> Result<u64> {
+    let mut count = 0;
+    for (table, column) in SEALED_COLUMNS {
+        let filter = if only_plaintext {
+            format!(" AND {column} NOT LIKE '{SEALED_PREFIX}%'")
+        } else {
+            String::new()
+        };
+        let rows = sqlx::query_as::<_, SealedValueRow>(&format!(
+            "SELECT rowid, {column} AS value FROM {table} \
+             WHERE {column} IS NOT NULL AND {column} != ''{filter}"
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for row in rows {
+            let plaintext = read.open(&row.value)
+                .with_context(|| format!("{table}.{column} rowid {}", row.rowid))?;
+            sqlx::query(&format!("UPDATE {table} SET {column} = ? WHERE rowid = ?"))
+                .bind(write.seal(&plaintext)?)
+                .bind(row.rowid)
+                .execute(&mut *tx)
+                .await?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Seal any plaintext secrets left from before encryption was enabled.
+pub async fn seal_plaintext_rows(pool: &SqlitePool) -> Result<u64> {
+    let keyring = keyring();
+    if keyring.active.is_none() {
+        return Ok(0);
+    }
+    let mut tx = pool.begin().await?;
+    let count = reseal_columns(&mut tx, keyring, keyring, true).await?;
+    tx.commit().await?;
+    if count > 0 {
+        tracing::info!(count, "Encrypted plaintext secrets at rest");
+    }
+    Ok(count)
+}
+
+/// Rotate keys: create a fresh DEK (wrapped by `new_master`, or `master` when
+/// not rotating the master key), re-seal every secret with it, and drop the
+/// old DEKs. Runs in one transaction.
+pub async fn rotate_keys(
+    pool: &SqlitePool,
+    master: &[u8; 32],
+    new_master: Option<&[u8; 32]>,
+) -> Result<u64> {
+    let old = load_keyring(pool, master).await?;
+
+    let mut tx = pool.begin().await?;
+    let wrap_with = new_master.unwrap_or(master);
+    let (new_id, cipher) = create_data_key(&mut *tx, wrap_with).await?;
+    let mut new = Keyring::empty();
+    new.keys.insert(new_id.clone(), cipher);
+    new.active = Some(new_id.clone());
+
+    let count = reseal_columns(&mut tx, &old, &new, false).await?;
+    sqlx::query("DELETE FROM encryption_keys WHERE id != ?")
+        .bind(&new_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    tracing::info!(count, key_id = new_id, master_rotated = new_master.is_some(), "Key rotation complete");
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_keyring() -> Keyring {
+        let mut keyring = Keyring::empty();
+        keyring.keys.insert("k1".into(), Aes256Gcm::new_from_slice(&[7u8; 32]).unwrap());
+        keyring.active = Some("k1".into());
+        keyring
+    }
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let keyring = test_keyring();
+        let sealed = keyring.seal("sessionid=abc").unwrap();
+        assert!(sealed.starts_with("enc:v1:k1:"));
+        assert_eq!(keyring.open(&sealed).unwrap(), "sessionid=abc");
+        // Legacy plaintext passes through.
+        assert_eq!(keyring.open("plain").unwrap(), "plain");
+        // Sealed values need the key.
+        assert!(Keyring::empty().open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_parse_master_key() {
+        assert!(parse_master_key(&B64.encode([1u8; 32])).is_ok());
+        assert!(parse_master_key(&"ab".repeat(32)).is_ok());
+        assert!(parse_master_key("short").is_err());
+    }
+
+    #[test]
+    fn test_bundle_iterations_are_bounded() {
+        // Checked before any key is derived.
+        for iterations in [1, PBKDF2_ITERATIONS - 1, 10 * PBKDF2_ITERATIONS + 1, u32::MAX] {
+            let bundle = EncryptedBundle {
+                kdf: "pbkdf2-sha256".into(),
+                iterations,
+                salt: B64.encode([0u8; 16]),
+                nonce: B64.encode([0u8; 12]),
+                ciphertext: B64.encode([0u8; 32]),
+            };
+            let err = decrypt_with_passphrase(&bundle, "correct horse battery").unwrap_err();
+            assert!(err.to_string().contains("iterations"), "{err}");
+        }
+    }
+}
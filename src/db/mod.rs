@@ -67,6 +67,9 @@ impl Database {
 
             CREATE INDEX IF NOT EXISTS idx_tasks_status ON tasks(status);
             CREATE INDEX IF NOT EXISTS idx_tasks_created ON tasks(created_at DESC);
+            DROP INDEX IF EXISTS idx_tasks_history;
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_tasks_history_record ON tasks(history_record_id)
+                WHERE history_record_id IS NOT NULL;
 
             CREATE TABLE IF NOT EXISTS admin_users (
                 id TEXT PRIMARY KEY,
@@ -112,7 +115,8 @@ impl Database {
 
             CREATE TABLE IF NOT EXISTS webhook_deliveries (
                 id TEXT PRIMARY KEY,
-                task_id TEXT NOT NULL UNIQUE,
+                task_id TEXT UNIQUE,
+                event_key TEXT UNIQUE,
                 webhook_url TEXT NOT NULL,
                 webhook_secret TEXT,
                 status TEXT NOT NULL DEFAULT 'pending',
@@ -126,6 +130,60 @@ impl Database {
                 updated_at TEXT NOT NULL DEFAULT (datetime('now'))
             );
             CREATE INDEX IF NOT EXISTS idx_webhook_due ON webhook_deliveries(status, next_attempt_at);
+
+            CREATE TABLE IF NOT EXISTS encryption_keys (
+                id TEXT PRIMARY KEY,
+                wrapped_key TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE TABLE IF NOT EXISTS cookie_refreshes (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                trigger TEXT NOT NULL,
+                status TEXT NOT NULL,
+                cookie_count INTEGER,
+                error TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            CREATE INDEX IF NOT EXISTS idx_cookie_refreshes_session ON cookie_refreshes(session_id, created_at);
+
+            CREATE TABLE IF NOT EXISTS session_stats_hourly (
+                session_id TEXT NOT NULL,
+                hour TEXT NOT NULL,
+                tasks INTEGER NOT NULL DEFAULT 0,
+                successes INTEGER NOT NULL DEFAULT 0,
+                failures INTEGER NOT NULL DEFAULT 0,
+                failures_by_kind TEXT NOT NULL DEFAULT '{}',
+                queue_wait_secs INTEGER NOT NULL DEFAULT 0,
+                queue_wait_count INTEGER NOT NULL DEFAULT 0,
+                credits_spent INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (session_id, hour)
+            );
+
+            CREATE TABLE IF NOT EXISTS task_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                task_id TEXT NOT NULL,
+                event TEXT NOT NULL,
+                transport TEXT,
+                detail TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            CREATE INDEX IF NOT EXISTS idx_task_events_task ON task_events(task_id, id);
+
+            CREATE TABLE IF NOT EXISTS material_cache (
+                session_id TEXT NOT NULL,
+                sha256 TEXT NOT NULL,
+                material_type TEXT NOT NULL,
+                uri TEXT,
+                vid TEXT,
+                width INTEGER NOT NULL DEFAULT 0,
+                height INTEGER NOT NULL DEFAULT 0,
+                duration INTEGER NOT NULL DEFAULT 0,
+                fps INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                PRIMARY KEY (session_id, sha256)
+            );
             "#,
         )
         .execute(&self.pool)
@@ -140,19 +198,98 @@ impl Database {
             "ALTER TABLE tasks ADD COLUMN webhook_url TEXT",
             "ALTER TABLE tasks ADD COLUMN webhook_secret TEXT",
             "ALTER TABLE sessions ADD COLUMN cookie_jar TEXT",
+            "ALTER TABLE sessions ADD COLUMN tags TEXT NOT NULL DEFAULT '[]'",
+            "ALTER TABLE tasks ADD COLUMN queue_reason TEXT",
+            "ALTER TABLE tasks ADD COLUMN not_before TEXT",
+            "ALTER TABLE sessions ADD COLUMN fingerprint TEXT NOT NULL DEFAULT '{}'",
+            "ALTER TABLE sessions ADD COLUMN proxy_url TEXT",
+            "ALTER TABLE sessions ADD COLUMN expires_at TEXT",
+            "ALTER TABLE sessions ADD COLUMN cookies_refreshed_at TEXT",
+            "ALTER TABLE sessions ADD COLUMN cookie_refresh_attempted_at TEXT",
+            "ALTER TABLE sessions ADD COLUMN cookie_refresh_failures INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE tasks ADD COLUMN credits INTEGER",
+            "ALTER TABLE sessions ADD COLUMN submit_interval_secs INTEGER",
+            "ALTER TABLE sessions ADD COLUMN daily_submit_cap INTEGER",
+            "ALTER TABLE sessions ADD COLUMN next_submit_at TEXT",
+            "ALTER TABLE sessions ADD COLUMN daily_submits INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE sessions ADD COLUMN daily_submits_date TEXT",
+            "ALTER TABLE sessions ADD COLUMN quota_exhausted_until TEXT",
+            "ALTER TABLE sessions ADD COLUMN draining TEXT",
+            "ALTER TABLE sessions ADD COLUMN profile TEXT",
+            "ALTER TABLE tasks ADD COLUMN source TEXT NOT NULL DEFAULT 'gateway'",
+            "ALTER TABLE sessions ADD COLUMN transport_prefs TEXT NOT NULL DEFAULT '{}'",
+            "ALTER TABLE sessions ADD COLUMN client_profile TEXT",
+            "ALTER TABLE tasks ADD COLUMN best_effort INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE tasks ADD COLUMN material_files TEXT",
+            "ALTER TABLE tasks ADD COLUMN quota_requeues INTEGER NOT NULL DEFAULT 0",
         ];
         for sql in &alter_columns {
-            if let Err(err) = sqlx::query(sql).execute(&self.pool).await {
-                if !err.to_string().contains("duplicate column name") {
-                    return Err(err.into());
-                }
+            if let Err(err) = sqlx::query(sql).execute(&self.pool).await
+                && !err.to_string().contains("duplicate column name")
+            {
+                return Err(err.into());
             }
         }
 
+        self.migrate_webhook_deliveries().await?;
+
         tracing::info!("Database migrated successfully");
         Ok(())
     }
 
+    /// `webhook_deliveries.task_id` used to be NOT NULL and also held the
+    /// dedupe keys of gateway events. Rebuild such a table with a nullable
+    /// `task_id` and the keys moved to `event_key`.
+    async fn migrate_webhook_deliveries(&self) -> Result<()> {
+        let task_id_required: bool = sqlx::query_scalar(
+            "SELECT \"notnull\" FROM pragma_table_info('webhook_deliveries') WHERE name = 'task_id'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !task_id_required {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            CREATE TABLE webhook_deliveries_new (
+                id TEXT PRIMARY KEY,
+                task_id TEXT UNIQUE,
+                event_key TEXT UNIQUE,
+                webhook_url TEXT NOT NULL,
+                webhook_secret TEXT,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempt_count INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at TEXT NOT NULL DEFAULT (datetime('now')),
+                last_attempt_at TEXT,
+                last_status_code INTEGER,
+                last_error TEXT,
+                payload TEXT NOT NULL DEFAULT '{}',
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            INSERT INTO webhook_deliveries_new
+                (id, task_id, event_key, webhook_url, webhook_secret, status, attempt_count, next_attempt_at,
+                 last_attempt_at, last_status_code, last_error, payload, created_at, updated_at)
+            SELECT d.id,
+                   CASE WHEN t.id IS NOT NULL THEN d.task_id END,
+                   CASE WHEN t.id IS NULL THEN d.task_id END,
+                   d.webhook_url, d.webhook_secret, d.status, d.attempt_count, d.next_attempt_at,
+                   d.last_attempt_at, d.last_status_code, d.last_error, d.payload, d.created_at, d.updated_at
+            FROM webhook_deliveries d LEFT JOIN tasks t ON t.id = d.task_id;
+            DROP TABLE webhook_deliveries;
+            ALTER TABLE webhook_deliveries_new RENAME TO webhook_deliveries;
+            CREATE INDEX IF NOT EXISTS idx_webhook_due ON webhook_deliveries(status, next_attempt_at);
+            "#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        tracing::info!("Moved event webhook keys out of webhook_deliveries.task_id");
+        Ok(())
+    }
+
     pub async fn recover_on_startup(&self) -> Result<()> {
         // Reset all session active_tasks counters
         let reset = sqlx::query("UPDATE sessions SET active_tasks = 0")
@@ -184,3 +321,51 @@ impl Database {
         Ok(())
     }
 }
+
+#[cfg(test)]
+impl Database {
+    /// A migrated in-memory database shared by the pool's connections.
+    pub async fn memory() -> Self {
+        let db = Self::connect("sqlite::memory:").await.expect("in-memory database");
+        db.migrate().await.expect("migrations");
+        db
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_migrate_webhook_deliveries() {
+        let db = Database::memory().await;
+        sqlx::query(
+            "DROP TABLE webhook_deliveries; \
+             CREATE TABLE webhook_deliveries ( \
+                 id TEXT PRIMARY KEY, task_id TEXT NOT NULL UNIQUE, webhook_url TEXT NOT NULL, \
+                 webhook_secret TEXT, status TEXT NOT NULL DEFAULT 'pending', \
+                 attempt_count INTEGER NOT NULL DEFAULT 0, next_attempt_at TEXT NOT NULL DEFAULT (datetime('now')), \
+                 last_attempt_at TEXT, last_status_code INTEGER, last_error TEXT, \
+                 payload TEXT NOT NULL DEFAULT '{}', created_at TEXT NOT NULL DEFAULT (datetime('now')), \
+                 updated_at TEXT NOT NULL DEFAULT (datetime('now'))); \
+             INSERT INTO tasks (id, prompt) VALUES ('t1', 'p'); \
+             INSERT INTO webhook_deliveries (id, task_id, webhook_url) VALUES \
+                 ('d1', 't1', 'http://hook'), ('d2', 'session.expiring:s1:2026-10-20 00:00:00', 'http://hook');",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        db.migrate().await.unwrap();
+        db.migrate().await.unwrap();
+        let rows: Vec<(String, Option<String>, Option<String>)> =
+            sqlx::query_as("SELECT id, task_id, event_key FROM webhook_deliveries ORDER BY id")
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(rows, [
+            ("d1".into(), Some("t1".into()), None),
+            ("d2".into(), None, Some("session.expiring:s1:2026-10-20 00:00:00".into())),
+        ]);
+    }
+}
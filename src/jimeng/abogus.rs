@@ -22,13 +22,15 @@ const END_STRING: &str = "cus";
 const DEFAULT_BROWSER: &str =
     "1920|969|1920|1080|0|0|0|0|1920|1080|1920|1080|1920|969|24|24|Win32";
 
-/// Pre-computed ua_code: SM3(SM3("Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
-/// AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36" + "cus"))
-/// This matches the User-Agent used in auth.rs headers.
+/// ua_code: SM3(SM3(user_agent + "cus")). Must match the User-Agent header
+/// sent with the signed request.
+fn ua_code(user_agent: &str) -> [u8; 32] {
+    double_sm3(user_agent)
+}
+
+#[cfg(test)]
 fn default_ua_code() -> [u8; 32] {
-    let ua = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
-              (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36";
-    double_sm3(ua)
+    ua_code(super::auth::DEFAULT_USER_AGENT)
 }
 
 /// Compute SM3 hash, returning 32 bytes.
@@ -73,6 +75,7 @@ fn rc4(data: &[u8], key: &[u8]) -> Vec<u8> {
 }
 
 /// Custom Base64 encoding using the s4 alphabet.
+#[allow(clippy::manual_div_ceil, clippy::same_item_push)]
 fn custom_b64_encode(data: &[u8]) -> String {
     let mut result = Vec::with_capacity((data.len() + 2) / 3 * 4);
 
@@ -194,7 +197,9 @@ fn build_list_4(
 ///
 /// `url_params` should be the full query string (without leading `?`).
 /// `method` is the HTTP method, typically "POST" for jimeng generate requests.
-pub fn generate(url_params: &str, method: &str) -> String {
+/// `user_agent` must be the User-Agent the request is sent with (a session's
+/// persisted fingerprint).
+pub fn generate(url_params: &str, method: &str, user_agent: &str) -> String {
     let now_ms = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
@@ -204,7 +209,7 @@ pub fn generate(url_params: &str, method: &str) -> String {
 
     let params_hash = double_sm3(url_params);
     let method_hash = double_sm3(method);
-    let ua_code = default_ua_code();
+    let ua_code = ua_code(user_agent);
 
     let browser = DEFAULT_BROWSER;
     let browser_len = browser.len() as u8;
@@ -243,6 +248,7 @@ pub fn generate(url_params: &str, method: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::jimeng::auth::DEFAULT_USER_AGENT;
 
     #[test]
     fn test_sm3_basic() {
@@ -341,7 +347,7 @@ mod tests {
     #[test]
     fn test_generate_produces_valid_output() {
         let params = "aid=513695&device_platform=web&region=cn";
-        let result = generate(params, "POST");
+        let result = generate(params, "POST", DEFAULT_USER_AGENT);
         // Should be non-empty URL-encoded string
         assert!(!result.is_empty());
         // Typical a_bogus length is 100-200 chars when URL-encoded
@@ -354,8 +360,8 @@ mod tests {
     fn test_generate_deterministic_structure() {
         // Two calls should produce different values (random nonce)
         let params = "aid=513695&test=1";
-        let r1 = generate(params, "POST");
-        let r2 = generate(params, "POST");
+        let r1 = generate(params, "POST", DEFAULT_USER_AGENT);
+        let r2 = generate(params, "POST", DEFAULT_USER_AGENT);
         assert_ne!(r1, r2, "Should be different due to random nonce");
     }
 
@@ -373,7 +379,7 @@ mod tests {
         let params = "aid=513695&device_platform=web&region=cn&webId=7000000000000000000\
                       &da_version=3.3.2&web_component_open_flag=1&web_version=7.5.0\
                       &aigc_features=app_lip_sync";
-        let result = generate(params, "POST");
+        let result = generate(params, "POST", DEFAULT_USER_AGENT);
 
         // URL-decode to check raw structure
         let decoded = urlencoding::decode(&result).unwrap();
@@ -411,14 +417,16 @@ mod tests {
     async fn test_live_cookie() {
         let token = std::env::var("TEST_SESSION_TOKEN")
             .unwrap_or_else(|_| "b68b270daf398ba25669932adab54784".to_string());
+        let fp = super::super::auth::Fingerprint::generate();
+        let profile = super::super::client_profile::ClientProfile::default();
         let client = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(15))
             .build()
             .unwrap();
 
         let uri = "/mweb/v1/get_history_by_ids";
-        let headers = super::super::auth::build_headers(&token, uri);
-        let params = super::super::auth::standard_query_params();
+        let headers = super::super::auth::build_headers(&token, uri, &fp, &profile);
+        let params = super::super::auth::standard_query_params_with_jar(None, &fp, &profile);
         let body = serde_json::json!({ "history_ids": ["fake_test_id"] });
 
         let resp = client
@@ -444,6 +452,8 @@ mod tests {
     async fn test_live_no_abogus() {
         let token = std::env::var("TEST_SESSION_TOKEN")
             .unwrap_or_else(|_| "b68b270daf398ba25669932adab54784".to_string());
+        let fp = super::super::auth::Fingerprint::generate();
+        let profile = super::super::client_profile::ClientProfile::default();
         let client = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(15))
             .build()
@@ -453,7 +463,7 @@ mod tests {
             "aid={}&device_platform=web&region=cn&webId={}&da_version=3.3.2\
              &web_component_open_flag=1&web_version=7.5.0&aigc_features=app_lip_sync",
             super::super::auth::DEFAULT_ASSISTANT_ID,
-            super::super::auth::standard_query_params()
+            super::super::auth::standard_query_params_with_jar(None, &fp, &profile)
                 .iter().find(|(k, _)| *k == "webId").map(|(_, v)| v.clone()).unwrap_or_default()
         );
 
@@ -462,7 +472,7 @@ mod tests {
             "https://jimeng.jianying.com/mweb/v1/aigc_draft/generate?{}",
             query_params
         );
-        let headers = super::super::auth::build_headers(&token, "/mweb/v1/aigc_draft/generate");
+        let headers = super::super::auth::build_headers(&token, "/mweb/v1/aigc_draft/generate", &fp, &profile);
         let body = serde_json::json!({
             "submit_id": uuid::Uuid::new_v4().to_string(),
             "draft_content": "{}",
@@ -476,8 +486,9 @@ mod tests {
         println!("[no a_bogus, empty body] Response: {}", &text[..text.len().min(500)]);
 
         // Now try with a real submit body, still no a_bogus
-        let internal_model = super::super::models::resolve_model("seedance-2.0-fast");
-        let draft_version = super::super::models::draft_version("seedance-2.0-fast");
+        let model = super::super::model_registry::ModelRegistry::load(None).unwrap().get("seedance-2.0-fast").unwrap();
+        let internal_model = model.internal_key.as_str();
+        let draft_version = profile.draft_version("seedance-2.0-fast");
         let sid = uuid::Uuid::new_v4().to_string();
         let cid = uuid::Uuid::new_v4().to_string();
 
@@ -511,7 +522,7 @@ mod tests {
             "http_common_info":{"aid":super::super::auth::DEFAULT_ASSISTANT_ID}
         });
 
-        let headers2 = super::super::auth::build_headers(&token, "/mweb/v1/aigc_draft/generate");
+        let headers2 = super::super::auth::build_headers(&token, "/mweb/v1/aigc_draft/generate", &fp, &profile);
         let resp2 = client.post(&url).headers(headers2).json(&full_body).send().await.expect("Network error");
         let status2 = resp2.status();
         let text2 = resp2.text().await.unwrap_or_default();
@@ -526,6 +537,8 @@ mod tests {
     async fn test_live_abogus() {
         let token = std::env::var("TEST_SESSION_TOKEN")
             .unwrap_or_else(|_| "b68b270daf398ba25669932adab54784".to_string());
+        let fp = super::super::auth::Fingerprint::generate();
+        let profile = super::super::client_profile::ClientProfile::default();
         let client = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(15))
             .build()
@@ -535,14 +548,14 @@ mod tests {
             "aid={}&device_platform=web&region=cn&webId={}&da_version=3.3.2\
              &web_component_open_flag=1&web_version=7.5.0&aigc_features=app_lip_sync",
             super::super::auth::DEFAULT_ASSISTANT_ID,
-            super::super::auth::standard_query_params()
+            super::super::auth::standard_query_params_with_jar(None, &fp, &profile)
                 .iter()
                 .find(|(k, _)| *k == "webId")
                 .map(|(_, v)| v.clone())
                 .unwrap_or_default()
         );
 
-        let a_bogus = generate(&query_params, "POST");
+        let a_bogus = generate(&query_params, "POST", DEFAULT_USER_AGENT);
         println!("[a_bogus] Generated ({} chars): {}...", a_bogus.len(), &a_bogus[..a_bogus.len().min(60)]);
 
         let url = format!(
@@ -551,7 +564,7 @@ mod tests {
         );
 
         let uri = "/mweb/v1/aigc_draft/generate";
-        let headers = super::super::auth::build_headers(&token, uri);
+        let headers = super::super::auth::build_headers(&token, uri, &fp, &profile);
 
         // Minimal body — we expect a business error but NOT an anti-bot rejection
         let body = serde_json::json!({
@@ -600,6 +613,8 @@ mod tests {
     async fn test_live_video_generation() {
         let token = std::env::var("TEST_SESSION_TOKEN")
             .unwrap_or_else(|_| "b68b270daf398ba25669932adab54784".to_string());
+        let fp = super::super::auth::Fingerprint::generate();
+        let profile = super::super::client_profile::ClientProfile::default();
         let client = reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(30))
             .gzip(true)
@@ -612,9 +627,10 @@ mod tests {
         let height = 1280u32;
         let duration = 4u32;
 
-        let internal_model = super::super::models::resolve_model(model_name);
-        let benefit_type = super::super::models::seedance_benefit_type(model_name);
-        let draft_version = super::super::models::draft_version(model_name);
+        let model = super::super::model_registry::ModelRegistry::load(None).unwrap().get(model_name).unwrap();
+        let internal_model = model.internal_key.as_str();
+        let benefit_type = model.benefit_type(false);
+        let draft_version = profile.draft_version(model_name);
         let aspect_ratio = super::super::models::aspect_ratio_str(width, height);
 
         let component_id = uuid::Uuid::new_v4().to_string();
@@ -722,19 +738,19 @@ mod tests {
             "aid={}&device_platform=web&region=cn&webId={}&da_version={}\
              &web_component_open_flag=1&web_version=7.5.0&aigc_features=app_lip_sync",
             super::super::auth::DEFAULT_ASSISTANT_ID,
-            super::super::auth::standard_query_params()
+            super::super::auth::standard_query_params_with_jar(None, &fp, &profile)
                 .iter().find(|(k,_)| *k == "webId").map(|(_, v)| v.clone()).unwrap_or_default(),
             draft_version,
         );
 
-        let a_bogus = generate(&query_string, "POST");
+        let a_bogus = generate(&query_string, "POST", DEFAULT_USER_AGENT);
         println!("[submit] a_bogus generated ({} chars)", a_bogus.len());
 
         let url = format!(
             "https://jimeng.jianying.com/mweb/v1/aigc_draft/generate?{}&a_bogus={}",
             query_string, a_bogus
         );
-        let headers = super::super::auth::build_headers(&token, "/mweb/v1/aigc_draft/generate");
+        let headers = super::super::auth::build_headers(&token, "/mweb/v1/aigc_draft/generate", &fp, &profile);
 
         let resp = client.post(&url).headers(headers).json(&body).send().await
             .expect("Submit request failed");
@@ -776,6 +792,9 @@ mod tests {
         println!("[submit] ✅ Task submitted! history_record_id={history_id}");
 
         // Poll for result (up to 5 minutes)
+        let mut jimeng = super::super::client::JimengClient::new(
+            client.clone(), Default::default(), token.clone(), None, fp.clone(), None,
+        );
         let poll_deadline = std::time::Instant::now() + std::time::Duration::from_secs(300);
         loop {
             if std::time::Instant::now() > poll_deadline {
@@ -784,7 +803,7 @@ mod tests {
             }
             tokio::time::sleep(std::time::Duration::from_secs(10)).await;
 
-            match super::super::poll::poll_status(&client, &token, &history_id, None).await {
+            match jimeng.poll(&history_id).await {
                 Ok(result) => {
                     println!(
                         "[poll] status={} queue={:?}/{:?} eta={:?}",
@@ -809,6 +828,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::manual_div_ceil)]
     fn test_full_pipeline_structure() {
         // Verify the internal pipeline produces correct byte layout
         let params = "aid=513695&test=1";
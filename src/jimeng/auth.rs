@@ -1,23 +1,78 @@
 //! Cookie generation and request signing for jimeng.jianying.com API.
 
-use md5::{Md5, Digest};
 use reqwest::header::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
 
+use super::client_profile::ClientProfile;
+use super::cookies::CookieJar;
+
+/// Host every jimeng API request goes to (cookie matching).
+pub const JIMENG_HOST: &str = "jimeng.jianying.com";
 /// Default assistant ID used in all API requests.
 pub const DEFAULT_ASSISTANT_ID: u64 = 513695;
-/// API version code.
-const VERSION_CODE: &str = "8.4.0";
-/// Platform code (web).
-const PLATFORM_CODE: &str = "7";
-
-lazy_static::lazy_static! {
-    static ref DEVICE_ID: u64 = rand::random::<u64>() % 999999999999999999 + 7000000000000000000;
-    static ref WEB_ID: u64 = rand::random::<u64>() % 999999999999999999 + 7000000000000000000;
-    static ref USER_ID: String = uuid::Uuid::new_v4().to_string().replace("-", "");
+/// Browser user agent used for new fingerprints (matches abogus' default ua_code).
+pub const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36";
+/// Value of the `os` query parameter for new fingerprints.
+const DEFAULT_PLATFORM: &str = "mac";
+
+/// Per-session device fingerprint.
+///
+/// Generated once when a session is created and persisted with it, so each
+/// account presents a stable, distinct identity across gateway restarts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fingerprint {
+    /// `_tea_web_id` cookie and `webId` query parameter.
+    pub web_id: String,
+    /// `device_id` query parameter.
+    pub device_id: String,
+    /// `uid_tt` cookie value.
+    pub uid: String,
+    pub user_agent: String,
+    /// Value of the `os` query parameter.
+    pub platform: String,
+}
+
+impl Fingerprint {
+    /// Generate a fresh random fingerprint.
+    pub fn generate() -> Self {
+        Self {
+            web_id: random_device_number().to_string(),
+            device_id: random_device_number().to_string(),
+            uid: uuid::Uuid::new_v4().to_string().replace('-', ""),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            platform: DEFAULT_PLATFORM.to_string(),
+        }
+    }
+
+    /// Generate a fingerprint that agrees with an existing cookie jar, so
+    /// `webId` and `uid_tt` match what the browser was issued.
+    pub fn for_cookie_jar(cookie_jar: Option<&CookieJar>) -> Self {
+        let mut fp = Self::generate();
+        if let Some(jar) = cookie_jar {
+            if let Some(web_id) = jar.get("_tea_web_id") {
+                fp.web_id = web_id.to_string();
+            }
+            if let Some(uid) = jar.get("uid_tt") {
+                fp.uid = uid.to_string();
+            }
+        }
+        fp
+    }
+
+    /// True for rows created before fingerprints were persisted.
+    pub fn is_empty(&self) -> bool {
+        self.web_id.is_empty()
+    }
+}
+
+fn random_device_number() -> u64 {
+    rand::random::<u64>() % 999999999999999999 + 7000000000000000000
 }
 
 /// Generate the Cookie header value for a given session token.
-pub fn generate_cookie(session_token: &str) -> String {
+pub fn generate_cookie(session_token: &str, fingerprint: &Fingerprint) -> String {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
@@ -27,73 +82,61 @@ pub fn generate_cookie(session_token: &str) -> String {
         "_tea_web_id={web_id}; is_staff_user=false; store-region=cn-gd; store-region-src=uid; \
          sid_guard={token}%7C{ts}%7C5184000%7CMon%2C+03-Feb-2025+08%3A17%3A09+GMT; \
          uid_tt={uid}; uid_tt_ss={uid}; sid_tt={token}; sessionid={token}; sessionid_ss={token}",
-        web_id = *WEB_ID,
+        web_id = fingerprint.web_id,
         token = session_token,
         ts = now,
-        uid = *USER_ID,
+        uid = fingerprint.uid,
     )
 }
 
-/// Compute the Sign header: md5("9e2c|{uri_last7}|{platform}|{version}|{timestamp}||11ac")
-pub fn compute_sign(uri: &str, timestamp: u64) -> String {
-    let uri_suffix = if uri.len() >= 7 {
-        &uri[uri.len() - 7..]
-    } else {
-        uri
-    };
-
-    let input = format!(
-        "9e2c|{uri_suffix}|{platform}|{version}|{timestamp}||11ac",
-        uri_suffix = uri_suffix,
-        platform = PLATFORM_CODE,
-        version = VERSION_CODE,
-        timestamp = timestamp,
-    );
-
-    let mut hasher = Md5::new();
-    hasher.update(input.as_bytes());
-    format!("{:x}", hasher.finalize())
-}
-
 /// Generate the Cookie header value, using a full cookie jar if provided.
 ///
-/// If `cookie_jar` is `Some` and non-empty, it is used verbatim (it already
-/// contains `sessionid` and all other cookies). Otherwise falls back to the
-/// minimal fake cookies built from `session_token`.
-pub fn generate_cookie_with_jar(session_token: &str, cookie_jar: Option<&str>) -> String {
+/// If `cookie_jar` is `Some` and non-empty, the cookies it holds for `uri` on
+/// jimeng are sent (it already contains `sessionid` and all other cookies).
+/// Otherwise falls back to the minimal fake cookies built from `session_token`.
+pub fn generate_cookie_with_jar(
+    session_token: &str,
+    uri: &str,
+    cookie_jar: Option<&CookieJar>,
+    fingerprint: &Fingerprint,
+) -> String {
     match cookie_jar {
-        Some(jar) if !jar.is_empty() => jar.to_string(),
-        _ => generate_cookie(session_token),
+        Some(jar) if !jar.is_empty() => jar.header_for(JIMENG_HOST, uri),
+        _ => generate_cookie(session_token, fingerprint),
     }
 }
 
 /// Build headers using the full cookie jar (if available) and an extra `tdid` header.
-pub fn build_headers_with_cookies(session_token: &str, uri: &str, cookie_jar: Option<&str>) -> HeaderMap {
+pub fn build_headers_with_cookies(
+    session_token: &str,
+    uri: &str,
+    cookie_jar: Option<&CookieJar>,
+    fingerprint: &Fingerprint,
+    profile: &ClientProfile,
+) -> HeaderMap {
     let timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
         .as_secs();
 
-    let sign = compute_sign(uri, timestamp);
-    let cookie = generate_cookie_with_jar(session_token, cookie_jar);
+    let sign = profile.sign(uri, timestamp);
+    let cookie = generate_cookie_with_jar(session_token, uri, cookie_jar, fingerprint);
 
     let mut headers = HeaderMap::new();
     headers.insert("Accept", HeaderValue::from_static("application/json, text/plain, */*"));
     headers.insert("Accept-Language", HeaderValue::from_static("zh-CN,zh;q=0.9"));
-    headers.insert("App-Sdk-Version", HeaderValue::from_static("48.0.0"));
+    headers.insert("App-Sdk-Version", HeaderValue::from_str(&profile.sdk_version).unwrap());
     headers.insert("Appid", HeaderValue::from_str(&DEFAULT_ASSISTANT_ID.to_string()).unwrap());
-    headers.insert("Appvr", HeaderValue::from_static(VERSION_CODE));
+    headers.insert("Appvr", HeaderValue::from_str(&profile.version_code).unwrap());
     headers.insert("Lan", HeaderValue::from_static("zh-Hans"));
     headers.insert("Loc", HeaderValue::from_static("cn"));
     headers.insert("Origin", HeaderValue::from_static("https://jimeng.jianying.com"));
     headers.insert("Referer", HeaderValue::from_static("https://jimeng.jianying.com"));
-    headers.insert("Pf", HeaderValue::from_static(PLATFORM_CODE));
+    headers.insert("Pf", HeaderValue::from_str(&profile.platform_code).unwrap());
     // When using a cookie jar, omit UA and encoding headers to avoid
     // fingerprint mismatch that triggers ByteDance risk control (4013).
-    if cookie_jar.is_none() || cookie_jar.map_or(true, |s| s.is_empty()) {
-        headers.insert("User-Agent", HeaderValue::from_static(
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36"
-        ));
+    if cookie_jar.is_none_or(CookieJar::is_empty) {
+        headers.insert("User-Agent", user_agent_header(fingerprint));
         headers.insert("Accept-Encoding", HeaderValue::from_static("gzip, deflate, br, zstd"));
         headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
         headers.insert("Pragma", HeaderValue::from_static("no-cache"));
@@ -108,32 +151,31 @@ pub fn build_headers_with_cookies(session_token: &str, uri: &str, cookie_jar: Op
 }
 
 /// Build the full set of fake browser headers for a jimeng API request.
-pub fn build_headers(session_token: &str, uri: &str) -> HeaderMap {
+#[cfg(test)]
+pub fn build_headers(session_token: &str, uri: &str, fingerprint: &Fingerprint, profile: &ClientProfile) -> HeaderMap {
     let timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
         .as_secs();
 
-    let sign = compute_sign(uri, timestamp);
-    let cookie = generate_cookie(session_token);
+    let sign = profile.sign(uri, timestamp);
+    let cookie = generate_cookie(session_token, fingerprint);
 
     let mut headers = HeaderMap::new();
     headers.insert("Accept", HeaderValue::from_static("application/json, text/plain, */*"));
     headers.insert("Accept-Encoding", HeaderValue::from_static("gzip, deflate, br, zstd"));
     headers.insert("Accept-Language", HeaderValue::from_static("zh-CN,zh;q=0.9"));
-    headers.insert("App-Sdk-Version", HeaderValue::from_static("48.0.0"));
+    headers.insert("App-Sdk-Version", HeaderValue::from_str(&profile.sdk_version).unwrap());
     headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
     headers.insert("Appid", HeaderValue::from_str(&DEFAULT_ASSISTANT_ID.to_string()).unwrap());
-    headers.insert("Appvr", HeaderValue::from_static(VERSION_CODE));
+    headers.insert("Appvr", HeaderValue::from_str(&profile.version_code).unwrap());
     headers.insert("Lan", HeaderValue::from_static("zh-Hans"));
     headers.insert("Loc", HeaderValue::from_static("cn"));
     headers.insert("Origin", HeaderValue::from_static("https://jimeng.jianying.com"));
     headers.insert("Pragma", HeaderValue::from_static("no-cache"));
     headers.insert("Referer", HeaderValue::from_static("https://jimeng.jianying.com"));
-    headers.insert("Pf", HeaderValue::from_static(PLATFORM_CODE));
-    headers.insert("User-Agent", HeaderValue::from_static(
-        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36"
-    ));
+    headers.insert("Pf", HeaderValue::from_str(&profile.platform_code).unwrap());
+    headers.insert("User-Agent", user_agent_header(fingerprint));
     headers.insert("Cookie", HeaderValue::from_str(&cookie).unwrap());
     headers.insert("Device-Time", HeaderValue::from_str(&timestamp.to_string()).unwrap());
     headers.insert("Sign", HeaderValue::from_str(&sign).unwrap());
@@ -142,56 +184,117 @@ pub fn build_headers(session_token: &str, uri: &str) -> HeaderMap {
     headers
 }
 
+fn user_agent_header(fingerprint: &Fingerprint) -> HeaderValue {
+    HeaderValue::from_str(&fingerprint.user_agent)
+        .unwrap_or_else(|_| HeaderValue::from_static(DEFAULT_USER_AGENT))
+}
+
 /// Standard query parameters appended to all jimeng API requests.
 /// When `cookie_jar` is provided, extracts `_tea_web_id` from it to ensure
-/// the `webId` query param matches the cookie fingerprint.
-pub fn standard_query_params_with_jar(cookie_jar: Option<&str>) -> Vec<(&'static str, String)> {
+/// the `webId` query param matches the cookie fingerprint; `device_id` always
+/// comes from the session's fingerprint.
+pub fn standard_query_params_with_jar(
+    cookie_jar: Option<&CookieJar>,
+    fingerprint: &Fingerprint,
+    profile: &ClientProfile,
+) -> Vec<(&'static str, String)> {
     let web_id = cookie_jar
-        .and_then(|jar| extract_cookie_value(jar, "_tea_web_id"))
-        .unwrap_or_else(|| WEB_ID.to_string());
+        .and_then(|jar| jar.get("_tea_web_id"))
+        .map_or_else(|| fingerprint.web_id.clone(), str::to_string);
 
     vec![
         ("aid", DEFAULT_ASSISTANT_ID.to_string()),
         ("device_platform", "web".to_string()),
         ("region", "cn".to_string()),
         ("webId", web_id),
-        ("da_version", "3.3.14".to_string()),
-        ("os", "mac".to_string()),
+        ("device_id", fingerprint.device_id.clone()),
+        ("da_version", profile.da_version.clone()),
+        ("os", fingerprint.platform.clone()),
         ("web_component_open_flag", "1".to_string()),
         ("commerce_with_input_video", "1".to_string()),
-        ("web_version", "7.5.0".to_string()),
+        ("web_version", profile.web_version.clone()),
         ("aigc_features", "app_lip_sync".to_string()),
     ]
 }
 
-/// Standard query parameters (legacy, uses random webId).
-pub fn standard_query_params() -> Vec<(&'static str, String)> {
-    standard_query_params_with_jar(None)
-}
-
-/// Extract a cookie value by name from a cookie jar string.
-fn extract_cookie_value(jar: &str, name: &str) -> Option<String> {
-    let prefix = format!("{name}=");
-    for part in jar.split("; ") {
-        if let Some(value) = part.strip_prefix(&prefix) {
-            return Some(value.to_string());
-        }
-    }
-    None
-}
-
 /// Get cookies as (name, value, domain) tuples for browser context injection.
-pub fn get_cookies_for_browser(session_token: &str) -> Vec<(&'static str, String, &'static str)> {
+pub fn get_cookies_for_browser(
+    session_token: &str,
+    fingerprint: &Fingerprint,
+) -> Vec<(&'static str, String, &'static str)> {
     let domain = ".jianying.com";
     vec![
-        ("_tea_web_id", WEB_ID.to_string(), domain),
+        ("_tea_web_id", fingerprint.web_id.clone(), domain),
         ("is_staff_user", "false".to_string(), domain),
         ("store-region", "cn-gd".to_string(), domain),
         ("store-region-src", "uid".to_string(), domain),
-        ("uid_tt", USER_ID.clone(), domain),
-        ("uid_tt_ss", USER_ID.clone(), domain),
+        ("uid_tt", fingerprint.uid.clone(), domain),
+        ("uid_tt_ss", fingerprint.uid.clone(), domain),
         ("sid_tt", session_token.to_string(), domain),
         ("sessionid", session_token.to_string(), domain),
         ("sessionid_ss", session_token.to_string(), domain),
     ]
 }
+
+/// Compute when a session expires from the `sid_guard` cookie in its jar.
+///
+/// `sid_guard` is `{sessionid}|{issued_at}|{max_age}|{expires}` (URL-encoded
+/// `|` as `%7C`); expiry is `issued_at + max_age`.
+pub fn session_expiry_from_jar(cookie_jar: &CookieJar) -> Option<chrono::DateTime<chrono::Utc>> {
+    let guard = cookie_jar.get("sid_guard")?;
+    let decoded = guard.replace("%7C", "|").replace("%7c", "|");
+    let mut parts = decoded.split('|');
+    let issued_at: i64 = parts.nth(1)?.trim().parse().ok()?;
+    let max_age: i64 = parts.next()?.trim().parse().ok()?;
+    chrono::DateTime::from_timestamp(issued_at.checked_add(max_age)?, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_expiry_from_jar() {
+        let jar = CookieJar::from_header(
+            "ttwid=1; sid_guard=abc%7C1700000000%7C5184000%7CSat%2C+18-Jan-2025+22%3A13%3A20+GMT; sessionid=abc",
+        );
+        let expires = session_expiry_from_jar(&jar).unwrap();
+        assert_eq!(expires.timestamp(), 1700000000 + 5184000);
+        assert!(session_expiry_from_jar(&CookieJar::from_header("sessionid=abc")).is_none());
+        assert!(session_expiry_from_jar(&CookieJar::from_header("sid_guard=garbage")).is_none());
+    }
+
+    #[test]
+    fn test_fingerprint_is_used() {
+        let fp = Fingerprint::generate();
+        assert_ne!(fp.web_id, fp.device_id);
+        let profile = ClientProfile::default();
+        let param = |params: &[(&str, String)], name: &str| {
+            params.iter().find(|(k, _)| *k == name).map(|(_, v)| v.clone())
+        };
+
+        let params = standard_query_params_with_jar(None, &fp, &profile);
+        assert_eq!(param(&params, "webId"), Some(fp.web_id.clone()));
+        assert_eq!(param(&params, "device_id"), Some(fp.device_id.clone()));
+        assert_eq!(param(&params, "os"), Some(fp.platform.clone()));
+
+        // A jar's web id wins for webId; the device id stays the session's.
+        let jar = CookieJar::from_header("_tea_web_id=7123; sessionid=abc");
+        let params = standard_query_params_with_jar(Some(&jar), &fp, &profile);
+        assert_eq!(param(&params, "webId").as_deref(), Some("7123"));
+        assert_eq!(param(&params, "device_id"), Some(fp.device_id.clone()));
+
+        let cookie = generate_cookie("tok", &fp);
+        assert!(cookie.starts_with(&format!("_tea_web_id={};", fp.web_id)));
+        assert!(cookie.contains(&format!("uid_tt={};", fp.uid)));
+        let browser = get_cookies_for_browser("tok", &fp);
+        assert!(browser.contains(&("_tea_web_id", fp.web_id.clone(), ".jianying.com")));
+        assert!(browser.contains(&("uid_tt", fp.uid.clone(), ".jianying.com")));
+
+        // Fingerprints follow the jar they are generated for.
+        let jar = CookieJar::from_header("_tea_web_id=7123; uid_tt=u1");
+        let fp = Fingerprint::for_cookie_jar(Some(&jar));
+        assert_eq!((fp.web_id.as_str(), fp.uid.as_str()), ("7123", "u1"));
+        assert!(!fp.device_id.is_empty());
+    }
+}
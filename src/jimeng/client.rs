@@ -0,0 +1,457 @@
+//! Session-bound client for the jimeng API.
+//!
+//! A `JimengClient` carries one session's credentials (token, cookie jar,
+//! fingerprint, proxy), the upstream base URLs and the transport policy.
+//! Each call walks the endpoint's fallback chain (see `transport`), starting
+//! with the transport that last worked for the session. Cookies set by
+//! responses are kept in the client's jar.
+//!
+//! Endpoint methods live next to their request building and parsing:
+//! `submit`, `poll`, `upload` and `profile`.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Method};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::auth::{self, Fingerprint};
+use super::browser::{self, BrowserService};
+use super::client_profile::ClientProfile;
+use super::cookies::CookieJar;
+use super::curl_transport::{self, CurlError, CurlRequest};
+use super::error::JimengError;
+use super::responses;
+use super::transport::{
+    self, BrowserFetch, Curl, Endpoint, Request, SignedReqwest, Transport, TransportAttempt, TransportKind,
+    TransportPolicy,
+};
+
+const CREDIT_URI: &str = "/commerce/v1/benefits/user_credit";
+
+/// Upstream base URLs (scheme and host, no trailing slash). Overridable so
+/// the gateway can be pointed at a staging or mock server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseUrls {
+    pub jimeng: String,
+    /// ImageX, for image uploads.
+    pub imagex: String,
+    /// VOD, for video and audio uploads.
+    pub vod: String,
+}
+
+impl Default for BaseUrls {
+    fn default() -> Self {
+        Self {
+            jimeng: "https://jimeng.jianying.com".into(),
+            imagex: "https://imagex.bytedanceapi.com".into(),
+            vod: "https://vod.bytedanceapi.com".into(),
+        }
+    }
+}
+
+/// Credit balance of an account.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Credit {
+    pub gift: i64,
+    pub purchase: i64,
+    pub vip: i64,
+    pub total: i64,
+}
+
+pub struct JimengClient {
+    pub(super) http: Client,
+    pub(super) base: BaseUrls,
+    pub(super) session_token: String,
+    pub(super) cookie_jar: Option<CookieJar>,
+    pub(super) fingerprint: Fingerprint,
+    pub(super) proxy_url: Option<String>,
+    pub(super) profile: Arc<ClientProfile>,
+    policy: TransportPolicy,
+    browser: Option<Arc<BrowserService>>,
+    preferred: BTreeMap<Endpoint, TransportKind>,
+    attempts: Vec<TransportAttempt>,
+    /// Transport that got the latest response.
+    last_transport: Option<TransportKind>,
+}
+
+impl JimengClient {
+    /// `http` should already route through `proxy_url`; the proxy is also
+    /// handed to curl and the browser. Uses the built-in client profile, the
+    /// default transport policy and no browser until configured otherwise.
+    pub fn new(
+        http: Client,
+        base: BaseUrls,
+        session_token: impl Into<String>,
+        cookie_jar: Option<CookieJar>,
+        fingerprint: Fingerprint,
+        proxy_url: Option<String>,
+    ) -> Self {
+        Self {
+            http,
+            base,
+            session_token: session_token.into(),
+            cookie_jar,
+            fingerprint,
+            proxy_url,
+            profile: Arc::default(),
+            policy: TransportPolicy::default(),
+            browser: None,
+            preferred: BTreeMap::new(),
+            attempts: Vec::new(),
+            last_transport: None,
+        }
+    }
+
+    /// Use `policy`, starting each chain with the transport in `preferred`
+    /// (what last worked for the session) where it applies.
+    pub fn with_transports(mut self, policy: TransportPolicy, preferred: BTreeMap<Endpoint, TransportKind>) -> Self {
+        self.policy = policy;
+        self.preferred = preferred;
+        self
+    }
+
+    /// Imitate the web client described by `profile`. Its user agent, if
+    /// set, replaces the fingerprint's for every transport.
+    pub fn with_profile(mut self, profile: Arc<ClientProfile>) -> Self {
+        if let Some(ua) = &profile.user_agent {
+            self.fingerprint.user_agent = ua.clone();
+        }
+        self.profile = profile;
+        self
+    }
+
+    /// Allow the browser transport.
+    pub fn with_browser(mut self, browser: Arc<BrowserService>) -> Self {
+        self.browser = Some(browser);
+        self
+    }
+
+    /// Transport that last worked per endpoint, including what this client
+    /// learned.
+    pub fn preferred_transports(&self) -> &BTreeMap<Endpoint, TransportKind> {
+        &self.preferred
+    }
+
+    /// Transport tries made since the last call, oldest first.
+    pub fn take_attempts(&mut self) -> Vec<TransportAttempt> {
+        std::mem::take(&mut self.attempts)
+    }
+
+    /// The session's cookies, including any jimeng set on this client's
+    /// responses.
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.cookie_jar.as_ref()
+    }
+
+    /// Standard query parameters for this session.
+    pub(super) fn query_params(&self) -> Vec<(&'static str, String)> {
+        auth::standard_query_params_with_jar(self.cookie_jar.as_ref(), &self.fingerprint, &self.profile)
+    }
+
+    /// POST JSON `body` to `uri` on the jimeng API, with the standard query
+    /// parameters plus `extra_params`, trying the endpoint's transports in
+    /// order until one gets a non-error status (submits stop early, see
+    /// `transport::may_retry`). Curl is skipped for sessions without a full
+    /// cookie jar, the browser when none is attached or it cannot use the
+    /// session's proxy. Returns the status and body of the last try.
+    pub async fn post_json(
+        &mut self,
+        endpoint: Endpoint,
+        uri: &str,
+        extra_params: &[(&'static str, String)],
+        body: &Value,
+        timeout_secs: u64,
+    ) -> Result<(u16, String)> {
+        let mut params = self.query_params();
+        params.extend_from_slice(extra_params);
+        let query = query_string(&params);
+        let headers = auth::build_headers_with_cookies(&self.session_token, uri, self.cookie_jar.as_ref(), &self.fingerprint, &self.profile);
+        let url = format!("{}{uri}", self.base.jimeng);
+        let body = body.to_string();
+        let req = Request { url: &url, query: &query, headers: &headers, body: &body, timeout_secs };
+
+        let chain: Vec<TransportKind> = self.policy.ordered(endpoint, &self.preferred)
+            .into_iter()
+            .filter(|kind| match kind {
+                TransportKind::Curl => self.cookie_jar.is_some(),
+                TransportKind::Reqwest => true,
+                TransportKind::Browser => self.browser.is_some() && browser::supports_proxy(self.proxy_url.as_deref()),
+            })
+            .collect();
+        if chain.is_empty() {
+            bail!("No usable transport for {} calls", endpoint.as_str());
+        }
+
+        let mut last = None;
+        for (i, &kind) in chain.iter().enumerate() {
+            let result = match kind {
+                TransportKind::Curl => Curl { proxy_url: self.proxy_url.as_deref() }.send(&req).await,
+                TransportKind::Reqwest => SignedReqwest { http: &self.http, user_agent: &self.fingerprint.user_agent }.send(&req).await,
+                TransportKind::Browser => {
+                    let Some(browser) = self.browser.as_ref() else { continue };
+                    BrowserFetch {
+                        browser,
+                        session_token: &self.session_token,
+                        fingerprint: &self.fingerprint,
+                        proxy_url: self.proxy_url.as_deref(),
+                    }.send(&req).await
+                }
+            };
+            let attempt = TransportAttempt {
+                endpoint,
+                transport: kind,
+                attempt: i + 1,
+                status: result.as_ref().ok().map(|r| r.status),
+                error: result.as_ref().err().map(|e| e.to_string()),
+            };
+            let ok = attempt.ok();
+            let next = !ok && i + 1 < chain.len() && transport::may_retry(endpoint, &result);
+            if next {
+                tracing::warn!(
+                    endpoint = endpoint.as_str(), transport = kind.as_str(),
+                    status = attempt.status, error = attempt.error,
+                    "jimeng call failed, trying next transport"
+                );
+            }
+            self.attempts.push(attempt);
+
+            if let Ok(resp) = &result
+                && let Some(jar) = self.cookie_jar.as_mut()
+            {
+                jar.store_set_cookies(auth::JIMENG_HOST, resp.set_cookies.iter().map(String::as_str));
+            }
+            if ok {
+                self.preferred.insert(endpoint, kind);
+            }
+            last = Some((kind, result));
+            if !next {
+                break;
+            }
+        }
+
+        let (kind, result) = last.expect("chain is not empty");
+        let resp = result.map_err(|e| {
+            let timeout = e.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_timeout)
+                || matches!(e.downcast_ref::<CurlError>(), Some(CurlError::Timeout(_)));
+            JimengError::Transport { transport: kind, message: format!("{e:#}"), timeout }
+        })?;
+        self.last_transport = Some(kind);
+        Ok((resp.status, resp.body))
+    }
+
+    /// Read the `data` of a jimeng response. Error statuses, a non-zero
+    /// `ret` and schema drift become `JimengError`s naming the transport
+    /// that got the response.
+    pub(super) fn parse_response<T: DeserializeOwned>(&self, what: &'static str, status: u16, text: &str) -> Result<T, JimengError> {
+        if status >= 400 {
+            return Err(JimengError::Http {
+                what,
+                status,
+                body: text.chars().take(500).collect(),
+                transport: self.last_transport,
+            });
+        }
+        responses::parse_data(what, text).map_err(|e| e.via(self.last_transport))
+    }
+
+    /// Send a request to the upload services (ImageX, VOD and their storage
+    /// hosts). These check neither cookies nor `a_bogus`, so it goes through
+    /// the first curl or reqwest transport of the upload chain, without
+    /// fallback. Returns the status and body.
+    pub(super) async fn storage_request(
+        &self,
+        method: Method,
+        url: &str,
+        headers: HeaderMap,
+        body: Option<Vec<u8>>,
+        timeout_secs: u64,
+    ) -> Result<(u16, String)> {
+        let curl_first = self.policy.ordered(Endpoint::Upload, &self.preferred)
+            .into_iter()
+            .find(|kind| *kind != TransportKind::Browser)
+            == Some(TransportKind::Curl);
+
+        if curl_first {
+            let resp = curl_transport::send(&CurlRequest {
+                method,
+                url,
+                headers: &headers,
+                body: body.as_deref(),
+                timeout_secs,
+                proxy_url: self.proxy_url.as_deref(),
+            }).await?;
+            return Ok((resp.status, resp.text()));
+        }
+
+        let mut builder = self.http.request(method, url)
+            .headers(headers)
+            .timeout(std::time::Duration::from_secs(timeout_secs));
+        if let Some(body) = body {
+            builder = builder.body(body);
+        }
+        let resp = builder.send().await?;
+        let status = resp.status().as_u16();
+        Ok((status, resp.text().await?))
+    }
+
+    /// Current credit balance of the account.
+    pub async fn get_credit(&mut self) -> Result<Credit> {
+        let (status, text) = self.post_json(Endpoint::Account, CREDIT_URI, &[], &serde_json::json!({}), 30).await?;
+        if status >= 400 {
+            bail!("Credit HTTP {status}: {}", &text[..text.len().min(500)]);
+        }
+        let payload: Value = serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Credit parse error: {e}. Body: {}", &text[..text.len().min(500)]))?;
+        parse_credit(&payload)
+    }
+}
+
+fn query_string(params: &[(&str, String)]) -> String {
+    params.iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn parse_credit(payload: &Value) -> Result<Credit> {
+    if let Some(ret) = payload.get("ret").and_then(|r| r.as_str().map(str::to_string).or_else(|| r.as_i64().map(|n| n.to_string())))
+        && ret != "0"
+    {
+        let errmsg = payload.get("errmsg").and_then(|v| v.as_str()).unwrap_or("unknown");
+        bail!("Credit request failed [ret={ret}]: {errmsg}");
+    }
+    let Some(credit) = payload.pointer("/data/credit") else {
+        bail!("No credit in response");
+    };
+    let amount = |key: &str| credit.get(key)
+        .and_then(|v| v.as_i64().or_else(|| v.as_str()?.parse().ok()))
+        .unwrap_or(0);
+    let (gift, purchase, vip) = (amount("gift_credit"), amount("purchase_credit"), amount("vip_credit"));
+    Ok(Credit { gift, purchase, vip, total: gift + purchase + vip })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_credit() {
+        let payload = serde_json::json!({"ret": "0", "data": {"credit": {
+            "gift_credit": 60, "purchase_credit": "100", "vip_credit": 0,
+        }}});
+        assert_eq!(
+            parse_credit(&payload).unwrap(),
+            Credit { gift: 60, purchase: 100, vip: 0, total: 160 },
+        );
+
+        let expired = serde_json::json!({"ret": "1015", "errmsg": "login error"});
+        assert!(parse_credit(&expired).is_err());
+    }
+
+    /// Walks the chain past a transport the upstream refuses, then starts
+    /// with the one that worked.
+    #[tokio::test]
+    async fn test_post_json_falls_back() {
+        use axum::extract::RawQuery;
+        use axum::http::StatusCode;
+
+        // Only signed (reqwest) calls get through.
+        let app = axum::Router::new().route("/mweb/v1/test", axum::routing::post(|RawQuery(query): RawQuery| async move {
+            if query.unwrap_or_default().contains("a_bogus=") {
+                (StatusCode::OK, r#"{"ret":"0","data":{}}"#)
+            } else {
+                (StatusCode::FORBIDDEN, "blocked")
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let jar = CookieJar::from_header("sessionid=abc; sid_tt=abc");
+        let fingerprint = Fingerprint::for_cookie_jar(Some(&jar));
+        let mut client = JimengClient::new(
+            Client::new(),
+            BaseUrls { jimeng: base, ..BaseUrls::default() },
+            "abc",
+            Some(jar),
+            fingerprint,
+            None,
+        );
+
+        let (status, _) = client.post_json(Endpoint::Submit, "/mweb/v1/test", &[], &serde_json::json!({}), 10).await.unwrap();
+        assert_eq!(status, 200);
+        let attempts = client.take_attempts();
+        let tried: Vec<_> = attempts.iter().map(|a| (a.transport, a.status, a.ok())).collect();
+        assert_eq!(tried, vec![
+            (TransportKind::Curl, Some(403), false),
+            (TransportKind::Reqwest, Some(200), true),
+        ]);
+        assert_eq!(client.preferred_transports().get(&Endpoint::Submit), Some(&TransportKind::Reqwest));
+
+        client.post_json(Endpoint::Submit, "/mweb/v1/test", &[], &serde_json::json!({}), 10).await.unwrap();
+        let attempts = client.take_attempts();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].transport, TransportKind::Reqwest);
+
+        // A transport error falls back too, and the last try is returned.
+        let mut client = JimengClient::new(
+            Client::new(),
+            BaseUrls { jimeng: "http://127.0.0.1:1".into(), ..BaseUrls::default() },
+            "abc",
+            Some(CookieJar::from_header("sessionid=abc")),
+            Fingerprint::generate(),
+            None,
+        );
+        let err = client.post_json(Endpoint::Submit, "/mweb/v1/test", &[], &serde_json::json!({}), 10).await.unwrap_err();
+        assert!(err.downcast_ref::<JimengError>().is_some(), "{err:#}");
+        let attempts = client.take_attempts();
+        assert_eq!(attempts.len(), 2);
+        assert!(attempts.iter().all(|a| a.status.is_none() && a.error.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_submit_is_not_repeated_once_sent() {
+        use axum::http::StatusCode;
+
+        let app = axum::Router::new()
+            .route("/slow", axum::routing::post(|| async {
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+                "late"
+            }))
+            .route("/broken", axum::routing::post(|| async { (StatusCode::BAD_GATEWAY, "upstream") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let jar = CookieJar::from_header("sessionid=abc; sid_tt=abc");
+        let mut client = JimengClient::new(
+            Client::new(),
+            BaseUrls { jimeng: base, ..BaseUrls::default() },
+            "abc",
+            Some(jar.clone()),
+            Fingerprint::for_cookie_jar(Some(&jar)),
+            None,
+        );
+        let tried = |client: &mut JimengClient| -> Vec<TransportKind> {
+            client.take_attempts().iter().map(|a| a.transport).collect()
+        };
+
+        // jimeng may have accepted a submit that timed out or failed with a
+        // server error, so it is not sent again.
+        let err = client.post_json(Endpoint::Submit, "/slow", &[], &serde_json::json!({}), 1).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<JimengError>(), Some(JimengError::Transport { timeout: true, .. })), "{err:#}");
+        assert_eq!(tried(&mut client), vec![TransportKind::Curl]);
+        let (status, _) = client.post_json(Endpoint::Submit, "/broken", &[], &serde_json::json!({}), 10).await.unwrap();
+        assert_eq!(status, 502);
+        assert_eq!(tried(&mut client), vec![TransportKind::Curl]);
+
+        // Other calls are safe to repeat.
+        client.post_json(Endpoint::Poll, "/broken", &[], &serde_json::json!({}), 10).await.unwrap();
+        assert_eq!(tried(&mut client), vec![TransportKind::Curl, TransportKind::Reqwest]);
+    }
+}
@@ -0,0 +1,286 @@
+//! Client profiles: the jimeng web-client constants the gateway imitates.
+//!
+//! jimeng checks the app version, signing pattern and draft versions its
+//! own frontend sends, and bumps them with every release. A profile bundles
+//! those values so they can be changed without a rebuild: profiles are read
+//! from `CLIENT_PROFILES_FILE`, selected per session (`client_profile`), and
+//! reloaded at runtime. The built-in profile (`builtin`) holds the values the
+//! gateway was released with.
+//!
+//! The file is versioned JSON:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "default": "web-7.6",
+//!   "profiles": {
+//!     "web-7.6": { "version_code": "8.5.0", "web_version": "7.6.0" }
+//!   }
+//! }
+//! ```
+//!
+//! Fields left out of a profile take the built-in value. Unknown fields,
+//! empty or malformed values and a missing default are errors.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result, bail, ensure};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+
+/// Name of the built-in profile.
+pub const BUILTIN: &str = "builtin";
+/// Profile file format this gateway reads.
+const FILE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientProfile {
+    /// `Appvr` header and the version in `Sign`.
+    pub version_code: String,
+    /// `Pf` header and the platform in `Sign`.
+    pub platform_code: String,
+    /// `App-Sdk-Version` header.
+    pub sdk_version: String,
+    /// `da_version` query parameter.
+    pub da_version: String,
+    /// `web_version` query parameter.
+    pub web_version: String,
+    /// Input of the `Sign` md5, with `{uri}` (the last `sign_uri_chars`
+    /// characters of the path), `{platform}`, `{version}` and `{timestamp}`.
+    pub sign_template: String,
+    pub sign_uri_chars: usize,
+    /// Replaces the sessions' fingerprint user agent when set.
+    pub user_agent: Option<String>,
+    /// Draft content version of generate requests.
+    pub draft_version: String,
+    /// Per-model overrides of `draft_version`, by gateway model name.
+    pub draft_versions: BTreeMap<String, String>,
+    /// Draft version of multi-image (blend) generations.
+    pub blend_draft_version: String,
+}
+
+impl Default for ClientProfile {
+    fn default() -> Self {
+        Self {
+            version_code: "8.4.0".into(),
+            platform_code: "7".into(),
+            sdk_version: "48.0.0".into(),
+            da_version: "3.3.14".into(),
+            web_version: "7.5.0".into(),
+            sign_template: "9e2c|{uri}|{platform}|{version}|{timestamp}||11ac".into(),
+            sign_uri_chars: 7,
+            user_agent: None,
+            draft_version: "3.3.9".into(),
+            draft_versions: BTreeMap::new(),
+            blend_draft_version: "3.2.9".into(),
+        }
+    }
+}
+
+impl ClientProfile {
+    /// The `Sign` header for a request to `uri` at `timestamp`.
+    pub fn sign(&self, uri: &str, timestamp: u64) -> String {
+        let uri_suffix = match uri.char_indices().rev().nth(self.sign_uri_chars.saturating_sub(1)) {
+            Some((i, _)) if self.sign_uri_chars > 0 => &uri[i..],
+            _ => uri,
+        };
+        let input = self.sign_template
+            .replace("{uri}", uri_suffix)
+            .replace("{platform}", &self.platform_code)
+            .replace("{version}", &self.version_code)
+            .replace("{timestamp}", &timestamp.to_string());
+
+        let mut hasher = Md5::new();
+        hasher.update(input.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn draft_version(&self, model: &str) -> &str {
+        self.draft_versions.get(model).unwrap_or(&self.draft_version)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (field, value) in [
+            ("version_code", &self.version_code),
+            ("sdk_version", &self.sdk_version),
+            ("da_version", &self.da_version),
+            ("web_version", &self.web_version),
+            ("draft_version", &self.draft_version),
+            ("blend_draft_version", &self.blend_draft_version),
+        ] {
+            ensure!(is_version(value), "{field} must be a dotted version like 8.4.0, got {value:?}");
+        }
+        for (model, version) in &self.draft_versions {
+            ensure!(is_version(version), "draft_versions.{model} must be a dotted version, got {version:?}");
+        }
+        ensure!(
+            !self.platform_code.is_empty() && self.platform_code.chars().all(|c| c.is_ascii_digit()),
+            "platform_code must be numeric, got {:?}", self.platform_code,
+        );
+        for placeholder in ["{uri}", "{timestamp}"] {
+            ensure!(self.sign_template.contains(placeholder), "sign_template must contain {placeholder}");
+        }
+        ensure!(self.sign_uri_chars > 0, "sign_uri_chars must be positive");
+        if let Some(ua) = &self.user_agent {
+            ensure!(!ua.trim().is_empty() && reqwest::header::HeaderValue::from_str(ua).is_ok(), "user_agent is not a valid header value");
+        }
+        Ok(())
+    }
+}
+
+fn is_version(s: &str) -> bool {
+    !s.is_empty() && s.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    version: u32,
+    /// Profile for sessions that do not pick one; `builtin` when left out.
+    #[serde(default)]
+    default: Option<String>,
+    profiles: BTreeMap<String, ClientProfile>,
+}
+
+#[derive(Debug, Clone)]
+struct ProfileSet {
+    default: String,
+    profiles: BTreeMap<String, Arc<ClientProfile>>,
+}
+
+impl ProfileSet {
+    fn builtin() -> Self {
+        Self {
+            default: BUILTIN.into(),
+            profiles: BTreeMap::from([(BUILTIN.to_string(), Arc::new(ClientProfile::default()))]),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let file: ProfileFile = serde_json::from_str(text)?;
+        if file.version != FILE_VERSION {
+            bail!("unsupported profile file version {} (expected {FILE_VERSION})", file.version);
+        }
+        let mut set = Self::builtin();
+        for (name, profile) in file.profiles {
+            ensure!(!name.trim().is_empty(), "profile names must not be empty");
+            profile.validate().with_context(|| format!("profile {name:?}"))?;
+            set.profiles.insert(name, Arc::new(profile));
+        }
+        if let Some(default) = file.default {
+            ensure!(set.profiles.contains_key(&default), "default profile {default:?} is not defined");
+            set.default = default;
+        }
+        Ok(set)
+    }
+}
+
+/// The loaded profiles, shared by all requests.
+pub struct ClientProfiles {
+    path: Option<PathBuf>,
+    set: RwLock<ProfileSet>,
+}
+
+/// The loaded profiles and which one is the default.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileList {
+    pub default: String,
+    pub profiles: BTreeMap<String, ClientProfile>,
+}
+
+impl ClientProfiles {
+    /// Load profiles from `path`, or only the built-in one without a file.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let set = match &path {
+            Some(path) => read(path)?,
+            None => ProfileSet::builtin(),
+        };
+        Ok(Self { path, set: RwLock::new(set) })
+    }
+
+    /// Re-read the profile file. A malformed file is rejected and the
+    /// loaded profiles stay in use.
+    pub fn reload(&self) -> Result<ProfileList> {
+        let Some(path) = &self.path else {
+            bail!("CLIENT_PROFILES_FILE is not set");
+        };
+        let set = read(path)?;
+        *self.set.write().unwrap() = set;
+        tracing::info!(path = %path.display(), "Client profiles reloaded");
+        Ok(self.list())
+    }
+
+    /// Profile `name`, or the default one for `None`. A session whose
+    /// profile was removed from the file falls back to the default.
+    pub fn get(&self, name: Option<&str>) -> Arc<ClientProfile> {
+        let set = self.set.read().unwrap();
+        if let Some(name) = name {
+            if let Some(profile) = set.profiles.get(name) {
+                return profile.clone();
+            }
+            tracing::warn!(profile = name, "Unknown client profile, using default");
+        }
+        set.profiles[&set.default].clone()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.set.read().unwrap().profiles.contains_key(name)
+    }
+
+    pub fn list(&self) -> ProfileList {
+        let set = self.set.read().unwrap();
+        ProfileList {
+            default: set.default.clone(),
+            profiles: set.profiles.iter().map(|(name, p)| (name.clone(), (**p).clone())).collect(),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<ProfileSet> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading client profiles from {}", path.display()))?;
+    ProfileSet::parse(&text).with_context(|| format!("invalid client profiles in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_sign_matches_legacy() {
+        // md5("9e2c|ft_list|7|8.4.0|1700000000||11ac") as computed before
+        // profiles existed.
+        let input = "9e2c|ft_list|7|8.4.0|1700000000||11ac";
+        let expected = format!("{:x}", Md5::digest(input.as_bytes()));
+        assert_eq!(ClientProfile::default().sign("/mweb/v1/get_history_by_ids/draft_list", 1700000000), expected);
+    }
+
+    #[test]
+    fn test_parse_profile_file() {
+        let set = ProfileSet::parse(r#"{
+            "version": 1,
+            "default": "web-7.6",
+            "profiles": {"web-7.6": {"version_code": "8.5.0", "draft_versions": {"jimeng-5.0": "3.4.0"}}}
+        }"#).unwrap();
+        assert_eq!(set.default, "web-7.6");
+        let profile = &set.profiles["web-7.6"];
+        assert_eq!(profile.version_code, "8.5.0");
+        assert_eq!(profile.web_version, "7.5.0");
+        assert_eq!(profile.draft_version("jimeng-5.0"), "3.4.0");
+        assert_eq!(profile.draft_version("seedance-2.0"), "3.3.9");
+        assert!(set.profiles.contains_key(BUILTIN));
+
+        for bad in [
+            r#"{"version": 2, "profiles": {}}"#,
+            r#"{"version": 1, "default": "missing", "profiles": {}}"#,
+            r#"{"version": 1, "profiles": {"a": {"web_versoin": "7.6.0"}}}"#,
+            r#"{"version": 1, "profiles": {"a": {"version_code": "8.x"}}}"#,
+            r#"{"version": 1, "profiles": {"a": {"sign_template": "{uri}|static"}}}"#,
+        ] {
+            assert!(ProfileSet::parse(bad).is_err(), "{bad}");
+        }
+    }
+}
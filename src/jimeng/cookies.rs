@@ -0,0 +1,329 @@
+//! Structured cookie jar for jimeng sessions.
+//!
+//! Jars are stored as a JSON list of cookies (name, value, domain, path,
+//! expiry, flags). Rows written before that hold a plain `a=1; b=2` header
+//! string, which is still accepted and read as `.jianying.com` cookies.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Domain assumed for cookies that don't carry one (header strings).
+pub const DEFAULT_DOMAIN: &str = ".jianying.com";
+
+/// Cookies that carry the jimeng login; the session ends when they expire.
+pub const AUTH_COOKIES: &[&str] = &["sessionid", "sid_tt", "sid_guard"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    #[serde(default = "default_domain")]
+    pub domain: String,
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// Expiry as a Unix timestamp; `None` for session cookies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+    #[serde(default)]
+    pub http_only: bool,
+    #[serde(default)]
+    pub secure: bool,
+}
+
+fn default_domain() -> String {
+    DEFAULT_DOMAIN.to_string()
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+impl Cookie {
+    /// A session cookie for `DEFAULT_DOMAIN`, path `/`.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            domain: default_domain(),
+            path: default_path(),
+            expires: None,
+            http_only: false,
+            secure: false,
+        }
+    }
+
+    /// Parse a `Set-Cookie` header value received from `host`.
+    pub fn parse_set_cookie(header: &str, host: &str) -> Option<Self> {
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.trim().split_once('=')?;
+        if name.trim().is_empty() {
+            return None;
+        }
+        let mut cookie = Self::new(name.trim(), value.trim().trim_matches('"'));
+        // Host-only cookie unless a Domain attribute says otherwise.
+        cookie.domain = host.to_string();
+
+        let mut max_age = None;
+        for attr in parts {
+            let (key, val) = attr.trim().split_once('=').unwrap_or((attr.trim(), ""));
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !val.is_empty() => {
+                    cookie.domain = format!(".{}", val.trim().trim_start_matches('.'));
+                }
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "expires" => cookie.expires = parse_cookie_date(val).or(cookie.expires),
+                "max-age" => max_age = val.trim().parse::<i64>().ok(),
+                "httponly" => cookie.http_only = true,
+                "secure" => cookie.secure = true,
+                _ => {}
+            }
+        }
+        // Max-Age takes precedence over Expires.
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(now().saturating_add(max_age));
+        }
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|e| e <= now)
+    }
+
+    fn matches(&self, host: &str, path: &str) -> bool {
+        let domain = self.domain.trim_start_matches('.');
+        let domain_ok = host.eq_ignore_ascii_case(domain)
+            || host.to_ascii_lowercase().ends_with(&format!(".{}", domain.to_ascii_lowercase()));
+        let path_ok = path.starts_with(&self.path)
+            && (self.path.ends_with('/') || path.len() == self.path.len()
+                || path[self.path.len()..].starts_with('/'));
+        domain_ok && path_ok
+    }
+
+    /// Same name, domain and path: a newer cookie replaces this one.
+    fn same_slot(&self, other: &Cookie) -> bool {
+        self.name == other.name
+            && self.path == other.path
+            && self.domain.trim_start_matches('.').eq_ignore_ascii_case(other.domain.trim_start_matches('.'))
+    }
+}
+
+/// Parse the date formats seen in `Expires` attributes
+/// (`Tue, 21 Oct 2025 07:28:00 GMT`, `Mon, 03-Feb-2025 08:17:09 GMT`).
+/// The weekday is ignored, as browsers do.
+fn parse_cookie_date(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let date = value.split_once(", ").map_or(value, |(_, date)| date);
+    ["%d-%b-%Y %H:%M:%S GMT", "%d %b %Y %H:%M:%S GMT", "%d-%b-%y %H:%M:%S GMT"]
+        .iter()
+        .find_map(|fmt| chrono::NaiveDateTime::parse_from_str(date, fmt).ok())
+        .map(|dt| dt.and_utc().timestamp())
+}
+
+/// A session's cookies, in the order they were first set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// Build a jar from cookies; later duplicates replace earlier ones.
+    pub fn from_cookies(cookies: impl IntoIterator<Item = Cookie>) -> Self {
+        let mut jar = Self::default();
+        for cookie in cookies {
+            jar.insert(cookie);
+        }
+        jar
+    }
+
+    /// Parse a stored jar: the JSON form, or a legacy `a=1; b=2` string.
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        if text.starts_with('[') {
+            return Ok(Self::from_cookies(serde_json::from_str::<Vec<Cookie>>(text)?));
+        }
+        Ok(Self::from_header(text))
+    }
+
+    /// Read a `Cookie` header string; every cookie gets `DEFAULT_DOMAIN`.
+    pub fn from_header(header: &str) -> Self {
+        Self::from_cookies(
+            header
+                .trim()
+                .trim_start_matches("Cookie:")
+                .split(';')
+                .filter_map(|part| part.trim().split_once('='))
+                .filter(|(n, _)| !n.is_empty())
+                .map(|(n, v)| Cookie::new(n, v)),
+        )
+    }
+
+    /// Serialized form stored in the database.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.cookies).unwrap_or_else(|_| "[]".into())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie> {
+        self.cookies.iter()
+    }
+
+    /// Earliest expiry (Unix seconds) among the login cookies
+    /// (`AUTH_COOKIES`); short-lived tracking cookies don't count.
+    pub fn auth_expiry(&self) -> Option<i64> {
+        self.cookies
+            .iter()
+            .filter(|c| AUTH_COOKIES.contains(&c.name.as_str()))
+            .filter_map(|c| c.expires)
+            .min()
+    }
+
+    /// Value of the first unexpired cookie called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let now = now();
+        self.cookies
+            .iter()
+            .find(|c| c.name == name && !c.is_expired(now))
+            .map(|c| c.value.as_str())
+    }
+
+    /// Add or replace a cookie. An expired cookie deletes its slot.
+    /// Returns whether the jar changed.
+    pub fn insert(&mut self, cookie: Cookie) -> bool {
+        let existing = self.cookies.iter().position(|c| c.same_slot(&cookie));
+        match (existing, cookie.is_expired(now())) {
+            (Some(i), true) => {
+                self.cookies.remove(i);
+                true
+            }
+            (None, true) => false,
+            (Some(i), false) if self.cookies[i] == cookie => false,
+            (Some(i), false) => {
+                self.cookies[i] = cookie;
+                true
+            }
+            (None, false) => {
+                self.cookies.push(cookie);
+                true
+            }
+        }
+    }
+
+    /// Apply cookies on top of this jar (see `insert`).
+    pub fn merge(&mut self, cookies: impl IntoIterator<Item = Cookie>) -> bool {
+        cookies.into_iter().fold(false, |changed, c| self.insert(c) | changed)
+    }
+
+    /// Record `Set-Cookie` header values from a response served by `host`.
+    pub fn store_set_cookies<'a>(&mut self, host: &str, headers: impl IntoIterator<Item = &'a str>) -> bool {
+        self.merge(headers.into_iter().filter_map(|h| Cookie::parse_set_cookie(h, host)))
+    }
+
+    /// Changes that turn `old` into this jar, for merging into a copy of
+    /// `old` that may have been updated in the meantime. Removed cookies are
+    /// returned already expired so that merging them deletes them.
+    pub fn changes_since(&self, old: &CookieJar) -> Vec<Cookie> {
+        let mut changes: Vec<Cookie> = self
+            .cookies
+            .iter()
+            .filter(|c| !old.cookies.contains(c))
+            .cloned()
+            .collect();
+        changes.extend(
+            old.cookies
+                .iter()
+                .filter(|o| !self.cookies.iter().any(|c| c.same_slot(o)))
+                .map(|o| Cookie { expires: Some(0), ..o.clone() }),
+        );
+        changes
+    }
+
+    /// `Cookie` header value for a request to `host` + `path`.
+    pub fn header_for(&self, host: &str, path: &str) -> String {
+        let now = now();
+        self.cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches(host, path))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl IntoIterator for CookieJar {
+    type Item = Cookie;
+    type IntoIter = std::vec::IntoIter<Cookie>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.cookies.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_legacy_and_json() {
+        let jar = CookieJar::parse("sessionid=abc; ttwid=1|x").unwrap();
+        assert_eq!(jar.get("ttwid"), Some("1|x"));
+        assert_eq!(jar.iter().next().unwrap().domain, DEFAULT_DOMAIN);
+
+        let again = CookieJar::parse(&jar.to_json()).unwrap();
+        assert_eq!(again, jar);
+        assert!(jar.to_json().contains("\"httpOnly\":false"));
+    }
+
+    #[test]
+    fn test_set_cookie_merges_and_deletes() {
+        let mut jar = CookieJar::from_header("sessionid=abc; msToken=old");
+        let changed = jar.store_set_cookies("jimeng.jianying.com", [
+            "msToken=new; Domain=jianying.com; Path=/; Max-Age=3600; Secure",
+            "odin_tt=o1; expires=Tue, 03-Feb-2099 08:17:09 GMT; path=/; HttpOnly",
+            "sessionid=; Domain=.jianying.com; Max-Age=0",
+        ]);
+        assert!(changed);
+        assert_eq!(jar.get("msToken"), Some("new"));
+        assert_eq!(jar.get("sessionid"), None);
+        let odin = jar.iter().find(|c| c.name == "odin_tt").unwrap();
+        assert!(odin.http_only);
+        assert_eq!(odin.domain, "jimeng.jianying.com");
+        assert_eq!(odin.expires, Some(4073789829));
+    }
+
+    #[test]
+    fn test_header_for_filters_domain_and_path() {
+        let jar = CookieJar::from_cookies([
+            Cookie::new("a", "1"),
+            Cookie { domain: "imagex.bytedanceapi.com".into(), ..Cookie::new("b", "2") },
+            Cookie { path: "/mweb".into(), ..Cookie::new("c", "3") },
+            Cookie { expires: Some(1), ..Cookie::new("d", "4") },
+        ]);
+        assert_eq!(jar.header_for("jimeng.jianying.com", "/mweb/v1/x"), "a=1; c=3");
+        assert_eq!(jar.header_for("jimeng.jianying.com", "/mwebx"), "a=1");
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let old = CookieJar::from_header("a=1; b=2");
+        let mut new = old.clone();
+        new.store_set_cookies("jimeng.jianying.com", ["a=9; Domain=.jianying.com", "b=; Domain=.jianying.com; Max-Age=0"]);
+
+        // Meanwhile the stored jar gained another cookie.
+        let mut stored = CookieJar::from_header("a=1; b=2; c=3");
+        stored.merge(new.changes_since(&old));
+        assert_eq!(stored.header_for("jimeng.jianying.com", "/"), "a=9; c=3");
+    }
+}
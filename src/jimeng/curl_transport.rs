@@ -3,84 +3,360 @@
 //! ByteDance uses TLS/JA3 fingerprinting to detect non-browser clients.
 //! reqwest's TLS fingerprint differs from Chrome, triggering 4013 risk control.
 //! System curl links to OpenSSL with a fingerprint that ByteDance accepts.
+//!
+//! Headers and the proxy go to curl as a config on stdin and request bodies
+//! over a pipe of their own, so nothing (prompts, cookies, proxy
+//! credentials) touches the disk or shows in `ps`.
+
+use std::os::fd::{AsRawFd, OwnedFd};
 
-use anyhow::{bail, Result};
-use reqwest::header::HeaderMap;
+use anyhow::{Context, Result};
+use reqwest::Method;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::io::AsyncWriteExt;
+use tokio::net::unix::pipe;
 use tokio::process::Command;
 
-/// POST JSON to a URL via system curl, returning (status_code, response_body).
-///
-/// Body is written to a temp file to avoid stdin piping issues.
-pub async fn post_json_via_curl(
-    url: &str,
-    headers: &HeaderMap,
-    body: &str,
-    timeout_secs: u64,
-) -> Result<(u16, String)> {
-    // Write body to a temp file (avoids stdin pipe buffering issues)
-    let ts = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_millis();
-    let body_file = format!("/tmp/jimeng_curl_{}_{}.json", std::process::id(), ts);
-    tokio::fs::write(&body_file, body.as_bytes()).await?;
-
-    let timeout_str = timeout_secs.to_string();
-
-    // Build header strings
-    let mut header_strings = vec!["Content-Type: application/json".to_string()];
-    for (name, value) in headers.iter() {
-        let value_str = value.to_str().unwrap_or("");
-        header_strings.push(format!("{}: {}", name.as_str(), value_str));
+const STATUS_MARKER: &[u8] = b"\n__CURL_STATUS__";
+
+/// A request for curl to send.
+pub struct CurlRequest<'a> {
+    pub method: Method,
+    pub url: &'a str,
+    pub headers: &'a HeaderMap,
+    /// Sent as-is; callers set `Content-Type` in `headers`.
+    pub body: Option<&'a [u8]>,
+    pub timeout_secs: u64,
+    /// Curl's `proxy` (http/https/socks5/socks5h), sent in the stdin config.
+    pub proxy_url: Option<&'a str>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CurlError {
+    #[error("curl timed out after {0}s")]
+    Timeout(u64),
+    #[error("curl failed (exit {status}): {stderr}")]
+    Failed { status: std::process::ExitStatus, stderr: String },
+}
+
+impl CurlError {
+    /// Failed before the request reached the server: the proxy or host
+    /// could not be resolved or connected to, or the TLS handshake failed.
+    pub fn before_send(&self) -> bool {
+        // 5/6: resolve proxy/host, 7: connect, 35: TLS handshake,
+        // 60: peer certificate, 97: proxy handshake.
+        matches!(self, Self::Failed { status, .. } if matches!(status.code(), Some(5 | 6 | 7 | 35 | 60 | 97)))
+    }
+}
+
+/// Response of a curl request.
+#[derive(Debug)]
+pub struct CurlResponse {
+    pub status: u16,
+    /// Headers of the final response (interim `1xx` responses are dropped).
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl CurlResponse {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Raw `Set-Cookie` header values, for merging into the session's jar.
+    pub fn set_cookies(&self) -> Vec<String> {
+        self.headers
+            .get_all(reqwest::header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok().map(str::to_string))
+            .collect()
     }
+}
 
+/// File descriptor on which curl reads the request body.
+const BODY_FD: i32 = 3;
+
+/// Send a request via system curl.
+pub async fn send(req: &CurlRequest<'_>) -> Result<CurlResponse> {
     let mut cmd = Command::new("curl");
     cmd.arg("--silent")
         .arg("--show-error")
-        .arg("--max-time").arg(&timeout_str)
+        .arg("--max-time").arg(req.timeout_secs.to_string())
         .arg("--connect-timeout").arg("15")
-        .arg("--request").arg("POST")
+        .arg("--request").arg(req.method.as_str())
+        .arg("--dump-header").arg("-")
+        .arg("--suppress-connect-headers")
         .arg("--write-out").arg("\n__CURL_STATUS__%{http_code}")
-        .arg("--url").arg(url)
-        .arg("-d").arg(format!("@{body_file}"));
+        .arg("--url").arg(req.url)
+        // Headers (cookies included) and the proxy are read from stdin, so
+        // they don't show up in the process list.
+        .arg("--config").arg("-");
 
-    for h in &header_strings {
-        cmd.arg("-H").arg(h);
+    // Large bodies would otherwise wait on `100 Continue`.
+    let mut config = config_line("header", b"Expect:");
+    for (name, value) in req.headers.iter() {
+        let mut header = name.as_str().as_bytes().to_vec();
+        header.extend_from_slice(b": ");
+        header.extend_from_slice(value.as_bytes());
+        config.extend(config_line("header", &header));
+    }
+    if let Some(proxy) = req.proxy_url {
+        config.extend(config_line("proxy", proxy.as_bytes()));
     }
 
+    // The body goes over a pipe of its own, mapped to `BODY_FD` in curl.
+    let body_pipe = match req.body {
+        Some(_) => {
+            let (reader, writer) = std::io::pipe().context("Failed to create curl body pipe")?;
+            let fd = reader.as_raw_fd();
+            // SAFETY: dup2 and fcntl are async-signal-safe; the closure
+            // allocates nothing.
+            unsafe {
+                cmd.pre_exec(move || {
+                    let res = if fd == BODY_FD {
+                        libc::fcntl(fd, libc::F_SETFD, 0)
+                    } else {
+                        libc::dup2(fd, BODY_FD)
+                    };
+                    if res == -1 { Err(std::io::Error::last_os_error()) } else { Ok(()) }
+                });
+            }
+            cmd.arg("--data-binary").arg(format!("@/dev/fd/{BODY_FD}"));
+            Some((reader, writer))
+        }
+        None => None,
+    };
+
     // Log header names (not values to avoid leaking cookies)
-    let header_names: Vec<&str> = header_strings.iter()
-        .map(|h| h.split(':').next().unwrap_or("?"))
-        .collect();
-    tracing::debug!(?header_names, body_len = body.len(), %body_file, "curl transport request");
+    let header_names: Vec<&str> = req.headers.keys().map(HeaderName::as_str).collect();
+    tracing::debug!(
+        method = %req.method, ?header_names, body_len = req.body.map_or(0, <[u8]>::len),
+        "curl transport request"
+    );
 
-    let output = cmd
+    let mut child = cmd
+        .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
         .kill_on_drop(true)
         .spawn()
-        .map_err(|e| anyhow::anyhow!("Failed to spawn curl: {e}"))?
-        .wait_with_output()
-        .await?;
+        .map_err(|e| anyhow::anyhow!("Failed to spawn curl: {e}"))?;
 
-    let _ = tokio::fs::remove_file(&body_file).await;
+    // Only curl holds the read end now, so it sees EOF once we're done.
+    let body_writer = match body_pipe {
+        Some((reader, writer)) => {
+            drop(reader);
+            Some(pipe::Sender::from_owned_fd(OwnedFd::from(writer)).context("Failed to open curl body pipe")?)
+        }
+        None => None,
+    };
 
-    if !output.status.success() && output.stdout.is_empty() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("curl failed (exit {}): {}", output.status, stderr.trim());
+    // Feed headers and body while curl's output is being read, so neither
+    // side blocks on a full pipe; dropping a writer closes its stream.
+    let stdin = child.stdin.take();
+    let body = req.body.unwrap_or_default();
+    let write_input = async move {
+        if let Some(mut stdin) = stdin {
+            stdin.write_all(&config).await?;
+        }
+        if let Some(mut writer) = body_writer {
+            writer.write_all(body).await?;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    let (written, output) = tokio::join!(write_input, child.wait_with_output());
+    let output = output?;
+
+    // `--write-out` prints the status marker even when the transfer fails,
+    // so the exit code decides; a timed out transfer may have a partial body.
+    if !output.status.success() {
+        // 28: operation timed out
+        if output.status.code() == Some(28) {
+            return Err(CurlError::Timeout(req.timeout_secs).into());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(CurlError::Failed { status: output.status, stderr }.into());
     }
+    written.context("Failed to stream request to curl")?;
+
+    parse_output(&output.stdout)
+}
 
-    let raw = String::from_utf8_lossy(&output.stdout);
+/// One `name = "value"` line of a curl config, with the value escaped.
+fn config_line(name: &str, value: &[u8]) -> Vec<u8> {
+    let mut line = format!("{name} = \"").into_bytes();
+    for &b in value {
+        match b {
+            b'"' | b'\\' => line.extend_from_slice(&[b'\\', b]),
+            b'\t' => line.extend_from_slice(b"\\t"),
+            _ => line.push(b),
+        }
+    }
+    line.extend_from_slice(b"\"\n");
+    line
+}
 
-    // Parse status code from --write-out marker
-    let (response_body, status_code) = if let Some(idx) = raw.rfind("\n__CURL_STATUS__") {
-        let body_part = &raw[..idx];
-        let code_str = &raw[idx + "\n__CURL_STATUS__".len()..];
-        let code: u16 = code_str.trim().parse().unwrap_or(0);
-        (body_part.to_string(), code)
-    } else {
-        (raw.to_string(), 0)
+/// Split curl's stdout (header blocks, body, status marker) into a response.
+fn parse_output(raw: &[u8]) -> Result<CurlResponse> {
+    let (raw, status) = match find_last(raw, STATUS_MARKER) {
+        Some(idx) => {
+            let code = String::from_utf8_lossy(&raw[idx + STATUS_MARKER.len()..]);
+            (&raw[..idx], code.trim().parse().unwrap_or(0))
+        }
+        None => (raw, 0),
     };
 
-    Ok((status_code, response_body))
+    // `--dump-header -` writes a header block per response, so interim
+    // `100 Continue` ones come before the final one.
+    let mut headers = HeaderMap::new();
+    let mut rest = raw;
+    while rest.starts_with(b"HTTP/") {
+        let Some(end) = find_first(rest, b"\r\n\r\n") else { break };
+        let block = String::from_utf8_lossy(&rest[..end]);
+        rest = &rest[end + 4..];
+
+        let mut lines = block.lines();
+        let interim = lines.next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .is_some_and(|code| code.starts_with('1'));
+        if interim {
+            continue;
+        }
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else { continue };
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.trim().as_bytes()), HeaderValue::from_str(value.trim())) {
+                headers.append(name, value);
+            }
+        }
+        break;
+    }
+
+    Ok(CurlResponse { status, headers, body: rest.to_vec() })
+}
+
+fn find_first(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn find_last(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let mut raw = b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+            Set-Cookie: a=1; Path=/\r\nSet-Cookie: b=2\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[0, 159, 146, 150, b'\n']);
+        raw.extend_from_slice(b"\n__CURL_STATUS__200");
+
+        let resp = parse_output(&raw).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.headers.get("content-type").unwrap(), "application/octet-stream");
+        assert_eq!(resp.set_cookies(), vec!["a=1; Path=/", "b=2"]);
+        assert_eq!(resp.body, vec![0, 159, 146, 150, b'\n']);
+
+        let resp = parse_output(b"\n__CURL_STATUS__000").unwrap();
+        assert_eq!(resp.status, 0);
+        assert!(resp.body.is_empty());
+    }
+
+    /// A one-shot server that answers with the request it got as body.
+    async fn echo_server() -> (String, tokio::task::JoinHandle<()>) {
+        use tokio::io::AsyncReadExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/echo", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = conn.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let Some(end) = find_first(&request, b"\r\n\r\n") else { continue };
+                let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                let len: usize = head.lines()
+                    .find_map(|l| l.strip_prefix("content-length:"))
+                    .map_or(0, |v| v.trim().parse().unwrap());
+                if n == 0 || request.len() >= end + 4 + len {
+                    break;
+                }
+            }
+            let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", request.len()).into_bytes();
+            response.extend_from_slice(&request);
+            conn.write_all(&response).await.unwrap();
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_send_passes_headers_and_body() {
+        let (url, server) = echo_server().await;
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", HeaderValue::from_static("sessionid=secret"));
+        headers.insert("x-quoted", HeaderValue::from_static(r#"a "b" \c"#));
+        let body = [0u8, 1, 2, 255, b'\n'];
+        let resp = send(&CurlRequest {
+            method: Method::POST,
+            url: &url,
+            headers: &headers,
+            body: Some(&body),
+            timeout_secs: 10,
+            proxy_url: None,
+        }).await.unwrap();
+        server.await.unwrap();
+
+        assert_eq!(resp.status, 200);
+        let text = resp.text();
+        assert!(text.starts_with("POST /echo HTTP/1.1\r\n"), "{text}");
+        assert!(text.contains("cookie: sessionid=secret\r\n"), "{text}");
+        assert!(text.contains("x-quoted: a \"b\" \\c\r\n"), "{text}");
+        assert!(!text.to_lowercase().contains("expect:"), "{text}");
+        assert!(resp.body.ends_with(&body));
+    }
+
+    #[tokio::test]
+    async fn test_send_through_proxy() {
+        // The echo server stands in for an HTTP proxy.
+        let (proxy, server) = echo_server().await;
+        let proxy = proxy.replace("http://", "http://user:p%40ss@").replace("/echo", "");
+        let headers = HeaderMap::new();
+        let resp = send(&CurlRequest {
+            method: Method::GET,
+            url: "http://jimeng.invalid/path",
+            headers: &headers,
+            body: None,
+            timeout_secs: 10,
+            proxy_url: Some(&proxy),
+        }).await.unwrap();
+        server.await.unwrap();
+
+        let text = resp.text();
+        assert!(text.starts_with("GET http://jimeng.invalid/path HTTP/1.1\r\n"), "{text}");
+        // base64("user:p@ss")
+        assert!(text.contains("Proxy-Authorization: Basic dXNlcjpwQHNz\r\n"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn test_send_failures() {
+        let headers = HeaderMap::new();
+        let request = |url| CurlRequest { method: Method::GET, url, headers: &headers, body: None, timeout_secs: 1, proxy_url: None };
+
+        // Nothing listens on the port once the listener is dropped.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let url = format!("http://{addr}/");
+        let err = send(&request(&url)).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<CurlError>(), Some(CurlError::Failed { .. })), "{err}");
+
+        // Accepts, never answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stalled = format!("http://{}/", listener.local_addr().unwrap());
+        let err = send(&request(&stalled)).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<CurlError>(), Some(CurlError::Timeout(1))), "{err}");
+        drop(listener);
+    }
 }
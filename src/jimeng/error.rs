@@ -0,0 +1,297 @@
+//! Typed jimeng failures and the public error codes they map to.
+//!
+//! Errors are built where the upstream response is read (`submit`, `poll`,
+//! `upload`, `client`), carrying the `ret` code, `fail_starling_key`, HTTP
+//! status and transport as reported. `JimengError::code` turns them into an
+//! `ErrorCode` through the tables below, which is what tasks store as
+//! `error_kind` and what the API and webhooks expose.
+
+use serde::Serialize;
+
+use super::responses::SchemaError;
+use super::transport::TransportKind;
+
+/// Public error codes. Stored in `tasks.error_kind` and returned by the API
+/// and webhooks, so existing codes must never be renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Prompt, input or output rejected by content moderation.
+    ContentRisk,
+    /// Account banned or restricted.
+    AccountBlocked,
+    /// Session token expired or invalid.
+    Auth,
+    /// Out of credits or daily generations.
+    Quota,
+    /// Request rejected by ByteDance's client checks (e.g. `ret=4013`);
+    /// usually fixed by fresh cookies or another transport.
+    RiskControl,
+    Timeout,
+    /// jimeng accepted the task but could not generate it.
+    GenerationFailed,
+    /// Transport could not reach jimeng.
+    Network,
+    /// jimeng answered with a 5xx.
+    UpstreamError,
+    /// Response no longer matches the expected schema.
+    UpstreamSchema,
+    /// ImageX/VOD rejected an upload.
+    UploadFailed,
+    /// The task's own input is unusable (e.g. a prompt placeholder without
+    /// a matching material).
+    InvalidInput,
+    Unknown,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ContentRisk => "content_risk",
+            Self::AccountBlocked => "account_blocked",
+            Self::Auth => "auth",
+            Self::Quota => "quota",
+            Self::RiskControl => "risk_control",
+            Self::Timeout => "timeout",
+            Self::GenerationFailed => "generation_failed",
+            Self::Network => "network",
+            Self::UpstreamError => "upstream_error",
+            Self::UpstreamSchema => "upstream_schema",
+            Self::UploadFailed => "upload_failed",
+            Self::InvalidInput => "invalid_input",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Upstream `ret` codes with a known meaning. Also matched against the
+/// numeric `fail_code` of failed generations.
+const RET_CODES: &[(&str, ErrorCode)] = &[
+    ("1015", ErrorCode::Auth),          // login error
+    ("4013", ErrorCode::RiskControl),   // client fingerprint rejected
+    ("5000", ErrorCode::Quota),         // not enough credits
+    ("2038", ErrorCode::ContentRisk),   // input text risk
+    ("2039", ErrorCode::ContentRisk),   // input image risk
+    ("2040", ErrorCode::ContentRisk),   // output risk
+    ("100402", ErrorCode::GenerationFailed),
+];
+
+/// Fragments of `fail_starling_key` (the i18n key of jimeng's failure
+/// message), matched case-insensitively.
+const FAIL_KEYS: &[(&str, ErrorCode)] = &[
+    ("violates_community_guidelines", ErrorCode::ContentRisk),
+    ("violate_guidelines", ErrorCode::ContentRisk),
+    ("sensitive_text", ErrorCode::ContentRisk),
+    ("fail2generate_input", ErrorCode::ContentRisk),
+    ("inputtextrisk", ErrorCode::ContentRisk),
+    ("inputimagerisk", ErrorCode::ContentRisk),
+    ("outputimagerisk", ErrorCode::ContentRisk),
+    ("outputvideorisk", ErrorCode::ContentRisk),
+    ("content_violation", ErrorCode::ContentRisk),
+    ("account_block", ErrorCode::AccountBlocked),
+    ("risk_notification", ErrorCode::AccountBlocked),
+    ("risk_control", ErrorCode::AccountBlocked),
+    ("daily_usage_limit", ErrorCode::Quota),
+    ("generation_failed", ErrorCode::GenerationFailed),
+];
+
+/// Phrases of `errmsg`/`fail_msg` for failures jimeng reports without a
+/// distinct code.
+const MESSAGES: &[(&str, ErrorCode)] = &[
+    ("平台规则", ErrorCode::ContentRisk),
+    ("内容违规", ErrorCode::ContentRisk),
+    ("不符合", ErrorCode::ContentRisk),
+    ("未通过审核", ErrorCode::ContentRisk),
+    ("不合适内容", ErrorCode::ContentRisk),
+    ("账号已被封禁", ErrorCode::AccountBlocked),
+    ("异常行为", ErrorCode::AccountBlocked),
+    ("风控失败", ErrorCode::AccountBlocked),
+    ("每日使用上限", ErrorCode::Quota),
+    ("积分不足", ErrorCode::Quota),
+    ("生成失败", ErrorCode::GenerationFailed),
+];
+
+/// Fail key and message of the daily usage limit (see `is_daily_limit`).
+const DAILY_LIMIT_KEY: &str = "daily_usage_limit";
+const DAILY_LIMIT_MESSAGE: &str = "每日使用上限";
+
+/// Phrases of a submit's `errmsg` saying a referenced material (ImageX
+/// `uri` or VOD `vid`) is unknown or gone, matched case-insensitively.
+const MATERIAL_MESSAGES: &[&str] = &[
+    "素材不存在",
+    "素材已失效",
+    "素材已过期",
+    "素材无效",
+    "资源不存在",
+    "invalid uri",
+    "invalid vid",
+    "uri not found",
+    "vid not found",
+];
+
+fn lookup_ret(code: &str) -> Option<ErrorCode> {
+    RET_CODES.iter().find(|(ret, _)| *ret == code).map(|(_, c)| *c)
+}
+
+fn lookup_fail_key(key: &str) -> Option<ErrorCode> {
+    let key = key.to_lowercase();
+    FAIL_KEYS.iter().find(|(fragment, _)| key.contains(fragment)).map(|(_, c)| *c)
+}
+
+fn lookup_message(msg: &str) -> Option<ErrorCode> {
+    MESSAGES.iter().find(|(phrase, _)| msg.contains(phrase)).map(|(_, c)| *c)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JimengError {
+    #[error("{what} HTTP {status}: {body}")]
+    Http {
+        what: &'static str,
+        status: u16,
+        /// Start of the response body.
+        body: String,
+        transport: Option<TransportKind>,
+    },
+    /// Non-zero `ret` in the response envelope.
+    #[error("{what} failed [ret={ret}]: {errmsg}")]
+    Api {
+        what: &'static str,
+        ret: String,
+        errmsg: String,
+        transport: Option<TransportKind>,
+    },
+    /// Generation ended in the failed status.
+    #[error("{}: {}", fail_code.as_deref().unwrap_or("unknown"), fail_msg.as_deref().unwrap_or(""))]
+    Generation {
+        /// `fail_starling_key`, else the numeric fail code.
+        fail_code: Option<String>,
+        fail_msg: Option<String>,
+    },
+    #[error(transparent)]
+    Schema(#[from] SchemaError),
+    #[error("{} transport failed: {message}", transport.as_str())]
+    Transport {
+        transport: TransportKind,
+        message: String,
+        timeout: bool,
+    },
+    /// Error reported by ImageX or VOD.
+    #[error("{action} failed: {message}")]
+    Storage {
+        action: &'static str,
+        code: Option<String>,
+        message: String,
+    },
+    #[error("Polling timed out after {secs}s")]
+    PollTimeout { secs: u64 },
+    /// Rejected before reaching jimeng.
+    #[error("{0}")]
+    InvalidInput(String),
+}
+
+impl JimengError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Http { status, .. } => match status {
+                401 => ErrorCode::Auth,
+                403 => ErrorCode::RiskControl,
+                408 | 504 => ErrorCode::Timeout,
+                500.. => ErrorCode::UpstreamError,
+                _ => ErrorCode::Unknown,
+            },
+            Self::Api { ret, errmsg, .. } => lookup_ret(ret)
+                .or_else(|| lookup_message(errmsg))
+                .unwrap_or(ErrorCode::Unknown),
+            Self::Generation { fail_code, fail_msg } => fail_code.as_deref()
+                .and_then(|code| lookup_ret(code).or_else(|| lookup_fail_key(code)))
+                .or_else(|| fail_msg.as_deref().and_then(lookup_message))
+                .unwrap_or(ErrorCode::GenerationFailed),
+            Self::Schema(_) => ErrorCode::UpstreamSchema,
+            Self::Transport { timeout: true, .. } | Self::PollTimeout { .. } => ErrorCode::Timeout,
+            Self::Transport { .. } => ErrorCode::Network,
+            Self::Storage { .. } => ErrorCode::UploadFailed,
+            Self::InvalidInput(_) => ErrorCode::InvalidInput,
+        }
+    }
+
+    /// Whether this is jimeng's per-account daily usage limit, the one quota
+    /// error that clears by itself at the daily reset (unlike running out
+    /// of credits).
+    pub fn is_daily_limit(&self) -> bool {
+        match self {
+            Self::Api { errmsg, .. } => errmsg.contains(DAILY_LIMIT_MESSAGE),
+            Self::Generation { fail_code, fail_msg } => {
+                fail_code.as_deref().is_some_and(|code| code.to_lowercase().contains(DAILY_LIMIT_KEY))
+                    || fail_msg.as_deref().is_some_and(|msg| msg.contains(DAILY_LIMIT_MESSAGE))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether jimeng rejected a submit for one of its materials, so fresh
+    /// uploads may succeed where cached ones failed.
+    pub fn rejects_material(&self) -> bool {
+        let Self::Api { errmsg, .. } = self else { return false };
+        let errmsg = errmsg.to_lowercase();
+        MATERIAL_MESSAGES.iter().any(|phrase| errmsg.contains(phrase))
+    }
+
+    /// Attach the transport that got the response, where it applies.
+    pub fn via(mut self, kind: Option<TransportKind>) -> Self {
+        if let Self::Http { transport, .. } | Self::Api { transport, .. } = &mut self {
+            *transport = kind;
+        }
+        self
+    }
+}
+
+/// Public code of any task error: that of the `JimengError` it carries,
+/// else `unknown`.
+pub fn error_code(e: &anyhow::Error) -> ErrorCode {
+    e.downcast_ref::<JimengError>().map_or(ErrorCode::Unknown, JimengError::code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        let api = |ret: &str, errmsg: &str| JimengError::Api {
+            what: "Submit", ret: ret.into(), errmsg: errmsg.into(), transport: None,
+        };
+        assert_eq!(api("1015", "login error").code(), ErrorCode::Auth);
+        assert_eq!(api("4013", "").code(), ErrorCode::RiskControl);
+        assert_eq!(api("1234", "积分不足").code(), ErrorCode::Quota);
+        // "token" in a message no longer means auth.
+        assert_eq!(api("1000", "invalid token count").code(), ErrorCode::Unknown);
+
+        let failed = |code: Option<&str>, msg: Option<&str>| JimengError::Generation {
+            fail_code: code.map(str::to_string), fail_msg: msg.map(str::to_string),
+        };
+        assert_eq!(failed(Some("web_Fail2Generate_InputTextRisk"), None).code(), ErrorCode::ContentRisk);
+        assert_eq!(failed(Some("2038"), None).code(), ErrorCode::ContentRisk);
+        assert_eq!(failed(None, Some("已达每日使用上限")).code(), ErrorCode::Quota);
+        assert_eq!(failed(Some("1999"), Some("prompt mentions 2038")).code(), ErrorCode::GenerationFailed);
+
+        assert!(api("1000", "Invalid URI: tos-cn-i-abc").rejects_material());
+        assert!(api("1000", "参考素材已失效").rejects_material());
+        // Material trouble that fresh uploads of the same bytes won't fix.
+        assert!(!api("1000", "素材审核未通过").rejects_material());
+        assert!(!api("1000", "积分不足").rejects_material());
+
+        assert!(failed(Some("Daily_Usage_Limit"), None).is_daily_limit());
+        assert!(failed(None, Some("已达每日使用上限")).is_daily_limit());
+        assert!(api("1234", "今日已达每日使用上限").is_daily_limit());
+        // Out of credits is a quota error too, but does not reset daily.
+        assert!(!api("5000", "").is_daily_limit());
+        assert!(!api("1234", "积分不足").is_daily_limit());
+
+        let http = JimengError::Http { what: "Poll", status: 502, body: String::new(), transport: None };
+        assert_eq!(http.code(), ErrorCode::UpstreamError);
+
+        let wrapped = anyhow::Error::new(JimengError::PollTimeout { secs: 60 }).context("task failed");
+        assert_eq!(error_code(&wrapped), ErrorCode::Timeout);
+        assert_eq!(error_code(&anyhow::anyhow!("Task cancelled")), ErrorCode::Unknown);
+    }
+}
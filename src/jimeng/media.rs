@@ -0,0 +1,640 @@
+//! Media probing for uploaded materials.
+//!
+//! Reads just enough of a file's headers to learn what it is and how big or
+//! long it is: PNG, JPEG and WebP dimensions, the `moov` atom of MP4/MOV
+//! (duration, fps, dimensions; audio-only files count as audio), and MP3,
+//! AAC (ADTS) and WAV durations. The type comes from the bytes, not the
+//! client's MIME type, so anything else is rejected before upload.
+
+use std::io::SeekFrom;
+use std::path::Path;
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::models::MaterialType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaFormat {
+    Png,
+    Jpeg,
+    WebP,
+    Mp4,
+    Mov,
+    Mp3,
+    Aac,
+    Wav,
+}
+
+impl MediaFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpeg",
+            Self::WebP => "webp",
+            Self::Mp4 => "mp4",
+            Self::Mov => "mov",
+            Self::Mp3 => "mp3",
+            Self::Aac => "aac",
+            Self::Wav => "wav",
+        }
+    }
+}
+
+/// What a material file turned out to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaInfo {
+    pub format: MediaFormat,
+    pub material_type: MaterialType,
+    /// Pixels; 0 for audio.
+    pub width: u32,
+    pub height: u32,
+    /// Milliseconds; 0 for images.
+    pub duration_ms: u32,
+    /// Frames per second, rounded; 0 unless video.
+    pub fps: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ProbeError {
+    #[error("unsupported file type (expected PNG, JPEG, WebP, MP4, MOV, MP3, AAC or WAV)")]
+    Unsupported,
+    #[error("invalid {}: {message}", format.as_str())]
+    Malformed { format: MediaFormat, message: &'static str },
+    #[error("cannot read file: {0}")]
+    Io(String),
+}
+
+fn malformed(format: MediaFormat, message: &'static str) -> ProbeError {
+    ProbeError::Malformed { format, message }
+}
+
+/// Identify `data` and read its dimensions, duration and frame rate.
+pub fn probe(data: &[u8]) -> Result<MediaInfo, ProbeError> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        probe_png(data)
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        probe_jpeg(data)
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        probe_webp(data)
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE" {
+        probe_wav(data)
+    } else if is_iso_media(data) {
+        probe_iso_media(data)
+    } else {
+        probe_mpeg_audio(data)
+    }
+}
+
+/// `probe` a file on disk. MP4/MOV files are only read as far as their
+/// `ftyp` and `moov` boxes, so large clips are not loaded whole.
+pub async fn probe_file(path: &Path) -> Result<MediaInfo, ProbeError> {
+    let io = |e: std::io::Error| ProbeError::Io(e.to_string());
+    let mut file = tokio::fs::File::open(path).await.map_err(io)?;
+    let len = file.metadata().await.map_err(io)?.len();
+    let mut head = [0u8; 8];
+    if len < 8 || file.read_exact(&mut head).await.is_err() || !is_iso_media(&head) {
+        return probe(&tokio::fs::read(path).await.map_err(io)?);
+    }
+
+    // Collect the boxes `probe` needs, re-framed with 32-bit sizes.
+    let mut boxes = Vec::new();
+    let mut offset = 0u64;
+    while offset + 8 <= len {
+        file.seek(SeekFrom::Start(offset)).await.map_err(io)?;
+        file.read_exact(&mut head).await.map_err(io)?;
+        let kind: [u8; 4] = head[4..8].try_into().unwrap();
+        let (header, size) = match be32(&head, 0).unwrap() {
+            0 => (8, len - offset),
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large).await.map_err(io)?;
+                (16, u64::from_be_bytes(large))
+            }
+            size => (8, size as u64),
+        };
+        let Some(end) = offset.checked_add(size).filter(|end| size >= header && *end <= len) else {
+            break;
+        };
+        if matches!(&kind, b"ftyp" | b"moov") {
+            let payload_len = u32::try_from(size - header)
+                .ok()
+                .filter(|n| *n <= MAX_MOOV_BYTES)
+                .ok_or(malformed(MediaFormat::Mp4, "moov atom too large"))?;
+            let mut payload = vec![0u8; payload_len as usize];
+            file.seek(SeekFrom::Start(offset + header)).await.map_err(io)?;
+            file.read_exact(&mut payload).await.map_err(io)?;
+            boxes.extend_from_slice(&(payload_len + 8).to_be_bytes());
+            boxes.extend_from_slice(&kind);
+            boxes.extend_from_slice(&payload);
+        }
+        offset = end;
+    }
+    if boxes.is_empty() {
+        return Err(malformed(MediaFormat::Mp4, "no moov atom"));
+    }
+    probe(&boxes)
+}
+
+/// Largest `moov` box `probe_file` reads; real ones are a few MB at most.
+const MAX_MOOV_BYTES: u32 = 64 * 1024 * 1024;
+
+fn is_iso_media(data: &[u8]) -> bool {
+    data.len() >= 8 && matches!(&data[4..8], b"ftyp" | b"moov" | b"mdat" | b"wide" | b"free")
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be64(data: &[u8], at: usize) -> Option<u64> {
+    data.get(at..at + 8).map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+}
+
+fn le16(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32)
+}
+
+fn le24(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 3).map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]))
+}
+
+fn le32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn image(format: MediaFormat, width: u32, height: u32) -> Result<MediaInfo, ProbeError> {
+    if width == 0 || height == 0 {
+        return Err(malformed(format, "zero image dimensions"));
+    }
+    Ok(MediaInfo { format, material_type: MaterialType::Image, width, height, duration_ms: 0, fps: 0 })
+}
+
+fn probe_png(data: &[u8]) -> Result<MediaInfo, ProbeError> {
+    let format = MediaFormat::Png;
+    if data.get(12..16) != Some(b"IHDR") {
+        return Err(malformed(format, "missing IHDR chunk"));
+    }
+    let (Some(width), Some(height)) = (be32(data, 16), be32(data, 20)) else {
+        return Err(malformed(format, "truncated IHDR chunk"));
+    };
+    image(format, width, height)
+}
+
+fn probe_jpeg(data: &[u8]) -> Result<MediaInfo, ProbeError> {
+    let format = MediaFormat::Jpeg;
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xFF {
+            return Err(malformed(format, "bad marker"));
+        }
+        let marker = data[i + 1];
+        // Fill bytes and markers without a length.
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+        if matches!(marker, 0x01 | 0xD0..=0xD9) {
+            i += 2;
+            continue;
+        }
+        let len = be16(data, i + 2).ok_or(malformed(format, "truncated segment"))? as usize;
+        // SOFn frames, except DHT (C4), JPG (C8) and DAC (CC).
+        if matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let (Some(height), Some(width)) = (be16(data, i + 5), be16(data, i + 7)) else {
+                return Err(malformed(format, "truncated frame header"));
+            };
+            return image(format, width, height);
+        }
+        if marker == 0xDA {
+            break;
+        }
+        i += 2 + len;
+    }
+    Err(malformed(format, "no frame header"))
+}
+
+fn probe_webp(data: &[u8]) -> Result<MediaInfo, ProbeError> {
+    let format = MediaFormat::WebP;
+    let dims = match data.get(12..16) {
+        Some(b"VP8 ") => {
+            if data.get(23..26) != Some(&[0x9D, 0x01, 0x2A]) {
+                return Err(malformed(format, "bad VP8 start code"));
+            }
+            le16(data, 26).zip(le16(data, 28)).map(|(w, h)| (w & 0x3FFF, h & 0x3FFF))
+        }
+        Some(b"VP8L") => {
+            if data.get(20) != Some(&0x2F) {
+                return Err(malformed(format, "bad VP8L signature"));
+            }
+            le32(data, 21).map(|bits| ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        Some(b"VP8X") => le24(data, 24).zip(le24(data, 27)).map(|(w, h)| (w + 1, h + 1)),
+        _ => return Err(malformed(format, "unknown WebP chunk")),
+    };
+    let (width, height) = dims.ok_or(malformed(format, "truncated header"))?;
+    image(format, width, height)
+}
+
+fn probe_wav(data: &[u8]) -> Result<MediaInfo, ProbeError> {
+    let format = MediaFormat::Wav;
+    let mut byte_rate = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let size = le32(data, offset + 4).unwrap() as usize;
+        match id {
+            b"fmt " => byte_rate = le32(data, offset + 16),
+            b"data" => {
+                let byte_rate = byte_rate.filter(|r| *r > 0).ok_or(malformed(format, "missing fmt chunk"))?;
+                // Truncated files report more data than they hold.
+                let size = size.min(data.len() - offset - 8);
+                let duration_ms = (size as u64 * 1000 / byte_rate as u64) as u32;
+                return Ok(audio(format, duration_ms));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size.
+        offset += 8 + size + (size & 1);
+    }
+    Err(malformed(format, "missing data chunk"))
+}
+
+fn audio(format: MediaFormat, duration_ms: u32) -> MediaInfo {
+    MediaInfo { format, material_type: MaterialType::Audio, width: 0, height: 0, duration_ms, fps: 0 }
+}
+
+/// An ISO BMFF (MP4/MOV) box: its type and payload.
+type Mp4Box<'a> = ([u8; 4], &'a [u8]);
+
+/// Child boxes of a box payload.
+fn boxes(data: &[u8]) -> Result<Vec<Mp4Box<'_>>, &'static str> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i + 8 <= data.len() {
+        let size = be32(data, i).unwrap() as u64;
+        let kind: [u8; 4] = data[i + 4..i + 8].try_into().unwrap();
+        let (header, size) = match size {
+            0 => (8, (data.len() - i) as u64),
+            1 => (16, be64(data, i + 8).ok_or("truncated box header")?),
+            _ => (8, size),
+        };
+        if size < header as u64 || size > (data.len() - i) as u64 {
+            // `mdat` is often cut short by partial uploads; nothing after
+            // it matters once `moov` has been seen.
+            if &kind == b"mdat" {
+                break;
+            }
+            return Err("box overruns its parent");
+        }
+        out.push((kind, &data[i + header..i + size as usize]));
+        i += size as usize;
+    }
+    Ok(out)
+}
+
+fn child<'a>(children: &[Mp4Box<'a>], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children.iter().find(|(k, _)| k == kind).map(|(_, payload)| *payload)
+}
+
+/// Timescale and duration of an `mvhd` or `mdhd` payload.
+fn media_header(payload: &[u8]) -> Option<(u32, u64)> {
+    match payload.first()? {
+        1 => Some((be32(payload, 20)?, be64(payload, 24)?)),
+        _ => Some((be32(payload, 12)?, be32(payload, 16)? as u64)),
+    }
+}
+
+struct Track {
+    handler: [u8; 4],
+    width: u32,
+    height: u32,
+    duration_ms: u32,
+    fps: u32,
+}
+
+/// `duration` in `timescale` units as milliseconds, saturating.
+fn to_ms(duration: u64, timescale: u32) -> u32 {
+    if timescale == 0 {
+        return 0;
+    }
+    u32::try_from(duration.saturating_mul(1000) / timescale as u64).unwrap_or(u32::MAX)
+}
+
+fn read_track(trak: &[u8]) -> Result<Track, &'static str> {
+    let children = boxes(trak)?;
+    let tkhd = child(&children, b"tkhd").ok_or("track without tkhd")?;
+    // Width and height are 16.16 fixed point at the end of tkhd.
+    let dims_at = if tkhd.first() == Some(&1) { 88 } else { 76 };
+    let width = be32(tkhd, dims_at).unwrap_or(0) >> 16;
+    let height = be32(tkhd, dims_at + 4).unwrap_or(0) >> 16;
+
+    let mdia = boxes(child(&children, b"mdia").ok_or("track without mdia")?)?;
+    let handler: [u8; 4] = child(&mdia, b"hdlr")
+        .and_then(|hdlr| hdlr.get(8..12))
+        .and_then(|h| h.try_into().ok())
+        .unwrap_or(*b"    ");
+    let (timescale, duration) = child(&mdia, b"mdhd").and_then(media_header).ok_or("track without mdhd")?;
+    let duration_ms = to_ms(duration, timescale);
+
+    let samples = child(&mdia, b"minf")
+        .and_then(|minf| boxes(minf).ok())
+        .and_then(|minf| child(&minf, b"stbl").and_then(|stbl| boxes(stbl).ok()))
+        .and_then(|stbl| child(&stbl, b"stts"))
+        .map_or(0, |stts| {
+            // The entry count is untrusted; only read entries that are there.
+            let entries = (be32(stts, 4).unwrap_or(0) as usize).min(stts.len().saturating_sub(8) / 8);
+            (0..entries).filter_map(|e| be32(stts, 8 + e * 8)).map(u64::from).sum::<u64>()
+        });
+    let fps = if duration > 0 && timescale > 0 {
+        (samples.saturating_mul(timescale as u64) as f64 / duration as f64).round() as u32
+    } else {
+        0
+    };
+
+    Ok(Track { handler, width, height, duration_ms, fps })
+}
+
+fn probe_iso_media(data: &[u8]) -> Result<MediaInfo, ProbeError> {
+    let top = boxes(data).map_err(|m| malformed(MediaFormat::Mp4, m))?;
+    let format = match child(&top, b"ftyp").and_then(|ftyp| ftyp.get(0..4)) {
+        Some(b"qt  ") | None => MediaFormat::Mov,
+        Some(_) => MediaFormat::Mp4,
+    };
+    let moov = child(&top, b"moov").ok_or(malformed(format, "no moov atom"))?;
+    let moov = boxes(moov).map_err(|m| malformed(format, m))?;
+
+    let tracks = moov.iter()
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, trak)| read_track(trak))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|m| malformed(format, m))?;
+    let movie_ms = child(&moov, b"mvhd")
+        .and_then(media_header)
+        .filter(|(timescale, _)| *timescale > 0)
+        .map(|(timescale, duration)| to_ms(duration, timescale));
+
+    if let Some(video) = tracks.iter().find(|t| &t.handler == b"vide") {
+        return Ok(MediaInfo {
+            format,
+            material_type: MaterialType::Video,
+            width: video.width,
+            height: video.height,
+            duration_ms: movie_ms.filter(|ms| *ms > 0).unwrap_or(video.duration_ms),
+            fps: video.fps,
+        });
+    }
+    if let Some(sound) = tracks.iter().find(|t| &t.handler == b"soun") {
+        return Ok(audio(format, movie_ms.filter(|ms| *ms > 0).unwrap_or(sound.duration_ms)));
+    }
+    Err(malformed(format, "no audio or video track"))
+}
+
+/// Length of a leading ID3v2 tag.
+fn id3_len(data: &[u8]) -> usize {
+    if !data.starts_with(b"ID3") || data.len() < 10 {
+        return 0;
+    }
+    // Syncsafe integer: 7 bits per byte.
+    let size = data[6..10].iter().fold(0usize, |acc, b| (acc << 7) | (*b & 0x7F) as usize);
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+/// Bytes and samples of the MPEG audio (MP3) frame whose header is `h`.
+fn mp3_frame(h: u32) -> Option<(usize, u32, u32)> {
+    const BITRATES: [[u32; 14]; 5] = [
+        [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448], // V1 L1
+        [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],    // V1 L2
+        [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],     // V1 L3
+        [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],    // V2 L1
+        [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],         // V2 L2/L3
+    ];
+    if h >> 21 != 0x7FF {
+        return None;
+    }
+    let version = (h >> 19) & 3; // 0: 2.5, 2: 2, 3: 1
+    let layer = (h >> 17) & 3; // 1: III, 2: II, 3: I
+    let bitrate_idx = ((h >> 12) & 0xF) as usize;
+    let rate_idx = ((h >> 10) & 3) as usize;
+    if version == 1 || layer == 0 || bitrate_idx == 0 || bitrate_idx == 15 || rate_idx == 3 {
+        return None;
+    }
+    let v1 = version == 3;
+    let table = match (v1, layer) {
+        (true, 3) => 0,
+        (true, 2) => 1,
+        (true, _) => 2,
+        (false, 3) => 3,
+        (false, _) => 4,
+    };
+    let bitrate = BITRATES[table][bitrate_idx - 1] * 1000;
+    let sample_rate = [44100, 48000, 32000][rate_idx] >> match version { 3 => 0, 2 => 1, _ => 2 };
+    let padding = (h >> 9) & 1;
+    let samples = match layer {
+        3 => 384,
+        2 => 1152,
+        _ if v1 => 1152,
+        _ => 576,
+    };
+    let len = if layer == 3 {
+        (12 * bitrate / sample_rate + padding) * 4
+    } else {
+        samples / 8 * bitrate / sample_rate + padding
+    };
+    Some((len as usize, samples, sample_rate))
+}
+
+/// Bytes and samples of the ADTS (AAC) frame starting at `f`.
+fn adts_frame(f: &[u8]) -> Option<(usize, u32, u32)> {
+    const RATES: [u32; 13] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+    if f.len() < 7 || f[0] != 0xFF || f[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+    let sample_rate = *RATES.get(((f[2] >> 2) & 0xF) as usize)?;
+    let len = (((f[3] & 0x03) as usize) << 11) | ((f[4] as usize) << 3) | ((f[5] >> 5) as usize);
+    let blocks = (f[6] & 0x03) as u32 + 1;
+    (len >= 7).then_some((len, 1024 * blocks, sample_rate))
+}
+
+fn probe_mpeg_audio(data: &[u8]) -> Result<MediaInfo, ProbeError> {
+    let start = id3_len(data);
+    let first = data.get(start..start + 4).ok_or(ProbeError::Unsupported)?;
+    let format = if adts_frame(&data[start..]).is_some() {
+        MediaFormat::Aac
+    } else if mp3_frame(be32(first, 0).unwrap()).is_some() {
+        MediaFormat::Mp3
+    } else {
+        return Err(ProbeError::Unsupported);
+    };
+
+    // Walk the frames; stop at the first thing that isn't one (e.g. a
+    // trailing ID3v1 tag).
+    let mut offset = start;
+    let mut frames = 0u32;
+    let mut seconds = 0f64;
+    while offset < data.len() {
+        let frame = match format {
+            MediaFormat::Aac => adts_frame(&data[offset..]),
+            _ => be32(data, offset).and_then(mp3_frame),
+        };
+        let Some((len, samples, sample_rate)) = frame.filter(|(len, _, _)| *len > 0) else { break };
+        if offset + len > data.len() && frames > 0 {
+            break;
+        }
+        seconds += samples as f64 / sample_rate as f64;
+        frames += 1;
+        offset += len;
+    }
+    if frames < 2 {
+        return Err(malformed(format, "too few audio frames"));
+    }
+    Ok(audio(format, (seconds * 1000.0).round() as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[test]
+    fn test_probe_images() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 4, 0, 0, 0, 2, 0x40]);
+        let info = probe(&png).unwrap();
+        assert_eq!((info.format, info.width, info.height), (MediaFormat::Png, 1024, 576));
+
+        // SOI, APP0 (length 4), SOF0 with 8-bit precision, 300 high, 200 wide.
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0, 4, 0, 0, 0xFF, 0xC0, 0, 11, 8, 0x01, 0x2C, 0, 0xC8, 3];
+        let info = probe(&jpeg).unwrap();
+        assert_eq!((info.format, info.width, info.height), (MediaFormat::Jpeg, 200, 300));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0\0\0\0\0".to_vec();
+        webp.extend_from_slice(&[0xFF, 0x07, 0, 0x37, 0x04, 0]); // 2048 x 1080
+        let info = probe(&webp).unwrap();
+        assert_eq!((info.format, info.width, info.height), (MediaFormat::WebP, 2048, 1080));
+
+        assert_eq!(probe(b"GIF89a...."), Err(ProbeError::Unsupported));
+        assert!(matches!(probe(&png[..18]), Err(ProbeError::Malformed { .. })));
+    }
+
+    fn mp4_file() -> Vec<u8> {
+        let mut mvhd = vec![0u8; 20];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&5000u32.to_be_bytes());
+
+        let mut tkhd = vec![0u8; 84];
+        tkhd[76..80].copy_from_slice(&(1280u32 << 16).to_be_bytes());
+        tkhd[80..84].copy_from_slice(&(720u32 << 16).to_be_bytes());
+        let mut mdhd = vec![0u8; 20];
+        mdhd[12..16].copy_from_slice(&12800u32.to_be_bytes());
+        mdhd[16..20].copy_from_slice(&64000u32.to_be_bytes());
+        let mut hdlr = vec![0u8; 12];
+        hdlr[8..12].copy_from_slice(b"vide");
+        // 125 frames over 5s.
+        let stts = [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 125, 0, 0, 2, 0];
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stts", &stts));
+        let minf = mp4_box(b"minf", &stbl);
+        let mdia = mp4_box(b"mdia", &[mp4_box(b"mdhd", &mdhd), mp4_box(b"hdlr", &hdlr), minf].concat());
+        let trak = mp4_box(b"trak", &[mp4_box(b"tkhd", &tkhd), mdia].concat());
+        let moov = mp4_box(b"moov", &[mp4_box(b"mvhd", &mvhd), trak].concat());
+        // `moov` after a large `mdat`, as cameras write it.
+        [mp4_box(b"ftyp", b"isom\0\0\0\0"), mp4_box(b"mdat", &[0; 4096]), moov].concat()
+    }
+
+    #[test]
+    fn test_probe_mp4() {
+        let info = probe(&mp4_file()).unwrap();
+        assert_eq!(info.format, MediaFormat::Mp4);
+        assert_eq!(info.material_type, MaterialType::Video);
+        assert_eq!((info.width, info.height, info.duration_ms, info.fps), (1280, 720, 5000, 25));
+
+        // Claims 2^32 - 1 stts entries and the largest durations, in a few bytes.
+        let mut mdhd = vec![0u8; 20];
+        mdhd[12..16].copy_from_slice(&1u32.to_be_bytes());
+        mdhd[16..20].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut hdlr = vec![0u8; 12];
+        hdlr[8..12].copy_from_slice(b"vide");
+        let stts = [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 1];
+        let minf = mp4_box(b"minf", &mp4_box(b"stbl", &mp4_box(b"stts", &stts)));
+        let mdia = mp4_box(b"mdia", &[mp4_box(b"mdhd", &mdhd), mp4_box(b"hdlr", &hdlr), minf].concat());
+        let trak = mp4_box(b"trak", &[mp4_box(b"tkhd", &[0u8; 84]), mdia].concat());
+        let moov = mp4_box(b"moov", &trak.repeat(64));
+        let info = probe(&[mp4_box(b"ftyp", b"isom\0\0\0\0"), moov].concat()).unwrap();
+        assert_eq!((info.duration_ms, info.fps), (u32::MAX, 1));
+
+        // A child box whose 64-bit size would overflow the offset.
+        let mut oversized = mp4_box(b"free", &[]);
+        oversized.extend_from_slice(&[0, 0, 0, 1]);
+        oversized.extend_from_slice(b"trak");
+        oversized.extend_from_slice(&(u64::MAX - 7).to_be_bytes());
+        let crafted = [mp4_box(b"ftyp", b"isom\0\0\0\0"), mp4_box(b"moov", &oversized)].concat();
+        assert!(probe(&crafted).is_err());
+
+        let no_moov = [mp4_box(b"ftyp", b"qt  \0\0\0\0"), mp4_box(b"mdat", &[0; 16])].concat();
+        assert_eq!(probe(&no_moov), Err(malformed(MediaFormat::Mov, "no moov atom")));
+    }
+
+    #[tokio::test]
+    async fn test_probe_file() {
+        let path = std::env::temp_dir().join(format!("probe-{}.mp4", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, mp4_file()).await.unwrap();
+        let info = probe_file(&path).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(info.unwrap(), probe(&mp4_file()).unwrap());
+
+        // A 64-bit box size that would overflow the file offset.
+        let mut crafted = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        crafted.extend_from_slice(&[0, 0, 0, 1]);
+        crafted.extend_from_slice(b"mdat");
+        crafted.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        tokio::fs::write(&path, &crafted).await.unwrap();
+        let info = probe_file(&path).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(info.is_err());
+    }
+
+    #[test]
+    fn test_probe_audio() {
+        // MPEG1 layer III, 128 kbps, 44.1 kHz: 417-byte frames of 1152 samples.
+        let frame = {
+            let mut f = vec![0u8; 417];
+            f[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+            f
+        };
+        let mut mp3 = b"ID3\x04\0\0\0\0\0\x02\0\0".to_vec();
+        for _ in 0..100 {
+            mp3.extend_from_slice(&frame);
+        }
+        mp3.extend_from_slice(b"TAG");
+        let info = probe(&mp3).unwrap();
+        assert_eq!((info.format, info.material_type), (MediaFormat::Mp3, MaterialType::Audio));
+        assert_eq!(info.duration_ms, 2612); // 100 * 1152 / 44100
+
+        // ADTS, 44.1 kHz, 200-byte frames of 1024 samples.
+        let mut aac = Vec::new();
+        for _ in 0..43 {
+            let mut f = vec![0u8; 200];
+            f[..7].copy_from_slice(&[0xFF, 0xF1, 0x50, 0x80, (200 >> 3) as u8, ((200 & 7) << 5) as u8, 0xFC]);
+            aac.extend_from_slice(&f);
+        }
+        let info = probe(&aac).unwrap();
+        assert_eq!((info.format, info.duration_ms), (MediaFormat::Aac, 998));
+
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        wav.extend_from_slice(&[1, 0, 1, 0, 0x44, 0xAC, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&vec![0; 44100]);
+        assert_eq!(probe(&wav).unwrap().duration_ms, 500);
+    }
+}
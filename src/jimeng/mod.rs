@@ -4,8 +4,18 @@
 pub mod abogus;
 pub mod auth;
 pub mod browser;
+pub mod client;
+pub mod client_profile;
+pub mod cookies;
 pub mod curl_transport;
+pub mod error;
+pub mod media;
+pub mod model_registry;
 pub mod models;
 pub mod poll;
+pub mod profile;
+pub mod proxy;
+pub mod responses;
 pub mod submit;
+pub mod transport;
 pub mod upload;
@@ -0,0 +1,376 @@
+//! Model registry: the public models the gateway offers and how each maps
+//! onto jimeng.
+//!
+//! Every model is declared once, with its jimeng key, benefit types, allowed
+//! durations, ratios and resolutions, material limits, credit cost and the
+//! session capabilities it needs. Task validation, session routing, submits,
+//! history import and `/v1/models` all read it from here.
+//!
+//! The built-in registry is `models.json` next to this file. `MODELS_FILE`
+//! replaces it with a file in the same format, which can be reloaded at
+//! runtime. Invalid files are rejected as a whole.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result, bail, ensure};
+use serde::{Deserialize, Serialize};
+
+use super::media::MediaInfo;
+use super::models::{self, MaterialType};
+
+const BUILTIN_MODELS: &str = include_str!("models.json");
+/// Registry file format this gateway reads.
+const FILE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelKind {
+    Video,
+    Image,
+}
+
+/// Reference files a single task may carry, per type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialLimits {
+    pub images: u32,
+    pub videos: u32,
+    pub audios: u32,
+    /// Longest reference video or audio, in seconds.
+    pub max_secs: Option<u32>,
+}
+
+impl MaterialLimits {
+    pub fn get(&self, material_type: MaterialType) -> u32 {
+        match material_type {
+            MaterialType::Image => self.images,
+            MaterialType::Video => self.videos,
+            MaterialType::Audio => self.audios,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelSpec {
+    /// Public model name.
+    pub id: String,
+    /// Other names accepted for the model (older or upstream names).
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub kind: ModelKind,
+    /// jimeng's model key (`model_req_key`).
+    pub internal_key: String,
+    /// `benefit_type` of video submits.
+    #[serde(default)]
+    pub benefit_type: Option<String>,
+    /// `benefit_type` when a reference video is attached;
+    /// `{benefit_type}_with_video` if unset.
+    #[serde(default)]
+    pub video_benefit_type: Option<String>,
+    /// Allowed durations in seconds (videos only).
+    #[serde(default)]
+    pub durations: Vec<u32>,
+    #[serde(default)]
+    pub default_duration: Option<u32>,
+    pub ratios: Vec<String>,
+    pub default_ratio: String,
+    pub resolutions: Vec<String>,
+    pub default_resolution: String,
+    #[serde(default)]
+    pub materials: MaterialLimits,
+    /// Credits one generation costs at the default duration and resolution,
+    /// as shown in `/v1/models`.
+    #[serde(default)]
+    pub credits: Option<u32>,
+    /// Session capability tags the model needs. Paid model keys are
+    /// rejected with 4013 on free accounts.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Extra capability tags per resolution (e.g. 4k images).
+    #[serde(default)]
+    pub resolution_requires: BTreeMap<String, Vec<String>>,
+}
+
+impl ModelSpec {
+    pub fn is_image(&self) -> bool {
+        self.kind == ModelKind::Image
+    }
+
+    /// `benefit_type` of a video submit, with or without a reference video.
+    pub fn benefit_type(&self, with_video: bool) -> String {
+        let base = self.benefit_type.clone().unwrap_or_default();
+        match (&self.video_benefit_type, with_video) {
+            (Some(video), true) => video.clone(),
+            (None, true) => format!("{base}_with_video"),
+            (_, false) => base,
+        }
+    }
+
+    /// Session capability tags a task at `resolution` needs.
+    pub fn required_capabilities(&self, resolution: Option<&str>) -> Vec<&str> {
+        let extra = resolution.and_then(|r| self.resolution_requires.get(r));
+        self.requires.iter().chain(extra.into_iter().flatten()).map(String::as_str).collect()
+    }
+
+    /// Check a task's parameters, filling in defaults. `materials` counts
+    /// the reference files per type.
+    pub fn apply(
+        &self,
+        duration: &mut Option<i32>,
+        ratio: &mut Option<String>,
+        resolution: &mut Option<String>,
+        materials: &[MaterialType],
+    ) -> Result<(), String> {
+        let ratio = ratio.get_or_insert_with(|| self.default_ratio.clone());
+        if !self.ratios.contains(ratio) {
+            return Err(format!("{} does not support ratio {ratio} (allowed: {})", self.id, self.ratios.join(", ")));
+        }
+        let resolution = resolution.get_or_insert_with(|| self.default_resolution.clone());
+        if !self.resolutions.contains(resolution) {
+            return Err(format!(
+                "{} does not support resolution {resolution} (allowed: {})", self.id, self.resolutions.join(", "),
+            ));
+        }
+        if self.kind == ModelKind::Video {
+            let secs = *duration.get_or_insert(self.default_duration.map_or(4, |d| d as i32));
+            if !self.durations.is_empty() && !u32::try_from(secs).is_ok_and(|d| self.durations.contains(&d)) {
+                let allowed: Vec<String> = self.durations.iter().map(u32::to_string).collect();
+                return Err(format!("{} does not support {secs}s videos (allowed: {})", self.id, allowed.join(", ")));
+            }
+        }
+        for material_type in [MaterialType::Image, MaterialType::Video, MaterialType::Audio] {
+            let count = materials.iter().filter(|t| **t == material_type).count();
+            let limit = self.materials.get(material_type) as usize;
+            if count > limit {
+                return Err(format!(
+                    "{} accepts at most {limit} {} file(s), got {count}", self.id, material_type.as_str(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a probed reference file against the model: its type must be
+    /// one the model takes and video/audio must fit `materials.max_secs`.
+    pub fn check_media(&self, media: &MediaInfo) -> Result<(), String> {
+        let material_type = media.material_type;
+        if self.materials.get(material_type) == 0 {
+            return Err(format!("{} does not accept {} files", self.id, material_type.as_str()));
+        }
+        if let Some(max) = self.materials.max_secs.filter(|_| material_type != MaterialType::Image) {
+            let secs = media.duration_ms as f64 / 1000.0;
+            if secs > max as f64 {
+                return Err(format!(
+                    "{} {} is {secs:.1}s long; {} takes at most {max}s", media.format.as_str(), material_type.as_str(), self.id,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(!self.id.trim().is_empty(), "model id must not be empty");
+        ensure!(!self.internal_key.trim().is_empty(), "internal_key must not be empty");
+        ensure!(self.ratios.contains(&self.default_ratio), "default_ratio {} is not in ratios", self.default_ratio);
+        ensure!(
+            self.resolutions.contains(&self.default_resolution),
+            "default_resolution {} is not in resolutions", self.default_resolution,
+        );
+        for resolution in self.resolution_requires.keys() {
+            ensure!(self.resolutions.contains(resolution), "resolution_requires names unknown resolution {resolution}");
+        }
+        // Every allowed combination must map to pixel dimensions.
+        for resolution in &self.resolutions {
+            for ratio in &self.ratios {
+                let known = match self.kind {
+                    ModelKind::Video => models::resolve_video_resolution(resolution, ratio).is_ok(),
+                    ModelKind::Image => models::resolve_image_resolution(resolution, ratio).is_ok(),
+                };
+                ensure!(known, "unsupported resolution/ratio {resolution}/{ratio}");
+            }
+        }
+        match self.kind {
+            ModelKind::Video => {
+                ensure!(self.benefit_type.as_deref().is_some_and(|b| !b.is_empty()), "video models need a benefit_type");
+                if let Some(d) = self.default_duration {
+                    ensure!(self.durations.is_empty() || self.durations.contains(&d), "default_duration {d} is not in durations");
+                }
+            }
+            ModelKind::Image => {
+                ensure!(self.durations.is_empty() && self.default_duration.is_none(), "image models take no durations");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    version: u32,
+    /// Model of tasks that name none.
+    default: String,
+    models: Vec<ModelSpec>,
+}
+
+#[derive(Debug)]
+struct ModelSet {
+    default: String,
+    /// In file order, which is also the `/v1/models` order.
+    models: Vec<Arc<ModelSpec>>,
+}
+
+impl ModelSet {
+    fn parse(text: &str) -> Result<Self> {
+        let file: RegistryFile = serde_json::from_str(text)?;
+        if file.version != FILE_VERSION {
+            bail!("unsupported model registry version {} (expected {FILE_VERSION})", file.version);
+        }
+        ensure!(!file.models.is_empty(), "no models defined");
+        let mut names = HashSet::new();
+        for spec in &file.models {
+            spec.validate().with_context(|| format!("model {:?}", spec.id))?;
+            for name in std::iter::once(&spec.id).chain(&spec.aliases) {
+                ensure!(names.insert(name.as_str()), "model name {name:?} is defined twice");
+            }
+        }
+        ensure!(file.models.iter().any(|m| m.id == file.default), "default model {:?} is not defined", file.default);
+        Ok(Self { default: file.default, models: file.models.into_iter().map(Arc::new).collect() })
+    }
+}
+
+/// The loaded registry, shared by all requests.
+pub struct ModelRegistry {
+    path: Option<PathBuf>,
+    set: RwLock<ModelSet>,
+}
+
+/// Snapshot of the registry.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelList {
+    pub default: String,
+    pub models: Vec<ModelSpec>,
+}
+
+impl ModelRegistry {
+    /// Load the registry from `path`, or the built-in one without a file.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let set = match &path {
+            Some(path) => read(path)?,
+            None => ModelSet::parse(BUILTIN_MODELS).context("invalid built-in model registry")?,
+        };
+        Ok(Self { path, set: RwLock::new(set) })
+    }
+
+    /// Re-read the registry file. An invalid file is rejected and the
+    /// loaded models stay in use.
+    pub fn reload(&self) -> Result<ModelList> {
+        let Some(path) = &self.path else {
+            bail!("MODELS_FILE is not set");
+        };
+        let set = read(path)?;
+        *self.set.write().unwrap() = set;
+        tracing::info!(path = %path.display(), "Model registry reloaded");
+        Ok(self.list())
+    }
+
+    /// Model by public name or alias.
+    pub fn get(&self, name: &str) -> Option<Arc<ModelSpec>> {
+        self.set.read().unwrap().models.iter()
+            .find(|m| m.id == name || m.aliases.iter().any(|a| a == name))
+            .cloned()
+    }
+
+    /// Model by name, or the default one for `None`.
+    pub fn resolve(&self, name: Option<&str>) -> Result<Arc<ModelSpec>, String> {
+        let name = name.map_or_else(|| self.set.read().unwrap().default.clone(), str::to_string);
+        self.get(&name).ok_or_else(|| format!("unknown model: {name}"))
+    }
+
+    /// Public name of a jimeng model key (history import): the first model
+    /// declared with it.
+    pub fn model_for_key(&self, key: &str) -> Option<String> {
+        self.set.read().unwrap().models.iter()
+            .find(|m| m.internal_key == key)
+            .map(|m| m.id.clone())
+    }
+
+    pub fn list(&self) -> ModelList {
+        let set = self.set.read().unwrap();
+        ModelList {
+            default: set.default.clone(),
+            models: set.models.iter().map(|m| (**m).clone()).collect(),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<ModelSet> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading model registry from {}", path.display()))?;
+    ModelSet::parse(&text).with_context(|| format!("invalid model registry in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jimeng::media::MediaFormat;
+
+    #[test]
+    fn test_builtin_registry() {
+        let registry = ModelRegistry::load(None).unwrap();
+        let pro = registry.get("seedance-2.0").unwrap();
+        assert_eq!(pro.benefit_type(true), "dreamina_video_seedance_20_pro_with_video");
+        assert_eq!(pro.required_capabilities(None), vec!["seedance-pro"]);
+        assert_eq!(registry.get("seedance-1-lite").unwrap().id, "seedance-2.0-lite");
+        assert_eq!(registry.model_for_key("dreamina_seedance_40_pro").as_deref(), Some("seedance-2.0"));
+        assert!(registry.resolve(Some("jimeng-4.0")).is_err());
+        // Untagged sessions run the default model.
+        let default = registry.resolve(None).unwrap();
+        assert_eq!(default.id, "seedance-2.0-lite");
+        assert!(default.required_capabilities(None).is_empty());
+
+        let image = registry.get("jimeng-5.0").unwrap();
+        assert!(image.required_capabilities(Some("2k")).is_empty());
+        assert_eq!(image.required_capabilities(Some("4k")), vec!["image-4k"]);
+
+        // Every built-in model states its cost.
+        assert!(registry.list().models.iter().all(|m| m.credits.is_some()));
+    }
+
+    #[test]
+    fn test_apply_task_parameters() {
+        let registry = ModelRegistry::load(None).unwrap();
+        let video = registry.get("seedance-2.0-fast").unwrap();
+
+        let (mut duration, mut ratio, mut resolution) = (None, None, None);
+        video.apply(&mut duration, &mut ratio, &mut resolution, &[MaterialType::Image]).unwrap();
+        assert_eq!((duration, ratio.as_deref(), resolution.as_deref()), (Some(4), Some("9:16"), Some("720p")));
+
+        assert!(video.apply(&mut Some(30), &mut None, &mut None, &[]).is_err());
+        assert!(video.apply(&mut None, &mut Some("21:9".into()), &mut None, &[]).is_err());
+        assert!(video.apply(&mut None, &mut None, &mut None, &[MaterialType::Video; 4]).is_err());
+
+        let clip = MediaInfo {
+            format: MediaFormat::Mp4, material_type: MaterialType::Video, width: 1280, height: 720, duration_ms: 15_000, fps: 30,
+        };
+        assert!(video.check_media(&clip).is_ok());
+        assert!(video.check_media(&MediaInfo { duration_ms: 15_500, ..clip }).is_err());
+        assert!(registry.get("jimeng-5.0").unwrap().check_media(&clip).is_err());
+    }
+
+    #[test]
+    fn test_reject_invalid_registry() {
+        for bad in [
+            r#"{"version": 1, "default": "x", "models": []}"#,
+            r#"{"version": 1, "default": "x", "models": [{"id": "x", "kind": "image", "internal_key": "k",
+                "ratios": ["1:1"], "default_ratio": "1:1", "resolutions": ["8k"], "default_resolution": "8k"}]}"#,
+            r#"{"version": 1, "default": "x", "models": [{"id": "x", "kind": "video", "internal_key": "k",
+                "ratios": ["1:1"], "default_ratio": "1:1", "resolutions": ["720p"], "default_resolution": "720p"}]}"#,
+        ] {
+            assert!(ModelSet::parse(bad).is_err(), "{bad}");
+        }
+    }
+}
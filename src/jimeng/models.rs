@@ -1,43 +1,8 @@
-//! Model name mappings, resolution tables, and material type definitions.
+//! Resolution tables and material type definitions. Models themselves are
+//! declared in the registry (`model_registry`).
 
 use std::collections::HashMap;
 
-/// Map user-facing model names to internal jimeng model keys.
-pub fn model_map() -> HashMap<&'static str, &'static str> {
-    HashMap::from([
-        ("seedance-2.0", "dreamina_seedance_40_pro"),
-        ("seedance-2.0-pro", "dreamina_seedance_40_pro"),
-        ("seedance-2.0-fast", "dreamina_seedance_40"),
-        ("seedance-2.0-lite", "seedance_2_0_lite"),
-        ("seedance-1-lite", "seedance_2_0_lite"),
-    ])
-}
-
-/// Map model names to their draft content version.
-pub fn draft_version(model: &str) -> &'static str {
-    match model {
-        "seedance-2.0" | "seedance-2.0-pro" | "seedance-2.0-fast" => "3.3.9",
-        "jimeng-5.0" => "3.3.9",
-        _ => "3.3.9",
-    }
-}
-
-/// Map model names to their benefit_type.
-pub fn seedance_benefit_type(model: &str) -> &'static str {
-    match model {
-        "seedance-2.0" | "seedance-2.0-pro" => "dreamina_video_seedance_20_pro",
-        "seedance-2.0-fast" => "dreamina_seedance_20_fast",
-        "seedance-2.0-lite" | "seedance-1-lite" => "seedance_2_0_lite",
-        _ => "dreamina_video_seedance_20_pro",
-    }
-}
-
-/// Resolve user model name to internal model key.
-pub fn resolve_model(model: &str) -> &str {
-    let map = model_map();
-    map.get(model).copied().unwrap_or("dreamina_seedance_40_pro")
-}
-
 /// Video resolution dimensions.
 #[derive(Debug, Clone, Copy)]
 pub struct Resolution {
@@ -75,7 +40,7 @@ pub fn resolve_video_resolution(resolution: &str, ratio: &str) -> Result<Resolut
 }
 
 /// Material type for Seedance multi-modal upload.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MaterialType {
     Image,
     Video,
@@ -102,33 +67,30 @@ impl MaterialType {
     }
 }
 
-/// Detect material type from MIME type string.
-pub fn detect_material_type_from_mime(mime: &str) -> MaterialType {
+/// Detect material type from MIME type string. `None` for anything that is
+/// not an image, video or audio type.
+pub fn detect_material_type_from_mime(mime: &str) -> Option<MaterialType> {
     let mime = mime.to_lowercase();
     if mime.starts_with("image/") {
-        MaterialType::Image
+        Some(MaterialType::Image)
     } else if mime.starts_with("video/") {
-        MaterialType::Video
+        Some(MaterialType::Video)
     } else if mime.starts_with("audio/") {
-        MaterialType::Audio
+        Some(MaterialType::Audio)
     } else {
-        MaterialType::Image // default fallback
+        None
     }
 }
 
 /// Detect material type from file extension.
 #[allow(dead_code)]
-pub fn detect_material_type_from_ext(filename: &str) -> MaterialType {
+pub fn detect_material_type_from_ext(filename: &str) -> Option<MaterialType> {
     let lower = filename.to_lowercase();
-    if let Some(dot_pos) = lower.rfind('.') {
-        match &lower[dot_pos..] {
-            ".jpg" | ".jpeg" | ".png" | ".webp" | ".gif" | ".bmp" => MaterialType::Image,
-            ".mp4" | ".mov" | ".m4v" => MaterialType::Video,
-            ".mp3" | ".wav" => MaterialType::Audio,
-            _ => MaterialType::Image,
-        }
-    } else {
-        MaterialType::Image
+    match &lower[lower.rfind('.')?..] {
+        ".jpg" | ".jpeg" | ".png" | ".webp" => Some(MaterialType::Image),
+        ".mp4" | ".mov" | ".m4v" => Some(MaterialType::Video),
+        ".mp3" | ".aac" | ".m4a" | ".wav" => Some(MaterialType::Audio),
+        _ => None,
     }
 }
 
@@ -147,19 +109,6 @@ pub struct UploadedMaterial {
     pub name: String,
 }
 
-/// Check if a model name is an image generation model.
-pub fn is_image_model(model: &str) -> bool {
-    model.starts_with("jimeng-")
-}
-
-/// Map user-facing image model names to internal jimeng model keys.
-pub fn resolve_image_model(model: &str) -> &str {
-    match model {
-        "jimeng-5.0" => "high_aes_general_v50",
-        _ => "high_aes_general_v50",
-    }
-}
-
 /// Image resolution dimensions and ratio code.
 #[derive(Debug, Clone, Copy)]
 pub struct ImageResolution {
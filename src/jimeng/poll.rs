@@ -1,12 +1,11 @@
 //! Poll video generation status via jimeng.jianying.com API.
 
-use anyhow::{bail, Result};
-use reqwest::Client;
+use anyhow::Result;
 
-use super::auth;
-use super::curl_transport;
-
-const JIMENG_BASE: &str = "https://jimeng.jianying.com";
+use super::client::JimengClient;
+use super::error::JimengError;
+use super::responses::{self, HistoryByIds, HistoryEntry, HistoryPageData, Item, LocalItemList, SchemaError};
+use super::transport::Endpoint;
 
 /// Upstream status codes.
 pub const STATUS_PENDING: i64 = 20;
@@ -27,144 +26,120 @@ pub struct PollResult {
     pub item_id: Option<String>,
 }
 
-/// Poll the status of a video generation task by history_record_id.
-pub async fn poll_status(
-    client: &Client,
-    session_token: &str,
-    history_record_id: &str,
-    cookie_jar: Option<&str>,
-) -> Result<PollResult> {
-    let uri = "/mweb/v1/get_history_by_ids";
-    let headers = auth::build_headers_with_cookies(session_token, uri, cookie_jar);
-    let params = auth::standard_query_params_with_jar(cookie_jar);
-
-    let body = serde_json::json!({
-        "history_ids": [history_record_id],
-    });
-
-    let (status_code, text) = if cookie_jar.is_some() {
-        let query_string = params.iter()
-            .map(|(k, v)| format!("{k}={v}"))
-            .collect::<Vec<_>>()
-            .join("&");
-        let url = format!("{JIMENG_BASE}{uri}?{query_string}");
-        let body_str = body.to_string();
-        curl_transport::post_json_via_curl(&url, &headers, &body_str, 30).await?
-    } else {
-        let resp = client.post(format!("{JIMENG_BASE}{uri}"))
-            .headers(headers)
-            .query(&params)
-            .json(&body)
-            .send().await?;
-        let sc = resp.status().as_u16();
-        let text = resp.text().await?;
-        (sc, text)
-    };
-
-    if status_code >= 400 {
-        bail!("Poll HTTP {status_code}: {}", &text[..text.len().min(500)]);
+impl PollResult {
+    /// The failure, if the generation failed upstream.
+    pub fn failure(&self) -> Option<JimengError> {
+        (self.status == STATUS_FAILED).then(|| JimengError::Generation {
+            fail_code: self.fail_code.clone(),
+            fail_msg: self.fail_msg.clone(),
+        })
     }
+}
 
-    let payload: serde_json::Value = serde_json::from_str(&text)
-        .map_err(|e| anyhow::anyhow!("Poll parse error: {e}. Body: {}", &text[..text.len().min(500)]))?;
-
-    // Extract data (handle {ret: "0", data: {...}} wrapper)
-    let data = if let Some(d) = payload.get("data") { d } else { &payload };
-
-    // Find history data: could be in history_list[0], result[historyId], or data[historyId]
-    let history_data = data.pointer("/history_list/0")
-        .or_else(|| data.get(history_record_id))
-        .or_else(|| payload.get(history_record_id))
-        .or_else(|| data.pointer("/history_records/0"));
-
-    let history_data = match history_data {
-        Some(d) => d,
-        None => bail!("History record not found for {history_record_id}"),
-    };
+/// One generation from a session's upstream history.
+#[derive(Debug, Clone)]
+pub struct HistoryRecord {
+    pub history_record_id: String,
+    pub prompt: String,
+    /// Upstream model key (e.g. `dreamina_seedance_40`), when present.
+    pub model: Option<String>,
+    /// Unix seconds.
+    pub created_time: Option<i64>,
+    pub result: PollResult,
+}
 
-    let status = history_data.get("status")
-        .and_then(|v| v.as_i64())
-        .unwrap_or(STATUS_PENDING);
+/// A page of `get_history`.
+#[derive(Debug, Clone)]
+pub struct HistoryPage {
+    pub records: Vec<HistoryRecord>,
+    pub has_more: bool,
+    /// Pass back to fetch the next page.
+    pub next_cursor: Option<String>,
+}
 
-    let fail_code = history_data.get("fail_starling_key")
-        .and_then(|v| v.as_str().filter(|s| !s.is_empty()).map(|s| s.to_string()))
-        .or_else(|| {
-            history_data.get("fail_code")
-                .or_else(|| history_data.get("error_code"))
-                .and_then(|v| {
-                    v.as_i64().map(|n| n.to_string())
-                        .or_else(|| v.as_str().filter(|s| !s.is_empty()).map(|s| s.to_string()))
-                })
+impl JimengClient {
+    /// Poll the status of a video generation task by history_record_id.
+    pub async fn poll(&mut self, history_record_id: &str) -> Result<PollResult> {
+        let body = serde_json::json!({
+            "history_ids": [history_record_id],
         });
+        let (status_code, text) = self.post_json(Endpoint::Poll, "/mweb/v1/get_history_by_ids", &[], &body, 30).await?;
+        let data: HistoryByIds = self.parse_response("Poll", status_code, &text)?;
+        match data.into_record("Poll", history_record_id)? {
+            Some(record) => Ok(poll_result(&record)),
+            None => Err(JimengError::Schema(SchemaError {
+                what: "Poll",
+                path: format!("data.{history_record_id}"),
+                message: "history record not found".into(),
+            }).into()),
+        }
+    }
 
-    let fail_msg = history_data.get("fail_starling_message")
-        .and_then(|v| v.as_str().filter(|s| !s.is_empty()).map(|s| s.to_string()))
-        .or_else(|| {
-            history_data.get("fail_msg")
-                .or_else(|| history_data.get("error_msg"))
-                .and_then(|v| v.as_str().map(|s| s.to_string()))
+    /// Fetch one page of the account's generation history, newest first.
+    pub async fn fetch_history_page(&mut self, cursor: Option<&str>, count: u32) -> Result<HistoryPage> {
+        let body = serde_json::json!({
+            "cursor": cursor.unwrap_or(""),
+            "count": count,
+            "need_page_item": true,
+            "need_aigc_data": true,
+            "aigc_mode_list": ["workbench"],
         });
 
-    // Extract video URL from item_list
-    let item_list = history_data.get("item_list")
-        .and_then(|v| v.as_array());
+        let (status_code, text) = self.post_json(Endpoint::Poll, "/mweb/v1/get_history", &[], &body, 30).await?;
+        Ok(history_page(self.parse_response("History request", status_code, &text)?))
+    }
 
-    let (video_url, item_id) = if let Some(items) = item_list {
-        let first = items.first();
-        let url = first.and_then(|item| {
-            item.pointer("/video/transcoded_video/origin/video_url")
-                .or_else(|| item.pointer("/video/play_url"))
-                .or_else(|| item.pointer("/video/download_url"))
-                .or_else(|| item.pointer("/video/url"))
-                .and_then(|v| v.as_str())
-                .filter(|s| !s.is_empty())
-                .map(|s| s.to_string())
-        });
-        let id = first.and_then(|item| {
-            item.get("item_id")
-                .or_else(|| item.get("id"))
-                .or_else(|| item.get("local_item_id"))
-                .or_else(|| item.pointer("/common_attr/id"))
-                .and_then(|v| {
-                    v.as_str().map(|s| s.to_string())
-                        .or_else(|| v.as_i64().map(|n| n.to_string()))
-                })
+    /// Try to get high-quality video URL via get_local_item_list API.
+    pub async fn fetch_hq(&mut self, item_id: &str) -> Result<Option<String>> {
+        let body = serde_json::json!({
+            "item_id_list": [item_id],
+            "pack_item_opt": { "scene": 1, "need_data_integrity": true },
+            "is_for_video_download": true,
         });
-        (url, id)
-    } else {
-        (None, None)
-    };
 
-    // Extract image URLs from item_list
-    let image_urls = if let Some(items) = item_list {
-        items.iter().filter_map(|item| {
-            item.pointer("/image/large_images/0/image_url")
-                .and_then(|v| v.as_str())
-                .filter(|s| !s.is_empty())
-                .map(|s| s.to_string())
-                .or_else(|| {
-                    item.pointer("/common_attr/cover_url")
-                        .and_then(|v| v.as_str())
-                        .filter(|s| !s.is_empty())
-                        .map(|s| s.to_string())
-                })
-        }).collect()
-    } else {
-        Vec::new()
-    };
+        let (status_code, text) = self.post_json(Endpoint::Poll, "/mweb/v1/get_local_item_list", &[], &body, 30).await?;
+        let data: LocalItemList = self.parse_response("HQ lookup", status_code, &text)?;
+        Ok(data.first().and_then(Item::download_url).map(str::to_string))
+    }
+}
 
-    // Extract queue info from queue_info object
-    let queue_info = history_data.get("queue_info");
+fn history_page(data: HistoryPageData) -> HistoryPage {
+    let has_more = data.has_more.unwrap_or(false);
+    let next_cursor = data.next_cursor.clone().or_else(|| data.cursor.clone());
+
+    let (field, list) = data.records();
+    let records = list.into_iter().enumerate().filter_map(|(i, record)| {
+        let record: HistoryEntry = match responses::from_value("History request", &format!("data.{field}[{i}]"), record) {
+            Ok(record) => record,
+            Err(e) => {
+                tracing::warn!(error = %e, "Skipping unreadable history record");
+                return None;
+            }
+        };
+        Some(HistoryRecord {
+            history_record_id: record.history_record_id.clone()?,
+            prompt: record.prompt().unwrap_or_default(),
+            model: record.model(),
+            created_time: record.created_time,
+            result: poll_result(&record),
+        })
+    }).collect();
+
+    HistoryPage { records, has_more, next_cursor }
+}
 
-    let (queue_position, queue_total, queue_eta) = if let Some(qi) = queue_info {
-        let pos = qi.get("queue_idx")
-            .and_then(|v| v.as_i64().map(|n| n as i32));
-        let total = qi.get("queue_length")
-            .and_then(|v| v.as_i64().map(|n| n as i32));
-        // ETA from forecast_queue_cost (seconds) at top level
-        let forecast_secs = history_data.get("forecast_queue_cost")
-            .and_then(|v| v.as_i64());
-        let eta = forecast_secs.map(|s| {
+/// Read status, outputs and queue info from one upstream history record.
+fn poll_result(record: &HistoryEntry) -> PollResult {
+    let first = record.item_list.first();
+    let image_urls = record.item_list.iter()
+        .filter_map(Item::image_url)
+        .map(str::to_string)
+        .collect();
+
+    // ETA from forecast_queue_cost (seconds), only while queued
+    let queue_eta = record.queue_info.as_ref()
+        .and(record.forecast_queue_cost)
+        .map(|s| {
             if s >= 3600 {
                 format!("{}h{}m", s / 3600, (s % 3600) / 60)
             } else if s >= 60 {
@@ -173,91 +148,59 @@ pub async fn poll_status(
                 format!("{}s", s)
             }
         });
-        (pos, total, eta)
-    } else {
-        (None, None, None)
-    };
 
-    Ok(PollResult {
-        status,
-        fail_code,
-        fail_msg,
-        video_url,
+    PollResult {
+        status: record.status,
+        fail_code: record.fail_code().map(str::to_string),
+        fail_msg: record.fail_msg().map(str::to_string),
+        video_url: first.and_then(Item::play_url).map(str::to_string),
         image_urls,
-        queue_position,
-        queue_total,
+        queue_position: record.queue_info.as_ref().and_then(|q| q.queue_idx).map(|n| n as i32),
+        queue_total: record.queue_info.as_ref().and_then(|q| q.queue_length).map(|n| n as i32),
         queue_eta,
-        item_id,
-    })
-}
-
-/// Try to get high-quality video URL via get_local_item_list API.
-pub async fn fetch_hq_video_url(
-    client: &Client,
-    session_token: &str,
-    item_id: &str,
-    cookie_jar: Option<&str>,
-) -> Result<Option<String>> {
-    let uri = "/mweb/v1/get_local_item_list";
-    let headers = auth::build_headers_with_cookies(session_token, uri, cookie_jar);
-    let params = auth::standard_query_params_with_jar(cookie_jar);
-
-    let body = serde_json::json!({
-        "item_id_list": [item_id],
-        "pack_item_opt": { "scene": 1, "need_data_integrity": true },
-        "is_for_video_download": true,
-    });
-
-    let text = if cookie_jar.is_some() {
-        let query_string = params.iter()
-            .map(|(k, v)| format!("{k}={v}"))
-            .collect::<Vec<_>>()
-            .join("&");
-        let url = format!("{JIMENG_BASE}{uri}?{query_string}");
-        let body_str = body.to_string();
-        let (_status, text) = curl_transport::post_json_via_curl(&url, &headers, &body_str, 30).await?;
-        text
-    } else {
-        let resp = client.post(format!("{JIMENG_BASE}{uri}"))
-            .headers(headers)
-            .query(&params)
-            .json(&body)
-            .send().await?;
-        resp.text().await?
-    };
-    let data = if let Some(d) = serde_json::from_str::<serde_json::Value>(&text).ok().and_then(|v| v.get("data").cloned()) {
-        d
-    } else {
-        serde_json::from_str(&text)?
-    };
-
-    // Try structured extraction first
-    let item_list = data.get("item_list").or_else(|| data.get("local_item_list"));
-    if let Some(items) = item_list.and_then(|v| v.as_array()) {
-        if let Some(item) = items.first() {
-            let url = item.pointer("/video/transcoded_video/origin/video_url")
-                .or_else(|| item.pointer("/video/download_url"))
-                .or_else(|| item.pointer("/video/play_url"))
-                .or_else(|| item.pointer("/video/url"))
-                .and_then(|v| v.as_str());
-            if let Some(url) = url {
-                return Ok(Some(url.to_string()));
-            }
-        }
+        item_id: first.and_then(Item::id).map(str::to_string),
     }
+}
 
-    // Fallback: regex match high-quality URL patterns
-    let re_patterns = [
-        r#"https://v\d+-dreamnia\.jimeng\.com/[^"\s\\]+"#,
-        r#"https://v\d+-[^"\\\s]*\.jimeng\.com/[^"\s\\]+"#,
-    ];
-    for pattern in &re_patterns {
-        if let Ok(re) = regex::Regex::new(pattern) {
-            if let Some(m) = re.find(&text) {
-                return Ok(Some(m.as_str().to_string()));
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_history_page() {
+        let draft = serde_json::json!({"component_list": [{"abilities": {"gen_video": {"text_to_video_params": {
+            "model_req_key": "dreamina_seedance_40",
+            "video_gen_inputs": [{"prompt": "a cat surfing"}],
+        }}}}]});
+        let payload = serde_json::json!({"ret": "0", "data": {
+            "has_more": true,
+            "next_cursor": "1739000000",
+            "records_list": [
+                {
+                    "history_record_id": "111", "status": 50, "created_time": 1739000100.5,
+                    "draft_content": draft.to_string(),
+                    "item_list": [{"video": {"play_url": "https://v/1.mp4"}}],
+                },
+                {"history_record_id": 222, "status": 20},
+                {"status": 50},
+                {"history_record_id": "333", "item_list": [{"video": {"play_url": "https://v/3.mp4"}}]},
+            ],
+        }});
+
+        let data = responses::parse_data("History request", &payload.to_string()).unwrap();
+        let page = history_page(data);
+        assert!(page.has_more);
+        assert_eq!(page.next_cursor.as_deref(), Some("1739000000"));
+        assert_eq!(page.records.len(), 2);
+        let first = &page.records[0];
+        assert_eq!(first.prompt, "a cat surfing");
+        assert_eq!(first.model.as_deref(), Some("dreamina_seedance_40"));
+        assert_eq!(first.created_time, Some(1739000100));
+        assert_eq!(first.result.video_url.as_deref(), Some("https://v/1.mp4"));
+        assert_eq!(page.records[1].history_record_id, "222");
+        assert_eq!(page.records[1].result.status, STATUS_PENDING);
+
+        let failed = serde_json::json!({"ret": "1015", "errmsg": "login error"});
+        assert!(responses::parse_data::<HistoryPageData>("History request", &failed.to_string()).is_err());
     }
-
-    Ok(None)
 }
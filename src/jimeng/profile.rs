@@ -0,0 +1,137 @@
+//! Jimeng account profile: who a session belongs to and its subscription.
+//!
+//! Identity comes from the passport `account/info/v2` call, the VIP tier from
+//! the commerce subscription endpoint. Responses are read leniently since
+//! both endpoints are undocumented.
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::client::JimengClient;
+use super::transport::Endpoint;
+
+const ACCOUNT_INFO_URI: &str = "/passport/account/info/v2";
+const SUBSCRIPTION_URI: &str = "/commerce/v1/subscription/user_info";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountProfile {
+    pub uid: Option<String>,
+    pub nickname: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Subscription tier as jimeng names it; `None` for free accounts.
+    pub vip_level: Option<String>,
+    /// When the subscription ends (UTC).
+    pub vip_expires_at: Option<String>,
+    /// Account region code (e.g. `cn`).
+    pub region: Option<String>,
+    /// Capability tags implied by the subscription, used for routing on top
+    /// of the session's own tags.
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    pub fetched_at: String,
+}
+
+impl JimengClient {
+    /// Fetch the profile of the account behind this session.
+    pub async fn fetch_profile(&mut self) -> Result<AccountProfile> {
+        let info = self.post_account(ACCOUNT_INFO_URI, &[("account_sdk_source", "web".to_string())]).await?;
+        let subscription = self.post_account(SUBSCRIPTION_URI, &[]).await?;
+
+        let mut profile = parse_account_info(&info)?;
+        (profile.vip_level, profile.vip_expires_at) = parse_subscription(&subscription);
+        if profile.region.is_none() {
+            profile.region = self.cookie_jar().and_then(|jar| jar.get("store-country-code")).map(str::to_lowercase);
+        }
+        profile.fetched_at = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let active = profile.vip_level.is_some()
+            && profile.vip_expires_at.as_deref().is_none_or(|t| t > profile.fetched_at.as_str());
+        if active {
+            profile.capabilities = vec!["vip".to_string()];
+        }
+        Ok(profile)
+    }
+
+    async fn post_account(&mut self, uri: &str, extra_params: &[(&'static str, String)]) -> Result<Value> {
+        let (status, text) = self.post_json(Endpoint::Account, uri, extra_params, &serde_json::json!({}), 30).await?;
+        if status >= 400 {
+            bail!("{uri} returned {status}");
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+/// Read identity fields from an `account/info/v2` response.
+fn parse_account_info(body: &Value) -> Result<AccountProfile> {
+    let data = body.get("data").unwrap_or(body);
+    let uid = str_at(data, &["user_id_str", "user_id", "uid"]);
+    if uid.is_none() {
+        let message = str_at(body, &["message", "errmsg", "description"]).unwrap_or_default();
+        bail!("account info has no user id: {message}");
+    }
+    Ok(AccountProfile {
+        uid,
+        nickname: str_at(data, &["screen_name", "name", "nickname"]),
+        avatar_url: str_at(data, &["avatar_url", "avatar_uri"]),
+        region: str_at(data, &["country_code", "region", "store_country_code"]).map(|r| r.to_lowercase()),
+        ..Default::default()
+    })
+}
+
+/// Read `(tier, expiry)` from a subscription response. Expiry is a Unix
+/// timestamp upstream and converted to a UTC datetime.
+fn parse_subscription(body: &Value) -> (Option<String>, Option<String>) {
+    let data = body.get("data").unwrap_or(body);
+    let vip = ["vip_info", "subscription", "user_vip_info"]
+        .iter()
+        .find_map(|k| data.get(k))
+        .unwrap_or(data);
+    let level = str_at(vip, &["vip_level", "level", "product_name", "vip_type"])
+        .filter(|l| !l.is_empty() && l != "0");
+    let expires = ["end_time", "expire_time", "vip_end_time"]
+        .iter()
+        .find_map(|k| vip.get(k).and_then(|v| v.as_i64().or_else(|| v.as_str()?.parse().ok())))
+        .filter(|&t| t > 0)
+        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string());
+    match level {
+        Some(level) => (Some(level), expires),
+        None => (None, None),
+    }
+}
+
+/// First of `keys` present in `value` as a string or number.
+fn str_at(value: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|k| match value.get(k)? {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profile_responses() {
+        let info = serde_json::json!({
+            "message": "success",
+            "data": {"user_id": 1234567890, "screen_name": "即梦用户", "avatar_url": "https://p3/x.jpg"},
+        });
+        let profile = parse_account_info(&info).unwrap();
+        assert_eq!(profile.uid.as_deref(), Some("1234567890"));
+        assert_eq!(profile.nickname.as_deref(), Some("即梦用户"));
+
+        let err = serde_json::json!({"message": "error", "data": {"description": "会话已过期"}});
+        assert!(parse_account_info(&err).is_err());
+
+        let sub = serde_json::json!({"ret": "0", "data": {"vip_info": {"vip_level": "basic", "end_time": 4102444800i64}}});
+        assert_eq!(
+            parse_subscription(&sub),
+            (Some("basic".into()), Some("2100-01-01 00:00:00".into())),
+        );
+        let free = serde_json::json!({"ret": "0", "data": {"vip_level": 0}});
+        assert_eq!(parse_subscription(&free), (None, None));
+    }
+}
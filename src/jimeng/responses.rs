@@ -0,0 +1,545 @@
+//! Typed models of jimeng API responses.
+//!
+//! jimeng has moved and renamed fields over time. Where it has, the models
+//! carry every known layout and the accessors try them newest first.
+//! Unknown fields are ignored, and ids and numbers are accepted as either
+//! strings or numbers. A response that no longer fits fails with a
+//! `SchemaError` naming the offending path instead of being read with
+//! defaults (a task that looks pending forever).
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::error::JimengError;
+
+/// A response that does not match its model.
+#[derive(Debug, thiserror::Error)]
+#[error("{what}: unexpected response at `{path}`: {message}")]
+pub struct SchemaError {
+    pub what: &'static str,
+    /// Path of the offending value, e.g. `data.item_list[0].video`.
+    pub path: String,
+    pub message: String,
+}
+
+/// `ret`/`errmsg` of the `{ret, errmsg, data}` envelope.
+#[derive(Debug, Deserialize)]
+struct Status {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    ret: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    errmsg: Option<String>,
+}
+
+/// Parse the `data` of a jimeng API response into `T`, failing on a
+/// non-zero `ret`. Responses without a `data` key (older layout) are read
+/// whole.
+pub fn parse_data<T: DeserializeOwned>(what: &'static str, text: &str) -> Result<T, JimengError> {
+    let mut payload: Value = serde_json::from_str(text).map_err(|e| SchemaError {
+        what,
+        path: ".".into(),
+        message: format!("{e}. Body: {}", text.chars().take(500).collect::<String>()),
+    })?;
+    let status: Status = from_value(what, "", payload.clone())?;
+    if let Some(ret) = status.ret.filter(|r| r != "0") {
+        return Err(JimengError::Api {
+            what,
+            ret,
+            errmsg: status.errmsg.unwrap_or_else(|| "unknown".into()),
+            transport: None,
+        });
+    }
+    match payload.get_mut("data").map(Value::take) {
+        Some(data) => Ok(from_value(what, "data", data)?),
+        None => Ok(from_value(what, "", payload)?),
+    }
+}
+
+/// Deserialize `value`, found at `prefix` in the response, into `T`.
+pub fn from_value<T: DeserializeOwned>(what: &'static str, prefix: &str, value: Value) -> Result<T, SchemaError> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let inner = e.path().to_string();
+        let path = match (prefix, inner.as_str()) {
+            ("", inner) => inner.to_string(),
+            (prefix, ".") => prefix.to_string(),
+            (prefix, inner) if inner.starts_with('[') => format!("{prefix}{inner}"),
+            (prefix, inner) => format!("{prefix}.{inner}"),
+        };
+        SchemaError { what, path, message: e.into_inner().to_string() }
+    })
+}
+
+/// Data of `aigc_draft/generate`.
+#[derive(Debug, Default, Deserialize)]
+pub struct GenerateData {
+    #[serde(default)]
+    pub aigc_data: Option<AigcData>,
+    /// Older responses put the id and charge next to `aigc_data`.
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub history_record_id: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_i64")]
+    pub credit_count: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AigcData {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub history_record_id: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_i64")]
+    pub credit_count: Option<i64>,
+    #[serde(default, deserialize_with = "de::opt_i64")]
+    pub consume_credit: Option<i64>,
+}
+
+impl GenerateData {
+    pub fn history_record_id(&self) -> Option<&str> {
+        self.aigc_data.as_ref()
+            .and_then(|a| a.history_record_id.as_deref())
+            .or(self.history_record_id.as_deref())
+    }
+
+    /// Credits jimeng says the generation costs, if reported.
+    pub fn credits(&self) -> Option<i64> {
+        self.aigc_data.as_ref()
+            .and_then(|a| a.credit_count.or(a.consume_credit))
+            .or(self.credit_count)
+    }
+}
+
+/// Data of `get_history_by_ids`.
+#[derive(Debug, Deserialize)]
+pub struct HistoryByIds {
+    /// Older layouts: a list holding the one record.
+    #[serde(default, deserialize_with = "de::list")]
+    pub history_list: Vec<Value>,
+    #[serde(default, deserialize_with = "de::list")]
+    pub history_records: Vec<Value>,
+    /// Current layout: records keyed by history id.
+    #[serde(flatten)]
+    pub by_id: BTreeMap<String, Value>,
+}
+
+impl HistoryByIds {
+    /// The record for `history_record_id`, if the response has one.
+    pub fn into_record(mut self, what: &'static str, history_record_id: &str) -> Result<Option<HistoryEntry>, SchemaError> {
+        if let Some(record) = self.by_id.remove(history_record_id) {
+            return from_value(what, &format!("data.{history_record_id}"), record).map(Some);
+        }
+        for (field, list) in [("history_list", self.history_list), ("history_records", self.history_records)] {
+            if let Some(record) = list.into_iter().next() {
+                return from_value(what, &format!("data.{field}[0]"), record).map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Data of `get_history`. Records stay raw so one odd record does not fail
+/// the page.
+#[derive(Debug, Deserialize)]
+pub struct HistoryPageData {
+    #[serde(default, deserialize_with = "de::list")]
+    pub records_list: Vec<Value>,
+    /// Older names of `records_list`.
+    #[serde(default, deserialize_with = "de::list")]
+    pub history_list: Vec<Value>,
+    #[serde(default, deserialize_with = "de::list")]
+    pub history_records: Vec<Value>,
+    #[serde(default)]
+    pub has_more: Option<bool>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub next_cursor: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub cursor: Option<String>,
+}
+
+impl HistoryPageData {
+    /// The records and the field they were found under.
+    pub fn records(self) -> (&'static str, Vec<Value>) {
+        [("records_list", self.records_list), ("history_list", self.history_list), ("history_records", self.history_records)]
+            .into_iter()
+            .find(|(_, list)| !list.is_empty())
+            .unwrap_or(("records_list", Vec::new()))
+    }
+}
+
+/// One generation record, as returned by `get_history_by_ids` and
+/// `get_history`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryEntry {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub history_record_id: Option<String>,
+    #[serde(deserialize_with = "de::i64")]
+    pub status: i64,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub fail_starling_key: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub fail_starling_message: Option<String>,
+    /// Older names of the failure fields.
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub fail_code: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub error_code: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub fail_msg: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub error_msg: Option<String>,
+    #[serde(default, deserialize_with = "de::list")]
+    pub item_list: Vec<Item>,
+    #[serde(default)]
+    pub queue_info: Option<QueueInfo>,
+    /// Seconds until generation is expected to start.
+    #[serde(default, deserialize_with = "de::opt_i64")]
+    pub forecast_queue_cost: Option<i64>,
+    /// Unix seconds.
+    #[serde(default, deserialize_with = "de::opt_i64")]
+    pub created_time: Option<i64>,
+    /// The submitted draft, as a JSON string.
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub draft_content: Option<String>,
+    #[serde(default, deserialize_with = "de::list")]
+    pub component_list: Vec<Component>,
+    #[serde(default)]
+    pub model_info: Option<ModelInfo>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub prompt: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn fail_code(&self) -> Option<&str> {
+        self.fail_starling_key.as_deref()
+            .or(self.fail_code.as_deref())
+            .or(self.error_code.as_deref())
+    }
+
+    pub fn fail_msg(&self) -> Option<&str> {
+        self.fail_starling_message.as_deref()
+            .or(self.fail_msg.as_deref())
+            .or(self.error_msg.as_deref())
+    }
+
+    /// Components of the record, then of its `draft_content`.
+    fn components(&self) -> Vec<Component> {
+        let draft = self.draft_content.as_deref()
+            .and_then(|d| serde_json::from_str::<Draft>(d).ok())
+            .map(|d| d.component_list)
+            .unwrap_or_default();
+        self.component_list.first().cloned().into_iter()
+            .chain(draft.into_iter().next())
+            .collect()
+    }
+
+    pub fn prompt(&self) -> Option<String> {
+        let components = self.components();
+        self.item_list.first()
+            .and_then(|item| item.common_attr.as_ref()?.description.clone())
+            .or_else(|| components.iter().find_map(|c| c.video_params()?.video_gen_inputs.first()?.prompt.clone()))
+            .or_else(|| components.iter().find_map(|c| c.core_param()?.prompt.clone()))
+            .or_else(|| self.prompt.clone())
+    }
+
+    /// Upstream model key (e.g. `dreamina_seedance_40`).
+    pub fn model(&self) -> Option<String> {
+        let components = self.components();
+        components.iter().find_map(|c| c.video_params()?.model_req_key.clone())
+            .or_else(|| components.iter().find_map(|c| c.core_param()?.model.clone()))
+            .or_else(|| self.model_info.as_ref()?.model_req_key.clone())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueueInfo {
+    /// 0-based position in jimeng's queue.
+    #[serde(default, deserialize_with = "de::opt_i64")]
+    pub queue_idx: Option<i64>,
+    #[serde(default, deserialize_with = "de::opt_i64")]
+    pub queue_length: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelInfo {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub model_req_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Draft {
+    #[serde(default, deserialize_with = "de::list")]
+    component_list: Vec<Component>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Component {
+    #[serde(default)]
+    pub abilities: Option<Abilities>,
+}
+
+impl Component {
+    fn video_params(&self) -> Option<&TextToVideoParams> {
+        self.abilities.as_ref()?.gen_video.as_ref()?.text_to_video_params.as_ref()
+    }
+
+    fn core_param(&self) -> Option<&CoreParam> {
+        self.abilities.as_ref()?.generate.as_ref()?.core_param.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Abilities {
+    /// Video generation.
+    #[serde(default)]
+    pub gen_video: Option<GenVideo>,
+    /// Image generation.
+    #[serde(default)]
+    pub generate: Option<Generate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GenVideo {
+    #[serde(default)]
+    pub text_to_video_params: Option<TextToVideoParams>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TextToVideoParams {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub model_req_key: Option<String>,
+    #[serde(default, deserialize_with = "de::list")]
+    pub video_gen_inputs: Vec<VideoGenInput>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VideoGenInput {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub prompt: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Generate {
+    #[serde(default)]
+    pub core_param: Option<CoreParam>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CoreParam {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub prompt: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub model: Option<String>,
+}
+
+/// Data of `get_upload_token`: temporary ImageX/VOD credentials.
+#[derive(Debug, Deserialize)]
+pub struct UploadToken {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: String,
+    /// ImageX service, for image uploads.
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub service_id: Option<String>,
+    /// VOD space, for video and audio uploads.
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub space_name: Option<String>,
+}
+
+/// Data of `get_local_item_list`.
+#[derive(Debug, Deserialize)]
+pub struct LocalItemList {
+    #[serde(default, deserialize_with = "de::list")]
+    pub item_list: Vec<Item>,
+    /// Older name of `item_list`.
+    #[serde(default, deserialize_with = "de::list")]
+    pub local_item_list: Vec<Item>,
+}
+
+impl LocalItemList {
+    pub fn first(&self) -> Option<&Item> {
+        self.item_list.first().or_else(|| self.local_item_list.first())
+    }
+}
+
+/// One output of a generation.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Item {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub item_id: Option<String>,
+    /// Older names of `item_id`.
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub id: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub local_item_id: Option<String>,
+    #[serde(default)]
+    pub common_attr: Option<CommonAttr>,
+    #[serde(default)]
+    pub video: Option<Video>,
+    #[serde(default)]
+    pub image: Option<Image>,
+}
+
+impl Item {
+    pub fn id(&self) -> Option<&str> {
+        self.item_id.as_deref()
+            .or(self.id.as_deref())
+            .or(self.local_item_id.as_deref())
+            .or_else(|| self.common_attr.as_ref()?.id.as_deref())
+    }
+
+    /// Best URL for playback: the transcoded original, else the play URL.
+    pub fn play_url(&self) -> Option<&str> {
+        let video = self.video.as_ref()?;
+        video.origin_url()
+            .or(video.play_url.as_deref())
+            .or(video.download_url.as_deref())
+            .or(video.url.as_deref())
+    }
+
+    /// Best URL for download: the transcoded original, else the download URL.
+    pub fn download_url(&self) -> Option<&str> {
+        let video = self.video.as_ref()?;
+        video.origin_url()
+            .or(video.download_url.as_deref())
+            .or(video.play_url.as_deref())
+            .or(video.url.as_deref())
+    }
+
+    /// Full-size image, else the cover.
+    pub fn image_url(&self) -> Option<&str> {
+        self.image.as_ref()
+            .and_then(|i| i.large_images.first()?.image_url.as_deref())
+            .or_else(|| self.common_attr.as_ref()?.cover_url.as_deref())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommonAttr {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub id: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub cover_url: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Video {
+    #[serde(default)]
+    pub transcoded_video: Option<TranscodedVideo>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub play_url: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub download_url: Option<String>,
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub url: Option<String>,
+}
+
+impl Video {
+    fn origin_url(&self) -> Option<&str> {
+        self.transcoded_video.as_ref()?.origin.as_ref()?.video_url.as_deref()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TranscodedVideo {
+    #[serde(default)]
+    pub origin: Option<VideoFile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VideoFile {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub video_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Image {
+    #[serde(default, deserialize_with = "de::list")]
+    pub large_images: Vec<ImageFile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImageFile {
+    #[serde(default, deserialize_with = "de::opt_text")]
+    pub image_url: Option<String>,
+}
+
+/// Lenient deserializers for jimeng's loosely typed fields.
+mod de {
+    use serde::Deserialize;
+    use serde::de::{Deserializer, Error};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scalar {
+        Int(i64),
+        Float(f64),
+        Text(String),
+    }
+
+    /// A string or number, as text. Null and `""` are `None`.
+    pub fn opt_text<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+        Ok(match Option::<Scalar>::deserialize(d)? {
+            Some(Scalar::Int(n)) => Some(n.to_string()),
+            Some(Scalar::Float(f)) => Some(f.to_string()),
+            Some(Scalar::Text(s)) => Some(s).filter(|s| !s.is_empty()),
+            None => None,
+        })
+    }
+
+    /// An integer, float (truncated) or numeric string. Null and `""` are
+    /// `None`.
+    pub fn opt_i64<'de, D: Deserializer<'de>>(d: D) -> Result<Option<i64>, D::Error> {
+        match Option::<Scalar>::deserialize(d)? {
+            Some(Scalar::Int(n)) => Ok(Some(n)),
+            Some(Scalar::Float(f)) => Ok(Some(f as i64)),
+            Some(Scalar::Text(s)) if s.is_empty() => Ok(None),
+            Some(Scalar::Text(s)) => s.parse().map(Some).map_err(|_| D::Error::custom(format!("expected a number, got {s:?}"))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn i64<'de, D: Deserializer<'de>>(d: D) -> Result<i64, D::Error> {
+        opt_i64(d)?.ok_or_else(|| D::Error::custom("expected a number, got null"))
+    }
+
+    /// A list; null is empty.
+    pub fn list<'de, D: Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Vec<T>, D::Error> {
+        Ok(Option::<Vec<T>>::deserialize(d)?.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_data_layouts() {
+        let current: GenerateData = parse_data("Submit",
+            r#"{"ret": "0", "data": {"aigc_data": {"history_record_id": 123, "credit_count": "10"}, "extra": 1}}"#).unwrap();
+        assert_eq!(current.history_record_id(), Some("123"));
+        assert_eq!(current.credits(), Some(10));
+
+        let bare: GenerateData = parse_data("Submit", r#"{"aigc_data": {"history_record_id": "h1", "consume_credit": 5}}"#).unwrap();
+        assert_eq!(bare.history_record_id(), Some("h1"));
+        assert_eq!(bare.credits(), Some(5));
+
+        let err = parse_data::<GenerateData>("Submit", r#"{"ret": 1015, "errmsg": "login error"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "Submit failed [ret=1015]: login error");
+    }
+
+    #[test]
+    fn test_schema_error_path() {
+        let text = r#"{"ret": "0", "data": {"h1": {"status": 20, "item_list": [{"video": "gone"}]}}}"#;
+        let data: HistoryByIds = parse_data("Poll", text).unwrap();
+        let err = data.into_record("Poll", "h1").unwrap_err();
+        assert_eq!(err.path, "data.h1.item_list[0].video");
+
+        let text = r#"{"data": {"history_list": [{"queue_info": {}}]}}"#;
+        let data: HistoryByIds = parse_data("Poll", text).unwrap();
+        let err = data.into_record("Poll", "h1").unwrap_err();
+        assert_eq!(err.path, "data.history_list[0]");
+        assert!(err.message.contains("status"), "{}", err.message);
+    }
+}
@@ -0,0 +1,292 @@
+//! Transports for jimeng API calls and the fallback chains between them.
+//!
+//! ByteDance checks clients at several layers: system curl presents a TLS
+//! fingerprint it accepts, reqwest gets through with an `a_bogus` signature,
+//! and the headless browser passes everything but is slow and serialized.
+//! Which one works varies by endpoint and account, so each endpoint class
+//! has a configurable chain that is tried in order.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+
+use super::abogus;
+use super::auth::Fingerprint;
+use super::browser::BrowserService;
+use super::curl_transport::{self, CurlError, CurlRequest};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    /// System curl (OpenSSL TLS fingerprint).
+    Curl,
+    /// reqwest with an `a_bogus` signature.
+    Reqwest,
+    /// `fetch` from the shared headless browser page.
+    Browser,
+}
+
+impl TransportKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Curl => "curl",
+            Self::Reqwest => "reqwest",
+            Self::Browser => "browser",
+        }
+    }
+}
+
+/// Endpoint classes, each with its own fallback chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endpoint {
+    /// `aigc_draft/generate`.
+    Submit,
+    /// Status, history and HQ URL lookups.
+    Poll,
+    /// Upload token requests.
+    Upload,
+    /// Profile, credit and validity checks.
+    Account,
+}
+
+impl Endpoint {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Submit => "submit",
+            Self::Poll => "poll",
+            Self::Upload => "upload",
+            Self::Account => "account",
+        }
+    }
+}
+
+/// Transport fallback chain per endpoint class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransportPolicy {
+    pub submit: Vec<TransportKind>,
+    pub poll: Vec<TransportKind>,
+    pub upload: Vec<TransportKind>,
+    pub account: Vec<TransportKind>,
+}
+
+impl Default for TransportPolicy {
+    fn default() -> Self {
+        use TransportKind::*;
+        Self {
+            submit: vec![Curl, Reqwest, Browser],
+            poll: vec![Curl, Reqwest],
+            upload: vec![Curl, Reqwest],
+            account: vec![Curl, Reqwest],
+        }
+    }
+}
+
+impl TransportPolicy {
+    pub fn chain(&self, endpoint: Endpoint) -> &[TransportKind] {
+        match endpoint {
+            Endpoint::Submit => &self.submit,
+            Endpoint::Poll => &self.poll,
+            Endpoint::Upload => &self.upload,
+            Endpoint::Account => &self.account,
+        }
+    }
+
+    /// Parse a comma-separated chain such as `curl,reqwest,browser`.
+    pub fn parse_chain(s: &str) -> Result<Vec<TransportKind>> {
+        let mut chain = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let kind = match name {
+                "curl" => TransportKind::Curl,
+                "reqwest" => TransportKind::Reqwest,
+                "browser" => TransportKind::Browser,
+                other => bail!("unknown transport '{other}' (expected curl, reqwest or browser)"),
+            };
+            if !chain.contains(&kind) {
+                chain.push(kind);
+            }
+        }
+        if chain.is_empty() {
+            bail!("transport chain is empty");
+        }
+        Ok(chain)
+    }
+
+    /// Chain for `endpoint`, with the transport that last worked for the
+    /// session (if it is in the chain) moved to the front.
+    pub fn ordered(&self, endpoint: Endpoint, preferred: &BTreeMap<Endpoint, TransportKind>) -> Vec<TransportKind> {
+        let mut chain = self.chain(endpoint).to_vec();
+        if let Some(pos) = preferred.get(&endpoint).and_then(|p| chain.iter().position(|k| k == p)) {
+            let kind = chain.remove(pos);
+            chain.insert(0, kind);
+        }
+        chain
+    }
+}
+
+/// A prepared jimeng POST, the same whichever transport sends it.
+pub struct Request<'a> {
+    /// Base URL plus path, without the query string.
+    pub url: &'a str,
+    pub query: &'a str,
+    pub headers: &'a HeaderMap,
+    pub body: &'a str,
+    pub timeout_secs: u64,
+}
+
+impl Request<'_> {
+    fn full_url(&self) -> String {
+        format!("{}?{}", self.url, self.query)
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+    /// Raw `Set-Cookie` values, where the transport can see them.
+    pub set_cookies: Vec<String>,
+}
+
+pub trait Transport {
+    async fn send(&self, req: &Request<'_>) -> Result<Response>;
+}
+
+pub struct Curl<'a> {
+    pub proxy_url: Option<&'a str>,
+}
+
+impl Transport for Curl<'_> {
+    async fn send(&self, req: &Request<'_>) -> Result<Response> {
+        let mut headers = req.headers.clone();
+        headers.insert(reqwest::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let resp = curl_transport::send(&CurlRequest {
+            method: Method::POST,
+            url: &req.full_url(),
+            headers: &headers,
+            body: Some(req.body.as_bytes()),
+            timeout_secs: req.timeout_secs,
+            proxy_url: self.proxy_url,
+        }).await?;
+        Ok(Response { status: resp.status, body: resp.text(), set_cookies: resp.set_cookies() })
+    }
+}
+
+pub struct SignedReqwest<'a> {
+    pub http: &'a Client,
+    pub user_agent: &'a str,
+}
+
+impl Transport for SignedReqwest<'_> {
+    async fn send(&self, req: &Request<'_>) -> Result<Response> {
+        let a_bogus = abogus::generate(req.query, "POST", self.user_agent);
+        let resp = self.http.post(format!("{}&a_bogus={a_bogus}", req.full_url()))
+            .headers(req.headers.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(req.body.to_string())
+            .timeout(Duration::from_secs(req.timeout_secs))
+            .send().await?;
+        let status = resp.status().as_u16();
+        let set_cookies = resp.headers()
+            .get_all(reqwest::header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok().map(str::to_string))
+            .collect();
+        let body = resp.text().await?;
+        Ok(Response { status, body, set_cookies })
+    }
+}
+
+pub struct BrowserFetch<'a> {
+    pub browser: &'a Arc<BrowserService>,
+    pub session_token: &'a str,
+    pub fingerprint: &'a Fingerprint,
+    pub proxy_url: Option<&'a str>,
+}
+
+impl Transport for BrowserFetch<'_> {
+    async fn send(&self, req: &Request<'_>) -> Result<Response> {
+        let (status, body) = self.browser
+            .fetch(self.session_token, self.fingerprint, self.proxy_url, &req.full_url(), req.body)
+            .await?;
+        Ok(Response { status, body, set_cookies: Vec::new() })
+    }
+}
+
+/// Whether a failed try of an `endpoint` call may be repeated on the next
+/// transport. Submits create a generation (and spend credits), so they are
+/// repeated only when jimeng cannot have acted on them: the request never
+/// left (connect or TLS failure) or it was rejected outright (4xx).
+pub fn may_retry(endpoint: Endpoint, result: &Result<Response>) -> bool {
+    if endpoint != Endpoint::Submit {
+        return true;
+    }
+    match result {
+        Ok(resp) => (400..500).contains(&resp.status),
+        Err(e) => {
+            e.downcast_ref::<CurlError>().is_some_and(CurlError::before_send)
+                || e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect() || e.is_builder())
+        }
+    }
+}
+
+/// One transport try of a jimeng call.
+#[derive(Debug, Clone)]
+pub struct TransportAttempt {
+    pub endpoint: Endpoint,
+    pub transport: TransportKind,
+    /// 1-based position of this try within the call.
+    pub attempt: usize,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl TransportAttempt {
+    /// Got a response with a success or redirect status.
+    pub fn ok(&self) -> bool {
+        self.error.is_none() && self.status.is_some_and(|s| (200..400).contains(&s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_parsing_and_preference() {
+        use TransportKind::*;
+        assert_eq!(TransportPolicy::parse_chain(" browser, curl,curl ").unwrap(), vec![Browser, Curl]);
+        assert!(TransportPolicy::parse_chain("curl,wget").is_err());
+        assert!(TransportPolicy::parse_chain(" , ").is_err());
+
+        let policy = TransportPolicy::default();
+        let mut preferred = BTreeMap::new();
+        assert_eq!(policy.ordered(Endpoint::Submit, &preferred), vec![Curl, Reqwest, Browser]);
+        preferred.insert(Endpoint::Submit, Browser);
+        preferred.insert(Endpoint::Poll, Browser);
+        assert_eq!(policy.ordered(Endpoint::Submit, &preferred), vec![Browser, Curl, Reqwest]);
+        // Not in the poll chain, so ignored there.
+        assert_eq!(policy.ordered(Endpoint::Poll, &preferred), vec![Curl, Reqwest]);
+    }
+
+    #[test]
+    fn test_attempt_ok() {
+        let attempt = |status, error: Option<&str>| TransportAttempt {
+            endpoint: Endpoint::Submit,
+            transport: TransportKind::Curl,
+            attempt: 1,
+            status,
+            error: error.map(str::to_string),
+        };
+        assert!(attempt(Some(200), None).ok());
+        assert!(attempt(Some(302), None).ok());
+        assert!(!attempt(Some(0), None).ok());
+        assert!(!attempt(Some(403), None).ok());
+        assert!(!attempt(None, Some("curl failed")).ok());
+    }
+}
@@ -1,23 +1,58 @@
 //! File upload to ByteDance ImageX and VOD services with AWS Signature V4.
 
-use anyhow::{bail, Result};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
 use hmac::{Hmac, Mac};
 use sha2::{Sha256, Digest};
-use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Method};
+use tokio::io::AsyncReadExt;
 
-use super::auth;
-use super::models::MaterialType;
+use super::client::JimengClient;
+use super::error::JimengError;
+use super::responses::UploadToken;
+use super::transport::Endpoint;
 
-const JIMENG_BASE: &str = "https://jimeng.jianying.com";
-const IMAGEX_HOST: &str = "https://imagex.bytedanceapi.com";
-const VOD_HOST: &str = "https://vod.bytedanceapi.com";
 const DEFAULT_SERVICE_ID: &str = "tb4s082cfz";
 const DEFAULT_SPACE_NAME: &str = "dreamina";
-
-const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36";
+/// Timeout for pushing file bytes to the storage hosts.
+const UPLOAD_TIMEOUT_SECS: u64 = 300;
+/// Videos and audio above this size are uploaded to VOD in parts of it.
+const PART_SIZE: u64 = 5 * 1024 * 1024;
+/// Tries per part before a chunked upload fails.
+const PART_ATTEMPTS: u32 = 3;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// Read an ImageX/VOD response, turning `ResponseMetadata.Error` into a
+/// `JimengError::Storage`.
+fn storage_response(action: &'static str, status: u16, text: &str) -> Result<serde_json::Value, JimengError> {
+    let value: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| storage_error(action, &format!("unreadable response (HTTP {status}): {e}")))?;
+    if let Some(err) = value.pointer("/ResponseMetadata/Error") {
+        return Err(JimengError::Storage {
+            action,
+            code: err.get("Code").and_then(|c| c.as_str()).map(str::to_string),
+            message: err.get("Message").and_then(|m| m.as_str()).map_or_else(|| err.to_string(), str::to_string),
+        });
+    }
+    Ok(value)
+}
+
+fn storage_error(action: &'static str, message: &str) -> JimengError {
+    JimengError::Storage { action, code: None, message: message.to_string() }
+}
+
+/// Build a header map from name/value pairs.
+fn header_map(pairs: &[(&str, &str)]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.insert(HeaderName::from_bytes(name.as_bytes())?, HeaderValue::from_str(value)?);
+    }
+    Ok(headers)
+}
+
 /// Compute CRC32 of a byte slice (hex string, zero-padded to 8 chars).
 fn crc32_hex(data: &[u8]) -> String {
     let crc = crc32fast::hash(data);
@@ -25,6 +60,7 @@ fn crc32_hex(data: &[u8]) -> String {
 }
 
 /// Generate an AWS4-HMAC-SHA256 signature.
+#[allow(clippy::too_many_arguments)]
 fn aws4_signature(
     method: &str,
     url: &str,
@@ -60,10 +96,10 @@ fn aws4_signature(
     let mut sign_headers: Vec<(String, String)> = headers_to_sign.iter()
         .map(|(k, v)| (k.to_lowercase(), v.to_string()))
         .collect();
-    if let Some(st) = session_token {
-        if !sign_headers.iter().any(|(k, _)| k == "x-amz-security-token") {
-            sign_headers.push(("x-amz-security-token".to_string(), st.to_string()));
-        }
+    if let Some(st) = session_token
+        && !sign_headers.iter().any(|(k, _)| k == "x-amz-security-token")
+    {
+        sign_headers.push(("x-amz-security-token".to_string(), st.to_string()));
     }
 
     let payload_hash = if method.to_uppercase() == "POST" && !payload.is_empty() {
@@ -116,162 +152,14 @@ fn aws_timestamp() -> String {
     chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string()
 }
 
-/// Get upload token from jimeng API.
-async fn get_upload_token(
-    client: &Client,
-    session_token: &str,
-    scene: u32,
-) -> Result<serde_json::Value> {
-    let uri = "/mweb/v1/get_upload_token";
-    let headers = auth::build_headers_with_cookies(session_token, uri, None);
-    let params = auth::standard_query_params();
-
-    let resp = client
-        .post(format!("{JIMENG_BASE}{uri}"))
-        .headers(headers)
-        .query(&params)
-        .json(&serde_json::json!({ "scene": scene }))
-        .send()
-        .await?;
-
-    let text = resp.text().await?;
-    let val: serde_json::Value = serde_json::from_str(&text)
-        .map_err(|e| anyhow::anyhow!("get_upload_token parse error: {e}, body: {}", &text[..text.len().min(500)]))?;
-
-    // Extract data from { ret: "0", data: {...} } format
-    if let Some(data) = val.get("data") {
-        Ok(data.clone())
-    } else {
-        Ok(val)
-    }
-}
-
-/// Upload an image to ImageX and return the image URI.
-///
-/// Flow: get_upload_token(scene=2) → ApplyImageUpload → Upload binary → CommitImageUpload
-pub async fn upload_image(
-    client: &Client,
-    session_token: &str,
-    image_data: &[u8],
-) -> Result<String> {
-    let token_data = get_upload_token(client, session_token, 2).await?;
-    let access_key = token_data["access_key_id"].as_str().unwrap_or("");
-    let secret_key = token_data["secret_access_key"].as_str().unwrap_or("");
-    let session_tok = token_data["session_token"].as_str().unwrap_or("");
-    let service_id = token_data["service_id"].as_str().unwrap_or(DEFAULT_SERVICE_ID);
-
-    if access_key.is_empty() || secret_key.is_empty() || session_tok.is_empty() {
-        bail!("Failed to get ImageX upload token");
-    }
-
-    let file_size = image_data.len();
-    let random_str: String = (0..10).map(|_| rand::random::<char>()).collect::<String>()
-        .chars().filter(|c| c.is_alphanumeric()).take(10).collect();
-    let random_str = if random_str.len() < 5 { uuid::Uuid::new_v4().to_string()[..10].to_string() } else { random_str };
-
-    // Step 1: ApplyImageUpload
-    let timestamp = aws_timestamp();
-    let apply_url = format!(
-        "{IMAGEX_HOST}/?Action=ApplyImageUpload&Version=2018-08-01&ServiceId={service_id}&FileSize={file_size}&s={random_str}"
-    );
-
-    let req_headers = vec![
-        ("x-amz-date", timestamp.as_str()),
-        ("x-amz-security-token", session_tok),
-    ];
-    let authorization = aws4_signature("GET", &apply_url, &req_headers, access_key, secret_key, Some(session_tok), "", "cn-north-1", "imagex")?;
-
-    let apply_resp = client.get(&apply_url)
-        .header("accept", "*/*")
-        .header("authorization", &authorization)
-        .header("origin", "https://jimeng.jianying.com")
-        .header("referer", "https://jimeng.jianying.com/ai-tool/video/generate")
-        .header("user-agent", USER_AGENT)
-        .header("x-amz-date", &timestamp)
-        .header("x-amz-security-token", session_tok)
-        .send().await?;
-
-    let apply_text = apply_resp.text().await?;
-    let apply_result: serde_json::Value = serde_json::from_str(&apply_text)
-        .map_err(|e| anyhow::anyhow!("ApplyImageUpload parse error: {e}"))?;
-
-    if let Some(err) = apply_result.pointer("/ResponseMetadata/Error") {
-        bail!("ApplyImageUpload failed: {err}");
-    }
-
-    let upload_address = apply_result.pointer("/Result/UploadAddress")
-        .ok_or_else(|| anyhow::anyhow!("No UploadAddress in ApplyImageUpload response"))?;
-    let store_info = upload_address.pointer("/StoreInfos/0")
-        .ok_or_else(|| anyhow::anyhow!("No StoreInfos in upload address"))?;
-    let upload_host = upload_address.pointer("/UploadHosts/0")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow::anyhow!("No UploadHosts"))?;
-    let store_uri = store_info["StoreUri"].as_str().unwrap_or("");
-    let store_auth = store_info["Auth"].as_str().unwrap_or("");
-    let session_key = upload_address["SessionKey"].as_str().unwrap_or("");
-
-    // Step 2: Upload binary
-    let upload_url = format!("https://{upload_host}/upload/v1/{store_uri}");
-    let crc32 = crc32_hex(image_data);
-
-    let upload_resp = client.post(&upload_url)
-        .header("Authorization", store_auth)
-        .header("Content-CRC32", &crc32)
-        .header("Content-Disposition", "attachment; filename=\"undefined\"")
-        .header("Content-Type", "application/octet-stream")
-        .header("Origin", "https://jimeng.jianying.com")
-        .header("User-Agent", USER_AGENT)
-        .body(image_data.to_vec())
-        .send().await?;
-
-    if !upload_resp.status().is_success() {
-        bail!("Image upload failed: HTTP {}", upload_resp.status());
-    }
-
-    // Step 3: CommitImageUpload
-    let commit_url = format!("{IMAGEX_HOST}/?Action=CommitImageUpload&Version=2018-08-01&ServiceId={service_id}");
-    let commit_timestamp = aws_timestamp();
-    let commit_payload = serde_json::json!({
-        "SessionKey": session_key,
-        "SuccessActionStatus": "200"
-    }).to_string();
-
-    let payload_hash = hex::encode(Sha256::digest(commit_payload.as_bytes()));
-    let commit_headers = vec![
-        ("x-amz-date", commit_timestamp.as_str()),
-        ("x-amz-security-token", session_tok),
-        ("x-amz-content-sha256", payload_hash.as_str()),
-    ];
-    let commit_auth = aws4_signature("POST", &commit_url, &commit_headers, access_key, secret_key, Some(session_tok), &commit_payload, "cn-north-1", "imagex")?;
-
-    let commit_resp = client.post(&commit_url)
-        .header("authorization", &commit_auth)
-        .header("content-type", "application/json")
-        .header("origin", "https://jimeng.jianying.com")
-        .header("user-agent", USER_AGENT)
-        .header("x-amz-date", &commit_timestamp)
-        .header("x-amz-security-token", session_tok)
-        .header("x-amz-content-sha256", &payload_hash)
-        .body(commit_payload)
-        .send().await?;
-
-    let commit_text = commit_resp.text().await?;
-    let commit_result: serde_json::Value = serde_json::from_str(&commit_text)
-        .map_err(|e| anyhow::anyhow!("CommitImageUpload parse error: {e}"))?;
-
-    if let Some(err) = commit_result.pointer("/ResponseMetadata/Error") {
-        bail!("CommitImageUpload failed: {err}");
-    }
-
-    // Try plugin result first, then direct result
-    if let Some(uri) = commit_result.pointer("/Result/PluginResult/0/ImageUri").and_then(|v| v.as_str()) {
-        return Ok(uri.to_string());
-    }
-    if let Some(uri) = commit_result.pointer("/Result/Results/0/Uri").and_then(|v| v.as_str()) {
-        return Ok(uri.to_string());
-    }
-
-    bail!("CommitImageUpload: no URI in response: {commit_text}")
+/// Progress of a chunked VOD upload, after each part.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadProgress {
+    pub part: u32,
+    pub parts: u32,
+    /// Bytes sent so far, of `total`.
+    pub bytes: u64,
+    pub total: u64,
 }
 
 /// Upload result from VOD.
@@ -284,170 +172,352 @@ pub struct VodUploadResult {
     pub fps: u32,
 }
 
-/// Upload video/audio to ByteDance VOD and return the vid + metadata.
-///
-/// Flow: get_upload_token(scene=1) → ApplyUploadInner → Upload binary → CommitUploadInner
-pub async fn upload_media(
-    client: &Client,
-    session_token: &str,
-    data: &[u8],
-    media_type: MaterialType,
-) -> Result<VodUploadResult> {
-    let token_data = get_upload_token(client, session_token, 1).await?;
-    let access_key = token_data["access_key_id"].as_str().unwrap_or("");
-    let secret_key = token_data["secret_access_key"].as_str().unwrap_or("");
-    let session_tok = token_data["session_token"].as_str().unwrap_or("");
-    let space_name = token_data["space_name"].as_str().unwrap_or(DEFAULT_SPACE_NAME);
-
-    if access_key.is_empty() || secret_key.is_empty() || session_tok.is_empty() {
-        bail!("Failed to get VOD upload token");
+impl JimengClient {
+    /// Get upload token from jimeng API.
+    async fn get_upload_token(&mut self, scene: u32) -> Result<UploadToken> {
+        let (status, text) = self.post_json(Endpoint::Upload, "/mweb/v1/get_upload_token", &[], &serde_json::json!({ "scene": scene }), 30).await?;
+        Ok(self.parse_response("Upload token", status, &text)?)
     }
 
-    let file_size = data.len();
-    let random_str = &uuid::Uuid::new_v4().to_string()[..10];
-
-    // Step 1: ApplyUploadInner
-    let timestamp = aws_timestamp();
-    let apply_url = format!(
-        "{VOD_HOST}/?Action=ApplyUploadInner&Version=2020-11-19&SpaceName={space_name}&FileType=video&IsInner=1&FileSize={file_size}&s={random_str}"
-    );
-
-    let req_headers = vec![
-        ("x-amz-date", timestamp.as_str()),
-        ("x-amz-security-token", session_tok),
-    ];
-    let authorization = aws4_signature("GET", &apply_url, &req_headers, access_key, secret_key, Some(session_tok), "", "cn-north-1", "vod")?;
-
-    let apply_resp = client.get(&apply_url)
-        .header("authorization", &authorization)
-        .header("origin", "https://jimeng.jianying.com")
-        .header("user-agent", USER_AGENT)
-        .header("x-amz-date", &timestamp)
-        .header("x-amz-security-token", session_tok)
-        .send().await?;
-
-    let apply_text = apply_resp.text().await?;
-    let apply_result: serde_json::Value = serde_json::from_str(&apply_text)
-        .map_err(|e| anyhow::anyhow!("ApplyUploadInner parse error: {e}"))?;
-
-    if let Some(err) = apply_result.pointer("/ResponseMetadata/Error") {
-        bail!("ApplyUploadInner failed: {err}");
-    }
+    /// Upload an image to ImageX and return the image URI.
+    ///
+    /// Flow: get_upload_token(scene=2) → ApplyImageUpload → Upload binary → CommitImageUpload
+    pub async fn upload_image(&mut self, image_data: &[u8]) -> Result<String> {
+        let token_data = self.get_upload_token(2).await?;
+        let imagex = &self.base.imagex;
+        let access_key = token_data.access_key_id.as_str();
+        let secret_key = token_data.secret_access_key.as_str();
+        let session_tok = token_data.session_token.as_str();
+        let service_id = token_data.service_id.as_deref().unwrap_or(DEFAULT_SERVICE_ID);
+
+        let file_size = image_data.len();
+        let random_str: String = (0..10).map(|_| rand::random::<char>()).collect::<String>()
+            .chars().filter(|c| c.is_alphanumeric()).take(10).collect();
+        let random_str = if random_str.len() < 5 { uuid::Uuid::new_v4().to_string()[..10].to_string() } else { random_str };
+
+        // Step 1: ApplyImageUpload
+        let timestamp = aws_timestamp();
+        let apply_url = format!(
+            "{imagex}/?Action=ApplyImageUpload&Version=2018-08-01&ServiceId={service_id}&FileSize={file_size}&s={random_str}"
+        );
+
+        let req_headers = vec![
+            ("x-amz-date", timestamp.as_str()),
+            ("x-amz-security-token", session_tok),
+        ];
+        let authorization = aws4_signature("GET", &apply_url, &req_headers, access_key, secret_key, Some(session_tok), "", "cn-north-1", "imagex")?;
+
+        let apply_headers = header_map(&[
+            ("accept", "*/*"),
+            ("authorization", &authorization),
+            ("origin", "https://jimeng.jianying.com"),
+            ("referer", "https://jimeng.jianying.com/ai-tool/video/generate"),
+            ("user-agent", &self.fingerprint.user_agent),
+            ("x-amz-date", &timestamp),
+            ("x-amz-security-token", session_tok),
+        ])?;
+        let (apply_status, apply_text) = self.storage_request(Method::GET, &apply_url, apply_headers, None, 30).await?;
+        let apply_result = storage_response("ApplyImageUpload", apply_status, &apply_text)?;
+
+        let upload_address = apply_result.pointer("/Result/UploadAddress")
+            .ok_or_else(|| storage_error("ApplyImageUpload", "No UploadAddress in ApplyImageUpload response"))?;
+        let store_info = upload_address.pointer("/StoreInfos/0")
+            .ok_or_else(|| storage_error("ApplyImageUpload", "No StoreInfos in upload address"))?;
+        let upload_host = upload_address.pointer("/UploadHosts/0")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| storage_error("ApplyImageUpload", "No UploadHosts"))?;
+        let store_uri = store_info["StoreUri"].as_str().unwrap_or("");
+        let store_auth = store_info["Auth"].as_str().unwrap_or("");
+        let session_key = upload_address["SessionKey"].as_str().unwrap_or("");
+
+        // Step 2: Upload binary
+        let upload_url = format!("https://{upload_host}/upload/v1/{store_uri}");
+        let crc32 = crc32_hex(image_data);
+
+        let upload_headers = header_map(&[
+            ("Authorization", store_auth),
+            ("Content-CRC32", &crc32),
+            ("Content-Disposition", "attachment; filename=\"undefined\""),
+            ("Content-Type", "application/octet-stream"),
+            ("Origin", "https://jimeng.jianying.com"),
+            ("User-Agent", &self.fingerprint.user_agent),
+        ])?;
+        let (upload_status, _) = self.storage_request(Method::POST, &upload_url, upload_headers, Some(image_data.to_vec()), UPLOAD_TIMEOUT_SECS).await?;
+
+        if !(200..300).contains(&upload_status) {
+            return Err(JimengError::Storage {
+                action: "Image upload",
+                code: Some(upload_status.to_string()),
+                message: format!("HTTP {upload_status}"),
+            }.into());
+        }
 
-    let upload_node = apply_result.pointer("/Result/InnerUploadAddress/UploadNodes/0")
-        .ok_or_else(|| anyhow::anyhow!("No upload nodes in VOD response"))?;
-    let store_info = upload_node.pointer("/StoreInfos/0")
-        .ok_or_else(|| anyhow::anyhow!("No StoreInfos in VOD upload node"))?;
-
-    let upload_host = upload_node["UploadHost"].as_str().unwrap_or("");
-    let store_uri = store_info["StoreUri"].as_str().unwrap_or("");
-    let store_auth = store_info["Auth"].as_str().unwrap_or("");
-    let session_key = upload_node["SessionKey"].as_str().unwrap_or("");
-    let vid = upload_node["Vid"].as_str().unwrap_or("");
-
-    // Step 2: Upload binary
-    let upload_url = format!("https://{upload_host}/upload/v1/{store_uri}");
-    let crc32 = crc32_hex(data);
-
-    let upload_resp = client.post(&upload_url)
-        .header("Authorization", store_auth)
-        .header("Content-CRC32", &crc32)
-        .header("Content-Type", "application/octet-stream")
-        .header("Origin", "https://jimeng.jianying.com")
-        .header("User-Agent", USER_AGENT)
-        .body(data.to_vec())
-        .send().await?;
+        // Step 3: CommitImageUpload
+        let commit_url = format!("{imagex}/?Action=CommitImageUpload&Version=2018-08-01&ServiceId={service_id}");
+        let commit_timestamp = aws_timestamp();
+        let commit_payload = serde_json::json!({
+            "SessionKey": session_key,
+            "SuccessActionStatus": "200"
+        }).to_string();
+
+        let payload_hash = hex::encode(Sha256::digest(commit_payload.as_bytes()));
+        let commit_headers = vec![
+            ("x-amz-date", commit_timestamp.as_str()),
+            ("x-amz-security-token", session_tok),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+        ];
+        let commit_auth = aws4_signature("POST", &commit_url, &commit_headers, access_key, secret_key, Some(session_tok), &commit_payload, "cn-north-1", "imagex")?;
+
+        let commit_headers = header_map(&[
+            ("authorization", &commit_auth),
+            ("content-type", "application/json"),
+            ("origin", "https://jimeng.jianying.com"),
+            ("user-agent", &self.fingerprint.user_agent),
+            ("x-amz-date", &commit_timestamp),
+            ("x-amz-security-token", session_tok),
+            ("x-amz-content-sha256", &payload_hash),
+        ])?;
+        let (commit_status, commit_text) = self.storage_request(Method::POST, &commit_url, commit_headers, Some(commit_payload.into_bytes()), 30).await?;
+        let commit_result = storage_response("CommitImageUpload", commit_status, &commit_text)?;
+
+        // Try plugin result first, then direct result
+        if let Some(uri) = commit_result.pointer("/Result/PluginResult/0/ImageUri").and_then(|v| v.as_str()) {
+            return Ok(uri.to_string());
+        }
+        if let Some(uri) = commit_result.pointer("/Result/Results/0/Uri").and_then(|v| v.as_str()) {
+            return Ok(uri.to_string());
+        }
 
-    if !upload_resp.status().is_success() {
-        bail!("VOD upload failed: HTTP {}", upload_resp.status());
+        Err(storage_error("CommitImageUpload", &format!("no URI in response: {commit_text}")).into())
     }
 
-    // Step 3: CommitUploadInner
-    let commit_url = format!("{VOD_HOST}/?Action=CommitUploadInner&Version=2020-11-19&SpaceName={space_name}");
-    let commit_timestamp = aws_timestamp();
-    let commit_payload = serde_json::json!({
-        "SessionKey": session_key,
-        "Functions": []
-    }).to_string();
-
-    let payload_hash = hex::encode(Sha256::digest(commit_payload.as_bytes()));
-    let commit_headers = vec![
-        ("x-amz-date", commit_timestamp.as_str()),
-        ("x-amz-security-token", session_tok),
-        ("x-amz-content-sha256", payload_hash.as_str()),
-    ];
-    let commit_auth = aws4_signature("POST", &commit_url, &commit_headers, access_key, secret_key, Some(session_tok), &commit_payload, "cn-north-1", "vod")?;
-
-    let commit_resp = client.post(&commit_url)
-        .header("authorization", &commit_auth)
-        .header("content-type", "application/json")
-        .header("origin", "https://jimeng.jianying.com")
-        .header("user-agent", USER_AGENT)
-        .header("x-amz-date", &commit_timestamp)
-        .header("x-amz-security-token", session_tok)
-        .header("x-amz-content-sha256", &payload_hash)
-        .body(commit_payload)
-        .send().await?;
-
-    let commit_text = commit_resp.text().await?;
-    let commit_result: serde_json::Value = serde_json::from_str(&commit_text)
-        .map_err(|e| anyhow::anyhow!("CommitUploadInner parse error: {e}"))?;
+    /// Upload video/audio to ByteDance VOD and return the vid + metadata.
+    ///
+    /// Flow: get_upload_token(scene=1) → ApplyUploadInner → Upload binary → CommitUploadInner.
+    /// The file is read from disk; above `PART_SIZE` it goes up in parts
+    /// (see `upload_parts`), reporting each one to `progress`.
+    pub async fn upload_media(&mut self, path: &Path, progress: &(dyn Fn(UploadProgress) + Send + Sync)) -> Result<VodUploadResult> {
+        let file_size = tokio::fs::metadata(path).await
+            .with_context(|| format!("reading {}", path.display()))?
+            .len();
+        let token_data = self.get_upload_token(1).await?;
+        let vod = &self.base.vod;
+        let access_key = token_data.access_key_id.as_str();
+        let secret_key = token_data.secret_access_key.as_str();
+        let session_tok = token_data.session_token.as_str();
+        let space_name = token_data.space_name.as_deref().unwrap_or(DEFAULT_SPACE_NAME);
+
+        let random_str = &uuid::Uuid::new_v4().to_string()[..10];
+
+        // Step 1: ApplyUploadInner
+        let timestamp = aws_timestamp();
+        let apply_url = format!(
+            "{vod}/?Action=ApplyUploadInner&Version=2020-11-19&SpaceName={space_name}&FileType=video&IsInner=1&FileSize={file_size}&s={random_str}"
+        );
+
+        let req_headers = vec![
+            ("x-amz-date", timestamp.as_str()),
+            ("x-amz-security-token", session_tok),
+        ];
+        let authorization = aws4_signature("GET", &apply_url, &req_headers, access_key, secret_key, Some(session_tok), "", "cn-north-1", "vod")?;
+
+        let apply_headers = header_map(&[
+            ("authorization", &authorization),
+            ("origin", "https://jimeng.jianying.com"),
+            ("user-agent", &self.fingerprint.user_agent),
+            ("x-amz-date", &timestamp),
+            ("x-amz-security-token", session_tok),
+        ])?;
+        let (apply_status, apply_text) = self.storage_request(Method::GET, &apply_url, apply_headers, None, 30).await?;
+        let apply_result = storage_response("ApplyUploadInner", apply_status, &apply_text)?;
+
+        let upload_node = apply_result.pointer("/Result/InnerUploadAddress/UploadNodes/0")
+            .ok_or_else(|| storage_error("ApplyUploadInner", "No upload nodes in VOD response"))?;
+        let store_info = upload_node.pointer("/StoreInfos/0")
+            .ok_or_else(|| storage_error("ApplyUploadInner", "No StoreInfos in VOD upload node"))?;
+
+        let upload_host = upload_node["UploadHost"].as_str().unwrap_or("");
+        let store_uri = store_info["StoreUri"].as_str().unwrap_or("");
+        let store_auth = store_info["Auth"].as_str().unwrap_or("");
+        let session_key = upload_node["SessionKey"].as_str().unwrap_or("");
+        let vid = upload_node["Vid"].as_str().unwrap_or("");
+
+        // Step 2: Upload binary
+        let upload_url = format!("https://{upload_host}/upload/v1/{store_uri}");
+        if file_size > PART_SIZE {
+            self.upload_parts(&upload_url, store_auth, path, file_size, progress).await?;
+        } else {
+            let data = tokio::fs::read(path).await
+                .with_context(|| format!("reading {}", path.display()))?;
+            let crc32 = crc32_hex(&data);
+
+            let upload_headers = header_map(&[
+                ("Authorization", store_auth),
+                ("Content-CRC32", &crc32),
+                ("Content-Type", "application/octet-stream"),
+                ("Origin", "https://jimeng.jianying.com"),
+                ("User-Agent", &self.fingerprint.user_agent),
+            ])?;
+            let (upload_status, _) = self.storage_request(Method::POST, &upload_url, upload_headers, Some(data), UPLOAD_TIMEOUT_SECS).await?;
+
+            if !(200..300).contains(&upload_status) {
+                return Err(JimengError::Storage {
+                    action: "VOD upload",
+                    code: Some(upload_status.to_string()),
+                    message: format!("HTTP {upload_status}"),
+                }.into());
+            }
+        }
 
-    if let Some(err) = commit_result.pointer("/ResponseMetadata/Error") {
-        bail!("CommitUploadInner failed: {err}");
+        // Step 3: CommitUploadInner
+        let commit_url = format!("{vod}/?Action=CommitUploadInner&Version=2020-11-19&SpaceName={space_name}");
+        let commit_timestamp = aws_timestamp();
+        let commit_payload = serde_json::json!({
+            "SessionKey": session_key,
+            "Functions": []
+        }).to_string();
+
+        let payload_hash = hex::encode(Sha256::digest(commit_payload.as_bytes()));
+        let commit_headers = vec![
+            ("x-amz-date", commit_timestamp.as_str()),
+            ("x-amz-security-token", session_tok),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+        ];
+        let commit_auth = aws4_signature("POST", &commit_url, &commit_headers, access_key, secret_key, Some(session_tok), &commit_payload, "cn-north-1", "vod")?;
+
+        let commit_headers = header_map(&[
+            ("authorization", &commit_auth),
+            ("content-type", "application/json"),
+            ("origin", "https://jimeng.jianying.com"),
+            ("user-agent", &self.fingerprint.user_agent),
+            ("x-amz-date", &commit_timestamp),
+            ("x-amz-security-token", session_tok),
+            ("x-amz-content-sha256", &payload_hash),
+        ])?;
+        let (commit_status, commit_text) = self.storage_request(Method::POST, &commit_url, commit_headers, Some(commit_payload.into_bytes()), 30).await?;
+        let commit_result = storage_response("CommitUploadInner", commit_status, &commit_text)?;
+
+        let result = commit_result.pointer("/Result/Results/0")
+            .ok_or_else(|| storage_error("CommitUploadInner", "No results in CommitUploadInner response"))?;
+
+        let final_vid = result["Vid"].as_str().unwrap_or(vid).to_string();
+        let video_meta = result.get("VideoMeta").cloned().unwrap_or_default();
+
+        let duration_ms = video_meta.get("Duration")
+            .and_then(|v| v.as_f64())
+            .map(|d| (d * 1000.0) as u32)
+            .unwrap_or(0);
+
+        Ok(VodUploadResult {
+            vid: final_vid,
+            width: video_meta.get("Width").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
+            height: video_meta.get("Height").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
+            duration: duration_ms,
+            fps: video_meta.get("Fps").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
+        })
     }
+}
 
-    let result = commit_result.pointer("/Result/Results/0")
-        .ok_or_else(|| anyhow::anyhow!("No results in CommitUploadInner response"))?;
-
-    let final_vid = result["Vid"].as_str().unwrap_or(vid).to_string();
-    let video_meta = result.get("VideoMeta").cloned().unwrap_or_default();
-
-    let mut duration_ms = video_meta.get("Duration")
-        .and_then(|v| v.as_f64())
-        .map(|d| (d * 1000.0) as u32)
-        .unwrap_or(0);
+impl JimengClient {
+    /// Upload a file to a VOD upload host in `PART_SIZE` parts: `init`
+    /// opens an upload id, each part is sent with its CRC32 (and retried
+    /// alone up to `PART_ATTEMPTS` times), and `finish` lists the parts'
+    /// checksums. Only one part is held in memory at a time. The upload id
+    /// is not kept past the call: an interrupted upload starts over when the
+    /// task runs again.
+    async fn upload_parts(
+        &self,
+        upload_url: &str,
+        store_auth: &str,
+        path: &Path,
+        total: u64,
+        progress: &(dyn Fn(UploadProgress) + Send + Sync),
+    ) -> Result<()> {
+        let headers = |crc32: Option<&str>, content_type: &str| {
+            let mut pairs = vec![
+                ("Authorization", store_auth),
+                ("Content-Type", content_type),
+                ("Origin", "https://jimeng.jianying.com"),
+                ("User-Agent", self.fingerprint.user_agent.as_str()),
+            ];
+            pairs.extend(crc32.map(|crc| ("Content-CRC32", crc)));
+            header_map(&pairs)
+        };
+
+        let init_url = format!("{upload_url}?uploadmode=part&phase=init");
+        let (status, text) = self.storage_request(Method::POST, &init_url, headers(None, "application/octet-stream")?, None, 30).await?;
+        let init = part_response("VOD part init", status, &text)?;
+        let upload_id = init.pointer("/data/uploadid").and_then(|v| v.as_str())
+            .ok_or_else(|| storage_error("VOD part init", &format!("no uploadid in response: {text}")))?
+            .to_string();
+
+        let parts = total.div_ceil(PART_SIZE) as u32;
+        let mut file = tokio::fs::File::open(path).await
+            .with_context(|| format!("reading {}", path.display()))?;
+        let mut checksums = Vec::with_capacity(parts as usize);
+        let mut sent = 0u64;
+        for part in 1..=parts {
+            let len = PART_SIZE.min(total - sent) as usize;
+            let mut chunk = vec![0u8; len];
+            file.read_exact(&mut chunk).await
+                .with_context(|| format!("reading {}", path.display()))?;
+            let crc32 = crc32_hex(&chunk);
+            let part_url = format!("{upload_url}?uploadid={upload_id}&part_number={part}&phase=transfer&part_offset={sent}");
+
+            let mut attempt = 1;
+            loop {
+                let result = match self.storage_request(
+                    Method::POST, &part_url, headers(Some(&crc32), "application/octet-stream")?, Some(chunk.clone()), UPLOAD_TIMEOUT_SECS,
+                ).await {
+                    Ok((status, text)) => part_response("VOD part upload", status, &text).map_err(anyhow::Error::from).and_then(|resp| {
+                        // The host echoes the checksum of what it received.
+                        match resp.pointer("/data/crc32").and_then(|v| v.as_str()) {
+                            Some(echoed) if !echoed.eq_ignore_ascii_case(&crc32) => {
+                                Err(storage_error("VOD part upload", &format!("CRC32 mismatch: sent {crc32}, stored {echoed}")).into())
+                            }
+                            _ => Ok(()),
+                        }
+                    }),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => break,
+                    Err(e) if attempt < PART_ATTEMPTS => {
+                        tracing::warn!(part, parts, attempt, error = %e, "VOD part upload failed, retrying");
+                        tokio::time::sleep(std::time::Duration::from_secs(attempt as u64)).await;
+                        attempt += 1;
+                    }
+                    Err(e) => return Err(e.context(format!("VOD part {part}/{parts} failed after {attempt} attempts"))),
+                }
+            }
+
+            sent += len as u64;
+            checksums.push(format!("{part}:{crc32}"));
+            progress(UploadProgress { part, parts, bytes: sent, total });
+        }
 
-    // Fallback: parse WAV duration locally for audio
-    if duration_ms == 0 && media_type == MaterialType::Audio {
-        duration_ms = parse_audio_duration(data);
+        let finish_url = format!("{upload_url}?uploadmode=part&phase=finish&uploadid={upload_id}");
+        let (status, text) = self.storage_request(
+            Method::POST, &finish_url, headers(None, "text/plain;charset=UTF-8")?, Some(checksums.join(",").into_bytes()), 60,
+        ).await?;
+        part_response("VOD part finish", status, &text)?;
+        Ok(())
     }
-
-    Ok(VodUploadResult {
-        vid: final_vid,
-        width: video_meta.get("Width").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
-        height: video_meta.get("Height").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
-        duration: duration_ms,
-        fps: video_meta.get("Fps").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
-    })
 }
 
-/// Parse WAV audio duration from header. Returns duration in milliseconds.
-fn parse_audio_duration(data: &[u8]) -> u32 {
-    if data.len() < 44 {
-        return 0;
-    }
-    // Check RIFF...WAVE magic
-    if &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
-        // Not WAV, estimate at 128kbps
-        return ((data.len() as f64) / (128.0 * 1000.0 / 8.0) * 1000.0) as u32;
-    }
-    let byte_rate = u32::from_le_bytes([data[28], data[29], data[30], data[31]]);
-    if byte_rate == 0 {
-        return 0;
-    }
-    // Find data chunk
-    let mut offset = 12;
-    while offset + 8 < data.len() {
-        let chunk_id = &data[offset..offset + 4];
-        let chunk_size = u32::from_le_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]);
-        if chunk_id == b"data" {
-            return ((chunk_size as f64) / (byte_rate as f64) * 1000.0) as u32;
-        }
-        offset += 8 + chunk_size as usize;
+/// Read an upload host's response to a part request: `{"code": 2000, ...}`
+/// on success.
+fn part_response(action: &'static str, status: u16, text: &str) -> Result<serde_json::Value, JimengError> {
+    let value: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| storage_error(action, &format!("unreadable response (HTTP {status}): {e}")))?;
+    match value.get("code").and_then(|c| c.as_i64()) {
+        Some(2000) if (200..300).contains(&status) => Ok(value),
+        code => Err(JimengError::Storage {
+            action,
+            code: Some(code.map_or_else(|| status.to_string(), |c| c.to_string())),
+            message: value.get("message").and_then(|m| m.as_str()).map_or_else(|| format!("HTTP {status}"), str::to_string),
+        }),
     }
-    // Fallback
-    (((data.len() - 44) as f64) / (byte_rate as f64) * 1000.0) as u32
 }
 
 /// Download file from URL and return its bytes.
@@ -461,3 +531,26 @@ pub async fn download_file(client: &Client, url: &str) -> Result<Vec<u8>> {
     }
     Ok(resp.bytes().await?.to_vec())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part_response() {
+        let ok = part_response("VOD part upload", 200, r#"{"code":2000,"data":{"crc32":"0a1b2c3d"}}"#).unwrap();
+        assert_eq!(ok.pointer("/data/crc32").unwrap(), "0a1b2c3d");
+
+        let rejected = part_response("VOD part upload", 200, r#"{"code":4004,"message":"crc32 mismatch"}"#).unwrap_err();
+        assert!(matches!(&rejected, JimengError::Storage { code: Some(c), message, .. } if c == "4004" && message == "crc32 mismatch"));
+
+        // A success code under an error status still fails.
+        let status = part_response("VOD part init", 502, r#"{"code":2000}"#).unwrap_err();
+        assert!(matches!(&status, JimengError::Storage { code: Some(c), .. } if c == "2000"));
+
+        let no_code = part_response("VOD part finish", 500, r#"{"error":"oops"}"#).unwrap_err();
+        assert!(matches!(&no_code, JimengError::Storage { code: Some(c), message, .. } if c == "500" && message == "HTTP 500"));
+
+        assert!(part_response("VOD part finish", 200, "<html>").is_err());
+    }
+}
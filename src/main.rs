@@ -1,5 +1,7 @@
 mod auth;
 mod config;
+mod cookie_refresh;
+mod crypto;
 mod db;
 mod jimeng;
 mod pool;
@@ -25,10 +27,14 @@ use crate::auth::backend::PasswordBackend;
 use crate::auth::middleware::api_key_auth;
 use crate::auth::rate_limiter::RateLimiter;
 use crate::config::Config;
+use crate::cookie_refresh::RefreshScheduler;
 use crate::db::Database;
 use crate::jimeng::browser::BrowserService;
-use crate::pool::SessionPool;
-use crate::queue::TaskQueue;
+use crate::jimeng::client::JimengClient;
+use crate::jimeng::client_profile::ClientProfiles;
+use crate::jimeng::model_registry::ModelRegistry;
+use crate::pool::{Pacing, SessionInfo, SessionPool};
+use crate::queue::{TaskQueue, UploadSpool};
 
 /// Shared application state accessible from all route handlers.
 pub struct AppState {
@@ -36,8 +42,30 @@ pub struct AppState {
     pub db: Database,
     pub pool: SessionPool,
     pub queue: TaskQueue,
-    pub browser: BrowserService,
+    pub browser: Arc<BrowserService>,
+    pub refresh: RefreshScheduler,
     pub rate_limiter: RateLimiter,
+    pub client_profiles: ClientProfiles,
+    pub models: ModelRegistry,
+}
+
+impl AppState {
+    /// A jimeng client for `session` with the configured base URLs and
+    /// transports and the session's client profile. `http` should route
+    /// through the session's proxy.
+    pub fn jimeng_client(&self, session: &SessionInfo, http: reqwest::Client) -> JimengClient {
+        JimengClient::new(
+            http,
+            self.config.base_urls.clone(),
+            &session.session_id,
+            session.cookie_jar.clone(),
+            session.fingerprint.clone(),
+            session.proxy_url.clone(),
+        )
+        .with_transports(self.config.transports.clone(), session.transport_prefs.clone())
+        .with_profile(self.client_profiles.get(session.client_profile.as_deref()))
+        .with_browser(self.browser.clone())
+    }
 }
 
 #[tokio::main]
@@ -50,27 +78,46 @@ async fn main() -> anyhow::Result<()> {
 
     dotenvy::dotenv().ok();
     let config = Config::from_env()?;
+
+    if std::env::args().nth(1).as_deref() == Some("rotate-keys") {
+        return rotate_keys_command(&config).await;
+    }
+
     tracing::info!(
         port = config.port,
         auth_enabled = config.auth_enabled,
         "Starting jimeng-gateway"
     );
 
+    // A malformed profile or model file must stop startup, not degrade requests.
+    let client_profiles = ClientProfiles::load(config.client_profiles_file.as_ref().map(Into::into))?;
+    let models = ModelRegistry::load(config.models_file.as_ref().map(Into::into))?;
+
     // Initialize subsystems
     let db = Database::connect(&config.database_url).await?;
     db.migrate().await?;
     db.recover_on_startup().await?;
 
-    let pool = SessionPool::new(db.clone());
+    // Secrets at rest: unwrap data keys, then seal any legacy plaintext rows
+    crypto::init_keyring(&db.pool, crypto::master_key_from_env("MASTER_KEY")?).await?;
+    crypto::seal_plaintext_rows(&db.pool).await?;
+
+    let pool = SessionPool::new(db.clone(), Pacing {
+        min_interval_secs: config.submit_min_interval_secs,
+        jitter_secs: config.submit_jitter_secs,
+        daily_cap: config.session_daily_submit_cap,
+    });
     pool.load_sessions().await?;
 
-    let browser = BrowserService::new(config.chromium_path.clone());
+    let browser = Arc::new(BrowserService::new(config.chromium_path.clone()));
     let queue = TaskQueue::new(
         db.clone(),
         pool.clone(),
         config.concurrency,
+        UploadSpool::new(&config.upload_dir),
     );
 
+    let refresh = RefreshScheduler::new(config.cookie_refresh_concurrency, config.cookie_refresh_max_age_mins);
     let rate_limiter = RateLimiter::new();
 
     let state = Arc::new(AppState {
@@ -79,7 +126,10 @@ async fn main() -> anyhow::Result<()> {
         pool,
         queue,
         browser,
+        refresh,
         rate_limiter,
+        client_profiles,
+        models,
     });
 
     // Start background workers
@@ -108,10 +158,13 @@ async fn main() -> anyhow::Result<()> {
         webhook::dispatcher_loop(webhook_pool).await;
     });
 
-    // Spawn periodic cookie refresh (every 2 hours)
-    let cookie_state = state.clone();
-    let cookie_task = tokio::task::spawn(async move {
-        cookie_refresh_loop(cookie_state).await;
+    // Spawn cookie refresh scheduler
+    let cookie_task = tokio::task::spawn(cookie_refresh::run(state.clone()));
+
+    // Spawn session expiry watcher
+    let expiry_state = state.clone();
+    let expiry_task = tokio::task::spawn(async move {
+        session_expiry_loop(expiry_state).await;
     });
 
     // Build router
@@ -157,43 +210,76 @@ async fn main() -> anyhow::Result<()> {
     deletion_task.abort();
     webhook_task.abort();
     cookie_task.abort();
+    expiry_task.abort();
 
     Ok(())
 }
 
-/// Periodically refresh cookie jars for all enabled sessions.
-async fn cookie_refresh_loop(state: Arc<AppState>) {
-    // Wait 30s on startup before first harvest
-    tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+/// `jimeng-gateway rotate-keys`: re-encrypt all secrets under a fresh data key.
+/// With `NEW_MASTER_KEY` / `NEW_MASTER_KEY_FILE` set, the new data key is
+/// wrapped by that master key instead (switch `MASTER_KEY` afterwards).
+async fn rotate_keys_command(config: &Config) -> anyhow::Result<()> {
+    let master = crypto::master_key_from_env("MASTER_KEY")?
+        .ok_or_else(|| anyhow::anyhow!("rotate-keys requires MASTER_KEY or MASTER_KEY_FILE"))?;
+    let new_master = crypto::master_key_from_env("NEW_MASTER_KEY")?;
 
-    let interval = tokio::time::Duration::from_secs(2 * 3600); // 2 hours
-    loop {
-        let sessions = state.pool.list_sessions().await;
-        let active: Vec<_> = sessions.iter().filter(|s| s.enabled).collect();
+    let db = Database::connect(&config.database_url).await?;
+    db.migrate().await?;
+    let count = crypto::rotate_keys(&db.pool, &master, new_master.as_ref()).await?;
 
-        if active.is_empty() {
-            tokio::time::sleep(interval).await;
-            continue;
-        }
+    println!("Re-encrypted {count} values.");
+    if new_master.is_some() {
+        println!("Master key rotated: set MASTER_KEY to the new key before restarting.");
+    }
+    Ok(())
+}
+
+/// Watch session expiry (from `sid_guard`): emit `session.expiring` events
+/// ahead of time and disable sessions shortly before they expire.
+async fn session_expiry_loop(state: Arc<AppState>) {
+    let interval = tokio::time::Duration::from_secs(600);
+    let warn_within = chrono::Duration::days(state.config.session_expiry_warn_days.max(0));
+    let disable_within = chrono::Duration::hours(state.config.session_expiry_disable_hours.max(0));
 
-        tracing::info!("CookieRefresh: refreshing cookies for {} sessions", active.len());
-
-        for session in &active {
-            match state.browser.harvest_cookies(&session.session_id).await {
-                Ok(cookie_jar) => {
-                    let count = cookie_jar.split("; ").count();
-                    if let Err(e) = state.pool.update_cookie_jar(&session.id, &cookie_jar).await {
-                        tracing::warn!(session_id = session.id, error = %e, "Failed to save harvested cookies");
-                    } else {
-                        tracing::info!(session_id = session.id, cookies = count, "Cookies refreshed");
-                    }
+    loop {
+        let now = chrono::Utc::now();
+        for session in state.pool.list_sessions().await.iter().filter(|s| s.enabled) {
+            let Some(expires_at) = session.expires_at.as_deref() else { continue };
+            let Ok(expires) = chrono::NaiveDateTime::parse_from_str(expires_at, "%Y-%m-%d %H:%M:%S") else {
+                continue;
+            };
+            let remaining = expires.and_utc() - now;
+
+            if remaining <= warn_within
+                && let Some(url) = state.config.events_webhook_url.as_deref()
+            {
+                let enqueued = webhook::enqueue_event(
+                    &state.db.pool,
+                    url,
+                    state.config.events_webhook_secret.as_deref(),
+                    &format!("session.expiring:{}:{expires_at}", session.id),
+                    "session.expiring",
+                    serde_json::json!({
+                        "session": {
+                            "id": session.id,
+                            "label": session.label,
+                            "expires_at": expires_at,
+                            "remaining_hours": remaining.num_hours(),
+                        },
+                    }),
+                ).await;
+                if let Err(e) = enqueued {
+                    tracing::warn!(id = session.id, error = %e, "Failed to enqueue session expiry event");
                 }
-                Err(e) => {
-                    tracing::warn!(session_id = session.id, error = %e, "Cookie harvest failed");
+            }
+
+            if remaining <= disable_within {
+                let reason = format!("session expires at {expires_at} UTC; disabled preemptively");
+                match state.pool.disable_session(&session.id, &reason).await {
+                    Ok(()) => tracing::warn!(session_id = session.id, expires_at, "Session disabled before expiry"),
+                    Err(e) => tracing::warn!(session_id = session.id, error = %e, "Failed to disable expiring session"),
                 }
             }
-            // Small delay between sessions to avoid overwhelming the browser
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
         }
 
         tokio::time::sleep(interval).await;
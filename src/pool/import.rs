@@ -0,0 +1,150 @@
+//! Parsing of cookie exports for bulk session import.
+//!
+//! Accepted inputs:
+//! - Netscape `cookies.txt` (tab-separated, `#HttpOnly_` prefixes allowed)
+//! - Browser-extension JSON exports (`[{name, value, domain, ...}]`, or an
+//!   object with a `cookies` array)
+//! - A raw `Cookie` header string (`a=1; b=2`)
+//! - The gateway's own stored jar (a JSON list of cookies)
+
+use anyhow::{bail, Result};
+
+use crate::jimeng::cookies::{Cookie, CookieJar};
+
+/// Only cookies for this domain (and subdomains) are kept.
+const COOKIE_DOMAIN: &str = "jianying.com";
+
+/// Cookie names that carry the session token, in order of preference.
+const SESSION_COOKIES: &[&str] = &["sessionid", "sessionid_ss", "sid_tt"];
+
+/// Convert a cookie export into a cookie jar, keeping only jianying cookies.
+pub fn parse_cookies(input: &serde_json::Value) -> Result<CookieJar> {
+    let cookies = match input {
+        serde_json::Value::String(text) if is_netscape(text) => parse_netscape(text),
+        serde_json::Value::String(text) if text.trim_start().starts_with('[') => {
+            CookieJar::parse(text)?.into_iter().collect()
+        }
+        serde_json::Value::String(text) => CookieJar::from_header(text).into_iter().collect(),
+        serde_json::Value::Array(items) => parse_json_cookies(items),
+        serde_json::Value::Object(obj) => match obj.get("cookies") {
+            Some(serde_json::Value::Array(items)) => parse_json_cookies(items),
+            _ => bail!("cookie JSON object has no `cookies` array"),
+        },
+        _ => bail!("unsupported cookie format"),
+    };
+
+    // Later duplicates win; expired cookies are dropped.
+    let jar = CookieJar::from_cookies(cookies.into_iter().filter(|c| domain_matches(&c.domain)));
+    if jar.is_empty() {
+        bail!("no {COOKIE_DOMAIN} cookies found");
+    }
+    Ok(jar)
+}
+
+/// Pull the jimeng session token out of a cookie jar.
+pub fn session_id_from_jar(jar: &CookieJar) -> Option<String> {
+    SESSION_COOKIES
+        .iter()
+        .find_map(|name| jar.get(name).filter(|v| !v.is_empty()))
+        .map(str::to_string)
+}
+
+fn domain_matches(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    domain.is_empty() || domain == COOKIE_DOMAIN || domain.ends_with(&format!(".{COOKIE_DOMAIN}"))
+}
+
+fn is_netscape(text: &str) -> bool {
+    text.lines()
+        .any(|line| line.trim_start_matches("#HttpOnly_").split('\t').count() >= 7)
+}
+
+fn parse_netscape(text: &str) -> Vec<Cookie> {
+    text.lines()
+        .filter_map(|line| {
+            let http_only = line.starts_with("#HttpOnly_");
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            Some(Cookie {
+                domain: fields[0].to_string(),
+                path: fields[2].to_string(),
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                // 0 marks a session cookie.
+                expires: fields[4].parse::<i64>().ok().filter(|&e| e > 0),
+                http_only,
+                ..Cookie::new(fields[5], fields[6])
+            })
+        })
+        .collect()
+}
+
+/// Browser-extension exports (`expirationDate`, float seconds) and the
+/// gateway's own format (`expires`) share the other field names.
+fn parse_json_cookies(items: &[serde_json::Value]) -> Vec<Cookie> {
+    items
+        .iter()
+        .filter_map(|c| {
+            let name = c.get("name")?.as_str()?;
+            let value = c.get("value")?.as_str()?;
+            let mut cookie = Cookie::new(name, value);
+            if let Some(domain) = c.get("domain").and_then(|d| d.as_str()).filter(|d| !d.is_empty()) {
+                cookie.domain = domain.to_string();
+            }
+            if let Some(path) = c.get("path").and_then(|p| p.as_str()).filter(|p| p.starts_with('/')) {
+                cookie.path = path.to_string();
+            }
+            cookie.expires = c
+                .get("expirationDate")
+                .or_else(|| c.get("expires"))
+                .and_then(|e| e.as_f64())
+                .filter(|&e| e > 0.0)
+                .map(|e| e as i64);
+            cookie.http_only = c.get("httpOnly").and_then(|v| v.as_bool()).unwrap_or(false);
+            cookie.secure = c.get("secure").and_then(|v| v.as_bool()).unwrap_or(false);
+            Some(cookie)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_netscape() {
+        let text = "# Netscape HTTP Cookie File\n\
+                    .jianying.com\tTRUE\t/\tFALSE\t1999999999\tsid_tt\tabc\n\
+                    #HttpOnly_.jianying.com\tTRUE\t/\tTRUE\t1999999999\tsessionid\tabc\n\
+                    .example.com\tTRUE\t/\tFALSE\t1999999999\tother\tx\n";
+        let jar = parse_cookies(&serde_json::json!(text)).unwrap();
+        assert_eq!(jar.header_for("jimeng.jianying.com", "/"), "sid_tt=abc; sessionid=abc");
+        assert_eq!(session_id_from_jar(&jar).as_deref(), Some("abc"));
+        assert!(jar.iter().find(|c| c.name == "sessionid").unwrap().http_only);
+    }
+
+    #[test]
+    fn test_parse_browser_json() {
+        let export = serde_json::json!([
+            { "name": "sessionid", "value": "tok", "domain": ".jianying.com" },
+            { "name": "ttwid", "value": "t1", "domain": "jimeng.jianying.com" },
+            { "name": "foo", "value": "bar", "domain": ".example.com" },
+        ]);
+        let header = |jar: CookieJar| jar.header_for("jimeng.jianying.com", "/");
+        assert_eq!(header(parse_cookies(&export).unwrap()), "sessionid=tok; ttwid=t1");
+        let wrapped = serde_json::json!({ "cookies": export });
+        assert_eq!(header(parse_cookies(&wrapped).unwrap()), "sessionid=tok; ttwid=t1");
+    }
+
+    #[test]
+    fn test_parse_header_and_fallback_session_cookie() {
+        let jar = parse_cookies(&serde_json::json!("sid_tt=xyz; ttwid=1")).unwrap();
+        assert_eq!(session_id_from_jar(&jar).as_deref(), Some("xyz"));
+        assert!(parse_cookies(&serde_json::json!([])).is_err());
+    }
+}
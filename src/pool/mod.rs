@@ -1,58 +1,190 @@
+pub mod import;
+mod pacing;
 mod session;
+pub mod stats;
 
-pub use session::SessionInfo;
+pub use pacing::Pacing;
 
+pub use session::{DrainAction, NewSession, Removal, SessionInfo, normalize_tags};
+
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use anyhow::Result;
 use tokio::sync::RwLock;
 
+use crate::crypto;
 use crate::db::Database;
+use crate::jimeng::auth::{self, Fingerprint};
+use crate::jimeng::cookies::{Cookie, CookieJar};
+use crate::jimeng::client::JimengClient;
+use crate::jimeng::profile::AccountProfile;
+use crate::jimeng::transport::{Endpoint, TransportKind};
+
+/// Session expiry (UTC, SQLite datetime format) derived from a cookie jar.
+fn expiry_for(cookie_jar: Option<&CookieJar>) -> Option<String> {
+    cookie_jar
+        .and_then(auth::session_expiry_from_jar)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// Cookie refresh history rows kept per session.
+const REFRESH_HISTORY_LIMIT: i64 = 50;
+
+/// One entry of a session's cookie refresh history.
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct CookieRefreshRecord {
+    /// What caused the refresh (`scheduled`, `added`, `auth_failure`, `manual`).
+    pub trigger: String,
+    /// `ok` or `failed`.
+    pub status: String,
+    pub cookie_count: Option<i64>,
+    pub error: Option<String>,
+    pub created_at: String,
+}
+
+/// Queue reason of tasks waiting because every capable session has hit
+/// jimeng's daily usage limit.
+pub const QUOTA_BLOCKED_REASON: &str = "blocked on upstream quota";
+
+/// Columns selected whenever a full `SessionInfo` row is read.
+const SESSION_COLUMNS: &str = "id, label, session_id, enabled, healthy, active_tasks, total_tasks, \
+     success_count, fail_count, last_used_at, last_error, cookie_jar, tags, fingerprint, proxy_url, expires_at, \
+     cookies_refreshed_at, cookie_refresh_attempted_at, cookie_refresh_failures, \
+     submit_interval_secs, daily_submit_cap, next_submit_at, daily_submits, daily_submits_date, \
+     quota_exhausted_until, draining, profile, transport_prefs, client_profile, created_at, updated_at";
+
+/// A session may take a submit: its pacing interval has passed and it is
+/// under today's cap. Binds: ?2 today, ?5 the pool's daily cap.
+const SUBMIT_OPEN: &str = "(next_submit_at IS NULL OR next_submit_at <= datetime('now')) \
+     AND (COALESCE(daily_submit_cap, ?5) = 0 OR daily_submits_date IS NOT ?2 \
+          OR daily_submits < COALESCE(daily_submit_cap, ?5))";
+
+/// Count a submit and start the next pacing interval. Binds: ?2 today,
+/// ?3 the pool's interval, ?4 this interval's jitter.
+const RESERVE_SUBMIT: &str = "next_submit_at = CASE WHEN COALESCE(submit_interval_secs, ?3) > 0 \
+         THEN datetime('now', '+' || (COALESCE(submit_interval_secs, ?3) + ?4) || ' seconds') END, \
+     daily_submits = CASE WHEN daily_submits_date = ?2 THEN daily_submits + 1 ELSE 1 END, \
+     daily_submits_date = ?2";
 
 #[derive(Debug, Clone)]
 pub struct SessionPool {
     db: Database,
     sessions: Arc<RwLock<Vec<SessionInfo>>>,
+    pacing: Pacing,
 }
 
 impl SessionPool {
-    pub fn new(db: Database) -> Self {
+    pub fn new(db: Database, pacing: Pacing) -> Self {
         Self {
             db,
             sessions: Arc::new(RwLock::new(Vec::new())),
+            pacing,
         }
     }
 
-    /// Load all sessions from database into memory.
+    /// Load all sessions from database into memory. Their credentials stay
+    /// sealed there (see `SessionInfo::open_secrets`).
     pub async fn load_sessions(&self) -> Result<()> {
-        let rows = sqlx::query_as::<_, SessionInfo>(
-            "SELECT id, label, session_id, enabled, healthy, active_tasks, total_tasks, \
-             success_count, fail_count, last_used_at, last_error, cookie_jar, created_at, updated_at \
-             FROM sessions ORDER BY created_at"
-        )
+        let mut rows = sqlx::query_as::<_, SessionInfo>(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions ORDER BY created_at"
+        ))
         .fetch_all(&self.db.pool)
         .await?;
+        let cookie_jar = |session: &SessionInfo| match session.open_secrets() {
+            Ok(opened) => opened.cookie_jar,
+            Err(e) => {
+                tracing::error!(id = session.id, error = %e, "Failed to decrypt session");
+                None
+            }
+        };
+
+        for session in rows.iter_mut().filter(|s| s.fingerprint.is_empty()) {
+            // Sessions created before fingerprints were persisted get one now.
+            session.fingerprint = Fingerprint::for_cookie_jar(cookie_jar(session).as_ref());
+            sqlx::query("UPDATE sessions SET fingerprint = ? WHERE id = ?")
+                .bind(serde_json::to_string(&session.fingerprint)?)
+                .bind(&session.id)
+                .execute(&self.db.pool)
+                .await?;
+            tracing::info!(id = session.id, "Generated fingerprint for session");
+        }
+        for session in rows.iter_mut().filter(|s| s.expires_at.is_none()) {
+            // Jars stored before expiry tracking existed.
+            let Some(expires_at) = expiry_for(cookie_jar(session).as_ref()) else { continue };
+            sqlx::query("UPDATE sessions SET expires_at = ? WHERE id = ?")
+                .bind(&expires_at)
+                .bind(&session.id)
+                .execute(&self.db.pool)
+                .await?;
+            session.expires_at = Some(expires_at);
+        }
 
         let count = rows.len();
+        // Tasks don't survive a restart on their session, so drains are done.
+        let drained: Vec<String> = rows.iter().filter(|s| s.draining.is_some()).map(|s| s.id.clone()).collect();
         *self.sessions.write().await = rows;
         tracing::info!(count, "Loaded sessions into pool");
+
+        for id in drained {
+            if let Err(e) = self.finish_drain(&id).await {
+                tracing::error!(id, error = %e, "Failed to finish session drain");
+            }
+        }
         Ok(())
     }
 
     /// Pick the best available session using atomic DB-level CAS.
-    pub async fn pick_session(&self) -> Option<SessionInfo> {
+    ///
+    /// Only sessions carrying every tag in `required` are considered
+    /// (see `models::required_capabilities`), and only once their pacing
+    /// interval has passed, they are under today's submit cap and not out
+    /// of upstream quota. Picking reserves the task's first submit: it starts
+    /// the next interval and counts against the cap, so a second task cannot
+    /// take the session before it. `unreserve_submit` gives the slot back if
+    /// the task sends nothing.
+    pub async fn pick_session(&self, required: &[&str]) -> Option<SessionInfo> {
+        let required_json = serde_json::to_string(required).ok()?;
+
         // Atomic pick + reserve: single SQL statement prevents race conditions
-        let row = sqlx::query_as::<_, SessionInfo>(
-            "UPDATE sessions SET active_tasks = active_tasks + 1, \
+        let row = sqlx::query_as::<_, SessionInfo>(&format!(
+            "UPDATE sessions SET active_tasks = active_tasks + 1, {RESERVE_SUBMIT}, \
              last_used_at = datetime('now'), updated_at = datetime('now') \
              WHERE id = (SELECT id FROM sessions WHERE enabled=1 AND healthy=1 AND active_tasks < 2 \
+                         AND draining IS NULL AND {SUBMIT_OPEN} \
+                         AND (quota_exhausted_until IS NULL OR quota_exhausted_until <= datetime('now')) \
+                         AND NOT EXISTS (SELECT 1 FROM json_each(?1) req \
+                                         WHERE req.value NOT IN (SELECT value FROM json_each(sessions.tags) \
+                                                                 UNION SELECT value FROM json_each( \
+                                                                     COALESCE(sessions.profile, '{{}}'), '$.capabilities'))) \
                          ORDER BY last_used_at LIMIT 1) \
-             RETURNING id, label, session_id, enabled, healthy, active_tasks, total_tasks, \
-                       success_count, fail_count, last_used_at, last_error, cookie_jar, created_at, updated_at",
-        )
+             RETURNING {SESSION_COLUMNS}"
+        ))
+        .bind(&required_json)
+        .bind(pacing::today())
+        .bind(self.pacing.min_interval_secs)
+        .bind(self.pacing.jitter())
+        .bind(self.pacing.daily_cap)
         .fetch_optional(&self.db.pool)
         .await
         .ok()?;
+        let row = match row {
+            Some(row) => {
+                let id = row.id.clone();
+                match row.open_secrets() {
+                    Ok(row) => Some(row),
+                    Err(e) => {
+                        // Undo the reservation and keep the session out of rotation.
+                        tracing::error!(session_id = id, error = %e, "Failed to decrypt picked session");
+                        let _ = self.unreserve_submit(&row).await;
+                        let _ = self.release_session(&id, false, Some(&e.to_string())).await;
+                        let _ = self.mark_unhealthy(&id).await;
+                        return None;
+                    }
+                }
+            }
+            None => None,
+        };
 
         if let Some(ref session) = row {
             // Sync in-memory cache
@@ -60,12 +192,127 @@ impl SessionPool {
             if let Some(s) = sessions.iter_mut().find(|s| s.id == session.id) {
                 s.active_tasks = session.active_tasks;
                 s.last_used_at = session.last_used_at.clone();
+                s.next_submit_at = session.next_submit_at.clone();
+                s.daily_submits = session.daily_submits;
+                s.daily_submits_date = session.daily_submits_date.clone();
             }
         }
 
         row
     }
 
+    /// Reserve another submit on a session the task already holds (a
+    /// resubmit), waiting for its pacing interval to pass. Fails once the
+    /// session has reached today's submit cap.
+    pub async fn reserve_submit(&self, id: &str) -> Result<()> {
+        loop {
+            let reserved = sqlx::query_as::<_, (Option<String>, i32, Option<String>)>(&format!(
+                "UPDATE sessions SET {RESERVE_SUBMIT}, updated_at = datetime('now') \
+                 WHERE id = ?1 AND {SUBMIT_OPEN} \
+                 RETURNING next_submit_at, daily_submits, daily_submits_date"
+            ))
+            .bind(id)
+            .bind(pacing::today())
+            .bind(self.pacing.min_interval_secs)
+            .bind(self.pacing.jitter())
+            .bind(self.pacing.daily_cap)
+            .fetch_optional(&self.db.pool)
+            .await?;
+            if let Some(counters) = reserved {
+                self.sync_submits(id, counters).await;
+                return Ok(());
+            }
+
+            let (next_submit_at, capped): (Option<String>, bool) = sqlx::query_as(
+                "SELECT next_submit_at, COALESCE(daily_submit_cap, ?3) > 0 AND daily_submits_date IS ?2 \
+                        AND daily_submits >= COALESCE(daily_submit_cap, ?3) \
+                 FROM sessions WHERE id = ?1",
+            )
+            .bind(id)
+            .bind(pacing::today())
+            .bind(self.pacing.daily_cap)
+            .fetch_optional(&self.db.pool)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("session {id} was removed"))?;
+            if capped {
+                anyhow::bail!("daily submit cap reached on session {id}");
+            }
+            let wait = next_submit_at
+                .and_then(|t| chrono::NaiveDateTime::parse_from_str(&t, "%Y-%m-%d %H:%M:%S").ok())
+                .map(|t| (t.and_utc() - chrono::Utc::now()).num_milliseconds())
+                .unwrap_or(0);
+            tokio::time::sleep(std::time::Duration::from_millis(wait.clamp(100, 60_000) as u64)).await;
+        }
+    }
+
+    /// Give back the submit slot `pick_session` reserved for a task that
+    /// ended without sending anything. `picked` is the session as picked.
+    pub async fn unreserve_submit(&self, picked: &SessionInfo) -> Result<()> {
+        let counters = sqlx::query_as::<_, (Option<String>, i32, Option<String>)>(
+            "UPDATE sessions SET \
+             next_submit_at = CASE WHEN next_submit_at IS ?2 THEN NULL ELSE next_submit_at END, \
+             daily_submits = CASE WHEN daily_submits_date IS ?3 THEN MAX(0, daily_submits - 1) ELSE daily_submits END, \
+             updated_at = datetime('now') \
+             WHERE id = ?1 \
+             RETURNING next_submit_at, daily_submits, daily_submits_date",
+        )
+        .bind(&picked.id)
+        .bind(&picked.next_submit_at)
+        .bind(&picked.daily_submits_date)
+        .fetch_optional(&self.db.pool)
+        .await?;
+        if let Some(counters) = counters {
+            self.sync_submits(&picked.id, counters).await;
+        }
+        Ok(())
+    }
+
+    async fn sync_submits(&self, id: &str, (next_submit_at, daily_submits, daily_submits_date): (Option<String>, i32, Option<String>)) {
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.next_submit_at = next_submit_at;
+            s.daily_submits = daily_submits;
+            s.daily_submits_date = daily_submits_date;
+        }
+    }
+
+    /// Explain why `pick_session` found nothing, for the task's queue reason.
+    pub async fn unavailable_reason(&self, required: &[&str]) -> String {
+        let sessions = self.sessions.read().await;
+        let usable: Vec<_> = sessions.iter().filter(|s| s.enabled && s.healthy && s.draining.is_none()).collect();
+        if usable.is_empty() {
+            return "no enabled healthy session".to_string();
+        }
+        let capable: Vec<_> = usable.into_iter().filter(|s| s.has_capabilities(required)).collect();
+        if capable.is_empty() {
+            return format!("no session with capabilities [{}]", required.join(", "));
+        }
+
+        let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let with_quota: Vec<_> = capable
+            .into_iter()
+            .filter(|s| s.quota_exhausted_until.as_deref().is_none_or(|t| t <= now.as_str()))
+            .collect();
+        if with_quota.is_empty() {
+            return QUOTA_BLOCKED_REASON.to_string();
+        }
+
+        let today = pacing::today();
+        let capped = |s: &SessionInfo| {
+            let cap = s.daily_submit_cap.map_or(self.pacing.daily_cap as i32, |c| c.max(0));
+            cap > 0 && s.daily_submits_date.as_deref() == Some(today.as_str()) && s.daily_submits >= cap
+        };
+        let open: Vec<_> = with_quota.into_iter().filter(|s| !capped(s)).collect();
+        if open.is_empty() {
+            return "daily submit cap reached on every capable session".to_string();
+        }
+        let cooling = |s: &SessionInfo| s.next_submit_at.as_deref().is_some_and(|t| t > now.as_str());
+        if open.iter().any(|s| s.active_tasks < 2 && cooling(s)) {
+            return "waiting for session cooldown".to_string();
+        }
+        "all capable sessions are busy".to_string()
+    }
+
     /// No-op: pick_session() atomically increments active_tasks.
     pub async fn mark_active(&self, _session_id: &str) -> Result<()> {
         Ok(())
@@ -73,32 +320,115 @@ impl SessionPool {
 
     /// Release a session (decrement active_tasks) and record result.
     pub async fn release_session(&self, session_id: &str, success: bool, error: Option<&str>) -> Result<()> {
-        let success_col = if success { "success_count" } else { "fail_count" };
+        self.release(session_id, Some(success), error).await
+    }
+
+    /// Release a session without counting the task for or against it, for
+    /// tasks that failed on their own input.
+    pub async fn release_neutral(&self, session_id: &str) -> Result<()> {
+        self.release(session_id, None, None).await
+    }
+
+    async fn release(&self, session_id: &str, success: Option<bool>, error: Option<&str>) -> Result<()> {
+        let counted = match success {
+            Some(true) => "total_tasks = total_tasks + 1, success_count = success_count + 1, ",
+            Some(false) => "total_tasks = total_tasks + 1, fail_count = fail_count + 1, ",
+            None => "",
+        };
         let query = format!(
             "UPDATE sessions SET active_tasks = MAX(0, active_tasks - 1), \
-             total_tasks = total_tasks + 1, \
-             {success_col} = {success_col} + 1, \
+             {counted}\
              last_error = CASE WHEN ? IS NOT NULL THEN ? ELSE last_error END, \
              updated_at = datetime('now') \
-             WHERE id = ?",
+             WHERE id = ? RETURNING active_tasks, draining",
         );
-        sqlx::query(&query)
+        let row: Option<(i32, Option<DrainAction>)> = sqlx::query_as(&query)
             .bind(error)
             .bind(error)
             .bind(session_id)
-            .execute(&self.db.pool)
+            .fetch_optional(&self.db.pool)
             .await?;
 
         let mut sessions = self.sessions.write().await;
         if let Some(s) = sessions.iter_mut().find(|s| s.id == session_id) {
-            s.active_tasks = s.active_tasks.saturating_sub(1);
-            s.total_tasks += 1;
-            if success {
-                s.success_count += 1;
-            } else {
-                s.fail_count += 1;
+            s.active_tasks = row.map_or(s.active_tasks.saturating_sub(1), |(active_tasks, _)| active_tasks);
+            if let Some(success) = success {
+                s.total_tasks += 1;
+                if success {
+                    s.success_count += 1;
+                } else {
+                    s.fail_count += 1;
+                }
+            }
+        }
+        drop(sessions);
+
+        if let Some((0, Some(_))) = row {
+            self.finish_drain(session_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Stop routing new tasks to a session and run `action` once its
+    /// in-flight tasks have finished (right away if it has none).
+    /// Returns false if the session doesn't exist.
+    pub async fn start_drain(&self, id: &str, action: DrainAction) -> Result<bool> {
+        // Idle as counted in the database, which pick/release update atomically.
+        let active_tasks: Option<i32> = sqlx::query_scalar(
+            "UPDATE sessions SET draining = ?, updated_at = datetime('now') WHERE id = ? RETURNING active_tasks",
+        )
+        .bind(action)
+        .bind(id)
+        .fetch_optional(&self.db.pool)
+        .await?;
+        let Some(active_tasks) = active_tasks else {
+            return Ok(false);
+        };
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.draining = Some(action);
+            s.active_tasks = active_tasks;
+        }
+        drop(sessions);
+
+        tracing::info!(id, ?action, "Draining session");
+        if active_tasks == 0 {
+            self.finish_drain(id).await?;
+        }
+        Ok(true)
+    }
+
+    /// Put a draining session back into rotation.
+    pub async fn cancel_drain(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE sessions SET draining = NULL, updated_at = datetime('now') WHERE id = ?")
+            .bind(id)
+            .execute(&self.db.pool)
+            .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.draining = None;
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Run the drain action of an idle draining session.
+    async fn finish_drain(&self, id: &str) -> Result<()> {
+        let action = self.sessions.read().await.iter().find(|s| s.id == id).and_then(|s| s.draining);
+        match action {
+            None | Some(DrainAction::Keep) => {}
+            Some(DrainAction::Disable) => {
+                self.toggle_session(id, false).await?;
+                self.cancel_drain(id).await?;
+            }
+            Some(DrainAction::Delete) => {
+                self.remove_session(id, true).await?;
             }
         }
+        if let Some(action) = action {
+            tracing::info!(id, ?action, "Session drained");
+        }
         Ok(())
     }
 
@@ -119,23 +449,38 @@ impl SessionPool {
     }
 
     /// Add a new session.
-    pub async fn add_session(&self, label: &str, jimeng_session_id: &str, cookie_jar: Option<&str>) -> Result<SessionInfo> {
+    pub async fn add_session(&self, new: NewSession) -> Result<SessionInfo> {
         let id = uuid::Uuid::new_v4().to_string();
+        let tags = normalize_tags(&new.tags);
+        let fingerprint = new
+            .fingerprint
+            .filter(|fp| !fp.is_empty())
+            .unwrap_or_else(|| Fingerprint::for_cookie_jar(new.cookie_jar.as_ref()));
+        let expires_at = expiry_for(new.cookie_jar.as_ref());
+        let sealed_session_id = crypto::seal(&new.session_id)?;
+        let sealed_jar = crypto::seal_opt(new.cookie_jar.as_ref().map(CookieJar::to_json).as_deref())?;
         sqlx::query(
-            "INSERT INTO sessions (id, label, session_id, cookie_jar) VALUES (?, ?, ?, ?)",
+            "INSERT INTO sessions (id, label, session_id, cookie_jar, tags, fingerprint, proxy_url, enabled, expires_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(&id)
-        .bind(label)
-        .bind(jimeng_session_id)
-        .bind(cookie_jar)
+        .bind(&new.label)
+        .bind(&sealed_session_id)
+        .bind(&sealed_jar)
+        .bind(serde_json::to_string(&tags)?)
+        .bind(serde_json::to_string(&fingerprint)?)
+        .bind(&new.proxy_url)
+        .bind(new.enabled)
+        .bind(&expires_at)
         .execute(&self.db.pool)
         .await?;
 
+        let label = new.label;
         let session = SessionInfo {
             id: id.clone(),
-            label: label.to_string(),
-            session_id: jimeng_session_id.to_string(),
-            enabled: true,
+            label: label.clone(),
+            session_id: sealed_session_id,
+            enabled: new.enabled,
             healthy: true,
             active_tasks: 0,
             total_tasks: 0,
@@ -143,25 +488,70 @@ impl SessionPool {
             fail_count: 0,
             last_used_at: None,
             last_error: None,
-            cookie_jar: cookie_jar.map(|s| s.to_string()),
+            cookie_jar_raw: sealed_jar,
+            cookie_jar: None,
+            tags,
+            fingerprint,
+            proxy_url: new.proxy_url,
+            expires_at,
+            cookies_refreshed_at: None,
+            cookie_refresh_attempted_at: None,
+            cookie_refresh_failures: 0,
+            submit_interval_secs: None,
+            daily_submit_cap: None,
+            next_submit_at: None,
+            daily_submits: 0,
+            daily_submits_date: None,
+            quota_exhausted_until: None,
+            draining: None,
+            profile: None,
+            transport_prefs: Default::default(),
+            client_profile: None,
             created_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
             updated_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
         };
 
         self.sessions.write().await.push(session.clone());
         tracing::info!(id, label, "Session added to pool");
-        Ok(session)
+        Ok(SessionInfo { session_id: new.session_id, cookie_jar: new.cookie_jar, ..session })
     }
 
-    /// Remove a session.
-    pub async fn remove_session(&self, id: &str) -> Result<bool> {
-        let result = sqlx::query("DELETE FROM sessions WHERE id = ?")
+    /// A session with its credentials decrypted, for code about to use them.
+    pub async fn open_session(&self, id: &str) -> Result<Option<SessionInfo>> {
+        let sessions = self.sessions.read().await;
+        sessions.iter().find(|s| s.id == id).map(SessionInfo::open_secrets).transpose()
+    }
+
+    /// Remove a session; unless `force`, only while no task is in flight on
+    /// it as counted in the database.
+    pub async fn remove_session(&self, id: &str, force: bool) -> Result<Removal> {
+        let result = sqlx::query("DELETE FROM sessions WHERE id = ? AND (? OR active_tasks = 0)")
+            .bind(id)
+            .bind(force)
+            .execute(&self.db.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            let active_tasks: Option<i64> = sqlx::query_scalar("SELECT active_tasks FROM sessions WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.db.pool)
+                .await?;
+            return Ok(active_tasks.map_or(Removal::NotFound, Removal::Busy));
+        }
+        sqlx::query("DELETE FROM cookie_refreshes WHERE session_id = ?")
+            .bind(id)
+            .execute(&self.db.pool)
+            .await?;
+        sqlx::query("DELETE FROM session_stats_hourly WHERE session_id = ?")
+            .bind(id)
+            .execute(&self.db.pool)
+            .await?;
+        sqlx::query("DELETE FROM material_cache WHERE session_id = ?")
             .bind(id)
             .execute(&self.db.pool)
             .await?;
 
         self.sessions.write().await.retain(|s| s.id != id);
-        Ok(result.rows_affected() > 0)
+        Ok(Removal::Removed)
     }
 
     /// Toggle enabled/disabled.
@@ -186,19 +576,300 @@ impl SessionPool {
         Ok(result.rows_affected() > 0)
     }
 
-    /// Update cookie jar for an existing session.
-    pub async fn update_cookie_jar(&self, id: &str, cookie_jar: &str) -> Result<bool> {
+    /// Disable a session and record why (e.g. about to expire).
+    pub async fn disable_session(&self, id: &str, reason: &str) -> Result<()> {
+        sqlx::query(
+            "UPDATE sessions SET enabled = 0, last_error = ?, updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(reason)
+        .bind(id)
+        .execute(&self.db.pool)
+        .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.enabled = false;
+            s.last_error = Some(reason.to_string());
+        }
+        Ok(())
+    }
+
+    /// Replace the cookie jar of an existing session.
+    /// Also refreshes `expires_at` when the jar carries `sid_guard`.
+    pub async fn update_cookie_jar(&self, id: &str, cookie_jar: &CookieJar) -> Result<bool> {
+        let mut sessions = self.sessions.write().await;
+        let Some(sealed) = self.store_cookie_jar(id, cookie_jar).await? else {
+            return Ok(false);
+        };
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            Self::apply_cookie_jar(s, cookie_jar, sealed);
+        }
+        Ok(true)
+    }
+
+    /// Merge cookies (harvested, or set by jimeng during API calls) into a
+    /// session's jar. Cookies already expired delete their counterpart.
+    /// Returns the merged jar, or `None` if the session doesn't exist.
+    pub async fn merge_cookies(&self, id: &str, cookies: Vec<Cookie>) -> Result<Option<CookieJar>> {
+        // Hold the write lock across the DB write so concurrent merges don't
+        // drop each other's cookies.
+        let mut sessions = self.sessions.write().await;
+        let Some(session) = sessions.iter_mut().find(|s| s.id == id) else {
+            return Ok(None);
+        };
+        let mut jar = session.open_secrets()?.cookie_jar.unwrap_or_default();
+        if !jar.merge(cookies) {
+            return Ok(Some(jar));
+        }
+        if let Some(sealed) = self.store_cookie_jar(id, &jar).await? {
+            Self::apply_cookie_jar(session, &jar, sealed);
+        }
+        Ok(Some(jar))
+    }
+
+    /// Store a jar sealed; returns the sealed value, or `None` if the
+    /// session doesn't exist.
+    async fn store_cookie_jar(&self, id: &str, cookie_jar: &CookieJar) -> Result<Option<String>> {
+        let sealed = crypto::seal(&cookie_jar.to_json())?;
         let result = sqlx::query(
-            "UPDATE sessions SET cookie_jar = ?, updated_at = datetime('now') WHERE id = ?",
+            "UPDATE sessions SET cookie_jar = ?, expires_at = COALESCE(?, expires_at), \
+             updated_at = datetime('now') WHERE id = ?",
         )
-        .bind(cookie_jar)
+        .bind(&sealed)
+        .bind(expiry_for(Some(cookie_jar)))
+        .bind(id)
+        .execute(&self.db.pool)
+        .await?;
+        Ok((result.rows_affected() > 0).then_some(sealed))
+    }
+
+    /// Record the outcome of a browser cookie refresh: history row plus the
+    /// session's refresh status. `outcome` is the number of cookies harvested
+    /// or the error message.
+    pub async fn record_cookie_refresh(
+        &self,
+        id: &str,
+        trigger: &str,
+        outcome: std::result::Result<usize, &str>,
+    ) -> Result<()> {
+        let (status, cookie_count, error) = match outcome {
+            Ok(count) => ("ok", Some(count as i64), None),
+            Err(e) => ("failed", None, Some(e)),
+        };
+        sqlx::query(
+            "INSERT INTO cookie_refreshes (id, session_id, trigger, status, cookie_count, error) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(id)
+        .bind(trigger)
+        .bind(status)
+        .bind(cookie_count)
+        .bind(error)
+        .execute(&self.db.pool)
+        .await?;
+        sqlx::query(
+            "DELETE FROM cookie_refreshes WHERE session_id = ? AND id NOT IN \
+             (SELECT id FROM cookie_refreshes WHERE session_id = ? ORDER BY created_at DESC LIMIT ?)",
+        )
+        .bind(id)
+        .bind(id)
+        .bind(REFRESH_HISTORY_LIMIT)
+        .execute(&self.db.pool)
+        .await?;
+
+        let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let ok = outcome.is_ok();
+        sqlx::query(
+            "UPDATE sessions SET cookie_refresh_attempted_at = ?, \
+             cookies_refreshed_at = CASE WHEN ? THEN ? ELSE cookies_refreshed_at END, \
+             cookie_refresh_failures = CASE WHEN ? THEN 0 ELSE cookie_refresh_failures + 1 END \
+             WHERE id = ?",
+        )
+        .bind(&now)
+        .bind(ok)
+        .bind(&now)
+        .bind(ok)
         .bind(id)
         .execute(&self.db.pool)
         .await?;
 
         let mut sessions = self.sessions.write().await;
         if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
-            s.cookie_jar = Some(cookie_jar.to_string());
+            s.cookie_refresh_attempted_at = Some(now.clone());
+            if ok {
+                s.cookies_refreshed_at = Some(now);
+                s.cookie_refresh_failures = 0;
+            } else {
+                s.cookie_refresh_failures += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Most recent cookie refreshes of a session, newest first.
+    pub async fn cookie_refresh_history(&self, id: &str) -> Result<Vec<CookieRefreshRecord>> {
+        Ok(sqlx::query_as::<_, CookieRefreshRecord>(
+            "SELECT trigger, status, cookie_count, error, created_at FROM cookie_refreshes \
+             WHERE session_id = ? ORDER BY created_at DESC LIMIT ?",
+        )
+        .bind(id)
+        .bind(REFRESH_HISTORY_LIMIT)
+        .fetch_all(&self.db.pool)
+        .await?)
+    }
+
+    fn apply_cookie_jar(session: &mut SessionInfo, cookie_jar: &CookieJar, sealed: String) {
+        if let Some(expires_at) = expiry_for(Some(cookie_jar)) {
+            session.expires_at = Some(expires_at);
+        }
+        session.cookie_jar_raw = Some(sealed);
+    }
+
+    /// Replace the capability tags of a session.
+    pub async fn update_tags(&self, id: &str, tags: &[String]) -> Result<Option<Vec<String>>> {
+        let tags = normalize_tags(tags);
+        let result = sqlx::query(
+            "UPDATE sessions SET tags = ?, updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(serde_json::to_string(&tags)?)
+        .bind(id)
+        .execute(&self.db.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.tags = tags.clone();
+        }
+        Ok(Some(tags))
+    }
+
+    /// Take a session out of rotation until the next daily reset after
+    /// jimeng reported its usage limit. Returns when it becomes usable again.
+    pub async fn mark_quota_exhausted(&self, id: &str) -> Result<String> {
+        let until = pacing::next_reset();
+        sqlx::query("UPDATE sessions SET quota_exhausted_until = ?, updated_at = datetime('now') WHERE id = ?")
+            .bind(&until)
+            .bind(id)
+            .execute(&self.db.pool)
+            .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.quota_exhausted_until = Some(until.clone());
+        }
+        Ok(until)
+    }
+
+    /// Cache a freshly fetched account profile.
+    pub async fn update_profile(&self, id: &str, profile: &AccountProfile) -> Result<()> {
+        sqlx::query("UPDATE sessions SET profile = ?, updated_at = datetime('now') WHERE id = ?")
+            .bind(serde_json::to_string(profile)?)
+            .bind(id)
+            .execute(&self.db.pool)
+            .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.profile = Some(profile.clone());
+        }
+        Ok(())
+    }
+
+    /// Keep what a jimeng client learned while working for `session`: cookies
+    /// set on its responses and the transports that worked. Failures are
+    /// logged.
+    pub async fn save_client_state(&self, session: &SessionInfo, jimeng: &JimengClient) {
+        if let (Some(before), Some(after)) = (&session.cookie_jar, jimeng.cookie_jar()) {
+            let changes = after.changes_since(before);
+            if !changes.is_empty()
+                && let Err(e) = self.merge_cookies(&session.id, changes).await
+            {
+                tracing::warn!(id = session.id, error = %e, "Failed to save response cookies");
+            }
+        }
+
+        let prefs = jimeng.preferred_transports();
+        if *prefs != session.transport_prefs
+            && let Err(e) = self.update_transport_prefs(&session.id, prefs).await
+        {
+            tracing::warn!(id = session.id, error = %e, "Failed to save transport preferences");
+        }
+    }
+
+    async fn update_transport_prefs(&self, id: &str, prefs: &BTreeMap<Endpoint, TransportKind>) -> Result<()> {
+        sqlx::query("UPDATE sessions SET transport_prefs = ? WHERE id = ?")
+            .bind(serde_json::to_string(prefs)?)
+            .bind(id)
+            .execute(&self.db.pool)
+            .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.transport_prefs = prefs.clone();
+        }
+        Ok(())
+    }
+
+    /// Set or clear a session's pacing overrides (`None` = pool default).
+    pub async fn update_pacing(
+        &self,
+        id: &str,
+        submit_interval_secs: Option<i32>,
+        daily_submit_cap: Option<i32>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE sessions SET submit_interval_secs = ?, daily_submit_cap = ?, \
+             updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(submit_interval_secs)
+        .bind(daily_submit_cap)
+        .bind(id)
+        .execute(&self.db.pool)
+        .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.submit_interval_secs = submit_interval_secs;
+            s.daily_submit_cap = daily_submit_cap;
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Set or clear the outbound proxy of a session.
+    pub async fn update_proxy(&self, id: &str, proxy_url: Option<&str>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE sessions SET proxy_url = ?, updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(proxy_url)
+        .bind(id)
+        .execute(&self.db.pool)
+        .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.proxy_url = proxy_url.map(|s| s.to_string());
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Pick the client profile the session imitates (`None` for the default).
+    pub async fn update_client_profile(&self, id: &str, client_profile: Option<&str>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE sessions SET client_profile = ?, updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(client_profile)
+        .bind(id)
+        .execute(&self.db.pool)
+        .await?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(s) = sessions.iter_mut().find(|s| s.id == id) {
+            s.client_profile = client_profile.map(|s| s.to_string());
         }
         Ok(result.rows_affected() > 0)
     }
@@ -208,3 +879,177 @@ impl SessionPool {
         self.sessions.read().await.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn add(pool: &SessionPool, tags: &[&str]) -> String {
+        let new = NewSession {
+            session_id: "sid".into(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            enabled: true,
+            ..Default::default()
+        };
+        pool.add_session(new).await.unwrap().id
+    }
+
+    #[tokio::test]
+    async fn test_quota_exhausted_session_is_skipped() {
+        let pool = SessionPool::new(Database::memory().await, Pacing::default());
+        let id = add(&pool, &[]).await;
+
+        pool.mark_quota_exhausted(&id).await.unwrap();
+        assert!(pool.pick_session(&[]).await.is_none());
+        assert_eq!(pool.unavailable_reason(&[]).await, QUOTA_BLOCKED_REASON);
+
+        // Another session with quota left is picked, and the pool is not
+        // blocked on quota while it is only busy.
+        let other = add(&pool, &[]).await;
+        assert_eq!(pool.pick_session(&[]).await.unwrap().id, other);
+        assert_eq!(pool.pick_session(&[]).await.unwrap().id, other);
+        assert_eq!(pool.unavailable_reason(&[]).await, "all capable sessions are busy");
+    }
+
+    #[tokio::test]
+    async fn test_pick_reserves_submit() {
+        let pacing = Pacing { min_interval_secs: 60, ..Pacing::default() };
+        let pool = SessionPool::new(Database::memory().await, pacing);
+        let id = add(&pool, &[]).await;
+
+        // The pick itself starts the cooldown, so a second task can't race it.
+        let picked = pool.pick_session(&[]).await.unwrap();
+        assert!(pool.pick_session(&[]).await.is_none());
+        assert_eq!(pool.unavailable_reason(&[]).await, "waiting for session cooldown");
+
+        // A task that never submits gives the slot back.
+        pool.unreserve_submit(&picked).await.unwrap();
+        pool.release_session(&id, false, Some("upload failed")).await.unwrap();
+        assert_eq!(pool.pick_session(&[]).await.unwrap().id, id);
+    }
+
+    #[tokio::test]
+    async fn test_daily_cap_counts_submits() {
+        let pacing = Pacing { daily_cap: 1, ..Pacing::default() };
+        let pool = SessionPool::new(Database::memory().await, pacing);
+        let id = add(&pool, &[]).await;
+
+        let picked = pool.pick_session(&[]).await.unwrap();
+        pool.unreserve_submit(&picked).await.unwrap();
+        pool.release_session(&id, false, None).await.unwrap();
+        pool.pick_session(&[]).await.unwrap();
+        pool.release_session(&id, true, None).await.unwrap();
+
+        assert!(pool.pick_session(&[]).await.is_none());
+        assert_eq!(pool.unavailable_reason(&[]).await, "daily submit cap reached on every capable session");
+        let cached = pool.list_sessions().await.into_iter().find(|s| s.id == id).unwrap();
+        assert_eq!((cached.daily_submits, cached.daily_submits_date), (1, Some(pacing::today())));
+    }
+
+    #[tokio::test]
+    async fn test_resubmit_is_paced_and_capped() {
+        let pacing = Pacing { min_interval_secs: 1, daily_cap: 2, ..Pacing::default() };
+        let pool = SessionPool::new(Database::memory().await, pacing);
+        let id = add(&pool, &[]).await;
+
+        pool.pick_session(&[]).await.unwrap();
+        let started = std::time::Instant::now();
+        pool.reserve_submit(&id).await.unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(500));
+        let cached = pool.list_sessions().await.into_iter().find(|s| s.id == id).unwrap();
+        assert_eq!(cached.daily_submits, 2);
+
+        let err = pool.reserve_submit(&id).await.unwrap_err();
+        assert!(err.to_string().contains("daily submit cap reached"));
+    }
+
+    #[tokio::test]
+    async fn test_release_neutral() {
+        let pool = SessionPool::new(Database::memory().await, Pacing::default());
+        let id = add(&pool, &[]).await;
+        pool.pick_session(&[]).await.unwrap();
+        pool.release_neutral(&id).await.unwrap();
+
+        let counts: (i32, i32, i32, i32, Option<String>) = sqlx::query_as(
+            "SELECT active_tasks, total_tasks, success_count, fail_count, last_error FROM sessions WHERE id = ?",
+        )
+        .bind(&id)
+        .fetch_one(&pool.db.pool)
+        .await
+        .unwrap();
+        assert_eq!(counts, (0, 0, 0, 0, None));
+        let cached = pool.list_sessions().await.into_iter().find(|s| s.id == id).unwrap();
+        assert_eq!((cached.active_tasks, cached.total_tasks, cached.fail_count), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_tasks_in_database() {
+        let pool = SessionPool::new(Database::memory().await, Pacing::default());
+        let id = add(&pool, &[]).await;
+        pool.pick_session(&[]).await.unwrap();
+        // Another process (or a stale cache) may disagree; the row decides.
+        pool.sessions.write().await[0].active_tasks = 0;
+
+        assert_eq!(pool.remove_session(&id, false).await.unwrap(), Removal::Busy(1));
+        assert!(pool.start_drain(&id, DrainAction::Delete).await.unwrap());
+        assert_eq!(pool.list_sessions().await.len(), 1);
+        assert!(pool.pick_session(&[]).await.is_none());
+
+        sqlx::query("INSERT INTO material_cache (session_id, sha256, material_type) VALUES (?, 'abc', 'image')")
+            .bind(&id)
+            .execute(&pool.db.pool)
+            .await
+            .unwrap();
+        pool.release_session(&id, true, None).await.unwrap();
+        assert!(pool.list_sessions().await.is_empty());
+        let cached: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM material_cache").fetch_one(&pool.db.pool).await.unwrap();
+        assert_eq!(cached, 0);
+        assert_eq!(pool.remove_session(&id, false).await.unwrap(), Removal::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_drain_idle_session() {
+        let pool = SessionPool::new(Database::memory().await, Pacing::default());
+        let id = add(&pool, &[]).await;
+        assert!(pool.start_drain(&id, DrainAction::Disable).await.unwrap());
+        let session = &pool.list_sessions().await[0];
+        assert!(!session.enabled);
+        assert_eq!(session.draining, None);
+        assert!(!pool.start_drain("missing", DrainAction::Keep).await.unwrap());
+
+        // A drain left over from before a restart finishes on load.
+        let other = add(&pool, &[]).await;
+        sqlx::query("UPDATE sessions SET draining = 'delete' WHERE id = ?")
+            .bind(&other)
+            .execute(&pool.db.pool)
+            .await
+            .unwrap();
+        pool.load_sessions().await.unwrap();
+        assert_eq!(pool.list_sessions().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_opened_on_use() {
+        let pool = SessionPool::new(Database::memory().await, Pacing::default());
+        let jar = CookieJar::from_cookies([Cookie::new("sessionid", "secret-session-id")]);
+        let new = NewSession {
+            session_id: "secret-session-id".into(),
+            cookie_jar: Some(jar),
+            enabled: true,
+            ..Default::default()
+        };
+        let added = pool.add_session(new).await.unwrap();
+        assert_eq!(added.cookie_jar.as_ref().and_then(|jar| jar.get("sessionid")), Some("secret-session-id"));
+
+        // The cache holds the stored form only.
+        let cached = &pool.list_sessions().await[0];
+        assert!(cached.cookie_jar.is_none());
+        assert_eq!(cached.masked().session_id, "secret-s...n-id");
+
+        pool.merge_cookies(&added.id, vec![Cookie::new("msToken", "t")]).await.unwrap();
+        let opened = pool.open_session(&added.id).await.unwrap().unwrap();
+        assert_eq!(opened.session_id, "secret-session-id");
+        assert_eq!(opened.cookie_jar.unwrap().len(), 2);
+        assert_eq!(pool.pick_session(&[]).await.unwrap().cookie_jar.unwrap().len(), 2);
+    }
+}
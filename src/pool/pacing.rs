@@ -0,0 +1,70 @@
+//! Per-session submission pacing.
+//!
+//! Bursts of `aigc_draft/generate` calls from one account draw jimeng's risk
+//! control, so each submit pushes the session's `next_submit_at` out by a
+//! minimum interval plus random jitter, and an optional cap limits submits
+//! per day. A task's first submit is reserved when its session is picked and
+//! given back if nothing is sent; resubmits wait for their own slot. Days
+//! follow jimeng's own reset: midnight in Asia/Shanghai.
+
+use chrono::{DateTime, FixedOffset, Utc};
+use rand::Rng;
+
+/// Pool-wide pacing defaults; sessions may override the interval and cap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pacing {
+    /// Minimum seconds between two submits on one session (0 = no pacing).
+    pub min_interval_secs: u32,
+    /// Up to this many seconds are added to each interval at random.
+    pub jitter_secs: u32,
+    /// Submits per session per day (0 = unlimited).
+    pub daily_cap: u32,
+}
+
+impl Pacing {
+    /// Random extra delay for the next interval.
+    pub fn jitter(&self) -> u32 {
+        if self.jitter_secs == 0 {
+            return 0;
+        }
+        rand::rng().random_range(0..=self.jitter_secs)
+    }
+}
+
+fn shanghai() -> FixedOffset {
+    FixedOffset::east_opt(8 * 3600).expect("valid offset")
+}
+
+/// Today's date (`YYYY-MM-DD`) in Asia/Shanghai, which has no DST.
+pub fn today() -> String {
+    day_of(Utc::now())
+}
+
+fn day_of(now: DateTime<Utc>) -> String {
+    now.with_timezone(&shanghai()).format("%Y-%m-%d").to_string()
+}
+
+/// Next daily reset (midnight Asia/Shanghai) as a UTC SQLite datetime.
+pub fn next_reset() -> String {
+    reset_after(Utc::now())
+}
+
+fn reset_after(now: DateTime<Utc>) -> String {
+    let tomorrow = now.with_timezone(&shanghai()).date_naive() + chrono::Days::new(1);
+    let midnight = tomorrow.and_hms_opt(0, 0, 0).expect("valid time") - chrono::Duration::hours(8);
+    midnight.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_day_resets_at_shanghai_midnight() {
+        let at = |s: &str| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap().and_utc();
+        assert_eq!(day_of(at("2026-10-17 15:59:59")), "2026-10-17");
+        assert_eq!(day_of(at("2026-10-17 16:00:00")), "2026-10-18");
+        assert_eq!(reset_after(at("2026-10-17 15:59:59")), "2026-10-17 16:00:00");
+        assert_eq!(reset_after(at("2026-10-17 16:00:00")), "2026-10-18 16:00:00");
+    }
+}
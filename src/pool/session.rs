@@ -1,11 +1,20 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+use crate::crypto;
+use crate::jimeng::auth::Fingerprint;
+use crate::jimeng::cookies::CookieJar;
+use crate::jimeng::profile::AccountProfile;
+use crate::jimeng::transport::{Endpoint, TransportKind};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
 pub struct SessionInfo {
     pub id: String,
     pub label: String,
-    /// The actual jimeng session_id cookie value.
+    /// The actual jimeng session_id cookie value: sealed as stored in the
+    /// pool's cache, plaintext once opened with `open_secrets`.
     /// Masked in API responses for security.
     pub session_id: String,
     pub enabled: bool,
@@ -16,25 +25,144 @@ pub struct SessionInfo {
     pub fail_count: i32,
     pub last_used_at: Option<String>,
     pub last_error: Option<String>,
-    /// Full browser cookie jar string (all cookies including HttpOnly).
+    /// Sealed `cookie_jar` column as read from the database; `open_secrets`
+    /// decodes it into `cookie_jar`.
+    #[serde(skip)]
+    #[sqlx(rename = "cookie_jar")]
+    pub(crate) cookie_jar_raw: Option<String>,
+    /// Full browser cookie jar (all cookies including HttpOnly).
     /// When present, used instead of constructing minimal cookies from session_id.
-    pub cookie_jar: Option<String>,
+    /// Only set on a session opened with `open_secrets`.
+    #[serde(skip)]
+    #[sqlx(skip)]
+    pub cookie_jar: Option<CookieJar>,
+    /// Capability tags (e.g. `vip`, `seedance-pro`, `image-4k`) used to route
+    /// tasks only to accounts that can run the requested model.
+    #[sqlx(json)]
+    pub tags: Vec<String>,
+    /// Device fingerprint presented to jimeng for this account.
+    #[sqlx(json)]
+    pub fingerprint: Fingerprint,
+    /// Outbound proxy for this account (`http://`, `socks5://`, ...).
+    /// Credentials are redacted in API responses.
+    pub proxy_url: Option<String>,
+    /// When the jimeng login expires (UTC), from the jar's `sid_guard` cookie.
+    pub expires_at: Option<String>,
+    /// Last successful browser cookie refresh (UTC).
+    pub cookies_refreshed_at: Option<String>,
+    /// Last refresh attempt, successful or not (UTC).
+    pub cookie_refresh_attempted_at: Option<String>,
+    /// Refresh failures since the last success (drives retry backoff).
+    pub cookie_refresh_failures: i32,
+    /// Overrides the pool's minimum seconds between submits.
+    pub submit_interval_secs: Option<i32>,
+    /// Overrides the pool's daily submit cap (0 = unlimited).
+    pub daily_submit_cap: Option<i32>,
+    /// Earliest time the next task may be submitted on this session (UTC).
+    pub next_submit_at: Option<String>,
+    /// Submits counted on `daily_submits_date` (Asia/Shanghai).
+    pub daily_submits: i32,
+    pub daily_submits_date: Option<String>,
+    /// Set when jimeng reports the account's daily usage limit; the session
+    /// is skipped until this time (the next Asia/Shanghai midnight, UTC).
+    pub quota_exhausted_until: Option<String>,
+    /// Set while the session is being retired: it takes no new tasks, and
+    /// the action runs once its in-flight tasks have finished.
+    pub draining: Option<DrainAction>,
+    /// Cached jimeng account profile (nickname, VIP tier, region), fetched
+    /// when the session is added and on health checks.
+    #[sqlx(json(nullable))]
+    pub profile: Option<AccountProfile>,
+    /// Transport that last worked for this session, per endpoint class;
+    /// tried first next time.
+    #[sqlx(json)]
+    pub transport_prefs: BTreeMap<Endpoint, TransportKind>,
+    /// Client profile the session imitates; the default profile if unset.
+    pub client_profile: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
 
 impl SessionInfo {
+    /// Whether this session carries every tag in `required`, counting
+    /// capabilities implied by its account profile.
+    pub fn has_capabilities(&self, required: &[&str]) -> bool {
+        let implied = self.profile.as_ref().map_or(&[][..], |p| &p.capabilities[..]);
+        required.iter().all(|r| self.tags.iter().chain(implied).any(|t| t == r))
+    }
+
+    /// A copy with `session_id` and `cookie_jar` decrypted. The pool keeps
+    /// them sealed, so open a session only where its credentials are used.
+    pub fn open_secrets(&self) -> anyhow::Result<Self> {
+        let cookie_jar = crypto::open_opt(self.cookie_jar_raw.as_deref())?
+            .filter(|jar| !jar.is_empty())
+            .map(|jar| CookieJar::parse(&jar))
+            .transpose()?;
+        Ok(Self {
+            session_id: crypto::open(&self.session_id)?,
+            cookie_jar,
+            ..self.clone()
+        })
+    }
+
     /// Return a masked version for API responses (hide most of the session_id).
     pub fn masked(&self) -> Self {
-        let masked_id = if self.session_id.len() > 8 {
-            format!("{}...{}", &self.session_id[..8], &self.session_id[self.session_id.len() - 4..])
+        let session_id = crypto::open(&self.session_id).unwrap_or_default();
+        let masked_id = if session_id.len() > 8 {
+            format!("{}...{}", &session_id[..8], &session_id[session_id.len() - 4..])
         } else {
             "****".to_string()
         };
 
         Self {
             session_id: masked_id,
+            proxy_url: self.proxy_url.as_deref().map(crate::jimeng::proxy::redact),
             ..self.clone()
         }
     }
 }
+
+/// What happens to a draining session once `active_tasks` reaches 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum DrainAction {
+    /// Stay out of rotation until the drain is cancelled.
+    Keep,
+    Disable,
+    Delete,
+}
+
+/// Outcome of `SessionPool::remove_session`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+    Removed,
+    NotFound,
+    /// Kept: this many tasks are in flight on it.
+    Busy(i64),
+}
+
+/// Fields for creating a session (`SessionPool::add_session`).
+#[derive(Debug, Clone, Default)]
+pub struct NewSession {
+    pub label: String,
+    pub session_id: String,
+    pub cookie_jar: Option<CookieJar>,
+    pub tags: Vec<String>,
+    pub proxy_url: Option<String>,
+    /// Carried over on import; generated when `None`.
+    pub fingerprint: Option<Fingerprint>,
+    pub enabled: bool,
+}
+
+/// Normalize user-supplied tags: trimmed, lowercase, deduplicated, sorted.
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = tags
+        .iter()
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    out.sort();
+    out.dedup();
+    out
+}
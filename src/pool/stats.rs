@@ -0,0 +1,255 @@
+//! Per-session usage rollups.
+//!
+//! Every finished task adds to its session's row in `session_stats_hourly`
+//! (one row per session per UTC hour). Queries fold those rows into hourly
+//! or daily buckets.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// Add a finished task to its session's row for the hour it finished in.
+/// `error_kind` is `None` for a success. Queue wait (creation to last
+/// claim) and credits are read from the task row.
+pub async fn record_task(db: &SqlitePool, session_id: &str, task_id: &str, error_kind: Option<&str>) {
+    let result = sqlx::query(
+        "INSERT INTO session_stats_hourly \
+         (session_id, hour, tasks, successes, failures, failures_by_kind, \
+          queue_wait_secs, queue_wait_count, credits_spent) \
+         SELECT ?1, strftime('%Y-%m-%d %H:00:00', COALESCE(finished_at, 'now')), 1, ?2 IS NULL, ?2 IS NOT NULL, \
+                CASE WHEN ?2 IS NULL THEN '{}' ELSE json_object(?2, 1) END, \
+                COALESCE(MAX(0, unixepoch(started_at) - unixepoch(created_at)), 0), \
+                started_at IS NOT NULL, COALESCE(credits, 0) \
+         FROM tasks WHERE id = ?3 \
+         ON CONFLICT(session_id, hour) DO UPDATE SET \
+             tasks = tasks + 1, \
+             successes = successes + excluded.successes, \
+             failures = failures + excluded.failures, \
+             failures_by_kind = CASE WHEN ?2 IS NULL THEN failures_by_kind ELSE json_set( \
+                 failures_by_kind, '$.' || ?2, \
+                 COALESCE(json_extract(failures_by_kind, '$.' || ?2), 0) + 1) END, \
+             queue_wait_secs = queue_wait_secs + excluded.queue_wait_secs, \
+             queue_wait_count = queue_wait_count + excluded.queue_wait_count, \
+             credits_spent = credits_spent + excluded.credits_spent",
+    )
+    .bind(session_id)
+    .bind(error_kind)
+    .bind(task_id)
+    .execute(db)
+    .await;
+
+    if let Err(e) = result {
+        tracing::warn!(session_id, task_id, error = %e, "Failed to record session stats");
+    }
+}
+
+/// Bucket size for `query`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Hour,
+    Day,
+}
+
+/// Usage of one session over one hour or day.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StatsBucket {
+    /// `YYYY-MM-DD HH:00:00` for hourly buckets, `YYYY-MM-DD` for daily ones
+    /// (UTC); empty for the range total.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub period: String,
+    pub tasks: i64,
+    pub successes: i64,
+    pub failures: i64,
+    pub failures_by_kind: BTreeMap<String, i64>,
+    pub avg_queue_wait_secs: Option<f64>,
+    pub credits_spent: i64,
+    #[serde(skip)]
+    queue_wait_secs: i64,
+    #[serde(skip)]
+    queue_wait_count: i64,
+}
+
+impl StatsBucket {
+    fn add(&mut self, row: &HourlyRow) {
+        self.tasks += row.tasks;
+        self.successes += row.successes;
+        self.failures += row.failures;
+        if let Ok(kinds) = serde_json::from_str::<BTreeMap<String, i64>>(&row.failures_by_kind) {
+            for (kind, n) in kinds {
+                *self.failures_by_kind.entry(kind).or_default() += n;
+            }
+        }
+        self.queue_wait_secs += row.queue_wait_secs;
+        self.queue_wait_count += row.queue_wait_count;
+        self.credits_spent += row.credits_spent;
+        self.avg_queue_wait_secs = (self.queue_wait_count > 0)
+            .then(|| self.queue_wait_secs as f64 / self.queue_wait_count as f64);
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct HourlyRow {
+    hour: String,
+    tasks: i64,
+    successes: i64,
+    failures: i64,
+    failures_by_kind: String,
+    queue_wait_secs: i64,
+    queue_wait_count: i64,
+    credits_spent: i64,
+}
+
+/// SQLite datetime format of the hour buckets.
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Parse a range bound: `YYYY-MM-DD HH:MM:SS`, or a bare `YYYY-MM-DD` for
+/// the start of that day (or its last second when `end` is set).
+pub fn parse_bound(value: &str, end: bool) -> Option<NaiveDateTime> {
+    if let Ok(t) = NaiveDateTime::parse_from_str(value, DATETIME_FORMAT) {
+        return Some(t);
+    }
+    let day = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    if end { day.and_hms_opt(23, 59, 59) } else { day.and_hms_opt(0, 0, 0) }
+}
+
+/// Buckets for `session_id` between `from` and `to` (inclusive, UTC),
+/// oldest first, plus the total over the range.
+pub async fn query(
+    db: &SqlitePool,
+    session_id: &str,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    granularity: Granularity,
+) -> Result<(Vec<StatsBucket>, StatsBucket)> {
+    let rows = sqlx::query_as::<_, HourlyRow>(
+        "SELECT hour, tasks, successes, failures, failures_by_kind, \
+         queue_wait_secs, queue_wait_count, credits_spent \
+         FROM session_stats_hourly WHERE session_id = ? AND hour >= ? AND hour <= ? ORDER BY hour",
+    )
+    .bind(session_id)
+    .bind(from.format(DATETIME_FORMAT).to_string())
+    .bind(to.format(DATETIME_FORMAT).to_string())
+    .fetch_all(db)
+    .await?;
+
+    Ok(fold(&rows, granularity))
+}
+
+fn fold(rows: &[HourlyRow], granularity: Granularity) -> (Vec<StatsBucket>, StatsBucket) {
+    let mut buckets: Vec<StatsBucket> = Vec::new();
+    let mut total = StatsBucket::default();
+    for row in rows {
+        let period = match granularity {
+            Granularity::Hour => row.hour.as_str(),
+            Granularity::Day => &row.hour[..row.hour.len().min(10)],
+        };
+        if buckets.last().is_none_or(|b| b.period != period) {
+            buckets.push(StatsBucket { period: period.to_string(), ..Default::default() });
+        }
+        buckets.last_mut().unwrap().add(row);
+        total.add(row);
+    }
+    (buckets, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(hour: &str, successes: i64, kinds: &str, wait: (i64, i64)) -> HourlyRow {
+        let failures = serde_json::from_str::<BTreeMap<String, i64>>(kinds).unwrap().values().sum();
+        HourlyRow {
+            hour: hour.into(),
+            tasks: successes + failures,
+            successes,
+            failures,
+            failures_by_kind: kinds.into(),
+            queue_wait_secs: wait.0,
+            queue_wait_count: wait.1,
+            credits_spent: successes * 10,
+        }
+    }
+
+    #[test]
+    fn test_fold_by_day() {
+        let rows = [
+            row("2026-10-17 08:00:00", 2, r#"{"auth":1}"#, (30, 3)),
+            row("2026-10-17 09:00:00", 1, r#"{"auth":1,"quota":2}"#, (0, 0)),
+            row("2026-10-18 00:00:00", 0, "{}", (0, 0)),
+        ];
+
+        let (hours, _) = fold(&rows, Granularity::Hour);
+        assert_eq!(hours.len(), 3);
+
+        let (days, total) = fold(&rows, Granularity::Day);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].period, "2026-10-17");
+        assert_eq!(days[0].tasks, 7);
+        assert_eq!(days[0].failures_by_kind.get("auth"), Some(&2));
+        assert_eq!(days[0].avg_queue_wait_secs, Some(10.0));
+        assert_eq!(days[1].avg_queue_wait_secs, None);
+        assert_eq!(total.credits_spent, 30);
+        assert_eq!(total.failures, 4);
+    }
+
+    #[test]
+    fn test_parse_bound() {
+        let t = |s: &str| NaiveDateTime::parse_from_str(s, DATETIME_FORMAT).unwrap();
+        assert_eq!(parse_bound("2026-10-17 08:30:00", true), Some(t("2026-10-17 08:30:00")));
+        assert_eq!(parse_bound("2026-10-17", false), Some(t("2026-10-17 00:00:00")));
+        assert_eq!(parse_bound("2026-10-17", true), Some(t("2026-10-17 23:59:59")));
+        for bad in ["", "yesterday", "2026-13-01", "2026-10-17T08:00:00Z", "2026-10-17 8am"] {
+            assert_eq!(parse_bound(bad, false), None, "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_task_upserts_hourly_row() {
+        let db = crate::db::Database::memory().await;
+        let task = |id: &str, finished_at: &str, started: bool| {
+            sqlx::query(
+                "INSERT INTO tasks (id, prompt, status, created_at, started_at, finished_at, credits) \
+                 VALUES (?, 'p', 'succeeded', '2026-10-17 07:59:00', \
+                         CASE WHEN ? THEN '2026-10-17 08:00:00' END, ?, 10)",
+            )
+            .bind(id.to_string())
+            .bind(started)
+            .bind(finished_at.to_string())
+            .execute(&db.pool)
+        };
+        task("t1", "2026-10-17 08:05:00", true).await.unwrap();
+        task("t2", "2026-10-17 08:40:00", true).await.unwrap();
+        task("t3", "2026-10-17 08:50:00", false).await.unwrap();
+        task("t4", "2026-10-17 09:10:00", true).await.unwrap();
+
+        record_task(&db.pool, "s1", "t1", None).await;
+        record_task(&db.pool, "s1", "t2", Some("auth")).await;
+        record_task(&db.pool, "s1", "t3", Some("auth")).await;
+        record_task(&db.pool, "s1", "t4", Some("quota")).await;
+        // Another session's tasks have their own rows.
+        record_task(&db.pool, "s2", "t1", None).await;
+
+        let from = parse_bound("2026-10-17", false).unwrap();
+        let to = parse_bound("2026-10-17", true).unwrap();
+        let (hours, total) = query(&db.pool, "s1", from, to, Granularity::Hour).await.unwrap();
+        assert_eq!(hours.len(), 2);
+        let first = &hours[0];
+        assert_eq!(first.period, "2026-10-17 08:00:00");
+        assert_eq!((first.tasks, first.successes, first.failures), (3, 1, 2));
+        assert_eq!(first.failures_by_kind, BTreeMap::from([("auth".to_string(), 2)]));
+        // t3 never started, so only two tasks count towards the wait.
+        assert_eq!(first.avg_queue_wait_secs, Some(60.0));
+        assert_eq!(first.credits_spent, 30);
+        assert_eq!(hours[1].failures_by_kind, BTreeMap::from([("quota".to_string(), 1)]));
+        assert_eq!((total.tasks, total.failures, total.credits_spent), (4, 3, 40));
+
+        // The range is inclusive and filters by hour.
+        let to = parse_bound("2026-10-17 08:59:59", true).unwrap();
+        let (hours, _) = query(&db.pool, "s1", from, to, Granularity::Hour).await.unwrap();
+        assert_eq!(hours.len(), 1);
+    }
+}
@@ -0,0 +1,72 @@
+//! Per-task log of upstream calls: which transport each jimeng call of a
+//! task went through and how it ended.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::TaskQueue;
+use crate::jimeng::transport::{Endpoint, TransportAttempt};
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct TaskEvent {
+    /// Endpoint class of the call (`submit`, `poll`, `upload`, `account`);
+    /// `upload` events without a transport are chunked upload progress.
+    pub event: String,
+    pub transport: Option<String>,
+    /// `HTTP <status>` or the transport error.
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+impl TaskQueue {
+    /// Log the transport tries of `task_id`'s jimeng calls. First-try
+    /// successful polls are left out: a task polls every few seconds, for
+    /// hours when jimeng's queue is long.
+    pub async fn record_transport_events(&self, task_id: &str, attempts: &[TransportAttempt]) {
+        for attempt in attempts {
+            if attempt.endpoint == Endpoint::Poll && attempt.attempt == 1 && attempt.ok() {
+                continue;
+            }
+            let detail = match (&attempt.error, attempt.status) {
+                (Some(error), _) => error.clone(),
+                (None, Some(status)) => format!("HTTP {status}"),
+                (None, None) => String::new(),
+            };
+            let result = sqlx::query(
+                "INSERT INTO task_events (task_id, event, transport, detail) VALUES (?, ?, ?, ?)",
+            )
+            .bind(task_id)
+            .bind(attempt.endpoint.as_str())
+            .bind(attempt.transport.as_str())
+            .bind(&detail)
+            .execute(&self.db.pool)
+            .await;
+            if let Err(e) = result {
+                tracing::warn!(task_id, error = %e, "Failed to record task event");
+            }
+        }
+    }
+
+    /// Log a gateway-side step of a task, such as upload progress.
+    pub async fn record_task_event(&self, task_id: &str, event: &str, detail: &str) {
+        let result = sqlx::query("INSERT INTO task_events (task_id, event, detail) VALUES (?, ?, ?)")
+            .bind(task_id)
+            .bind(event)
+            .bind(detail)
+            .execute(&self.db.pool)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!(task_id, error = %e, "Failed to record task event");
+        }
+    }
+
+    /// Events of a task, oldest first.
+    pub async fn task_events(&self, task_id: &str) -> Result<Vec<TaskEvent>> {
+        Ok(sqlx::query_as::<_, TaskEvent>(
+            "SELECT event, transport, detail, created_at FROM task_events WHERE task_id = ? ORDER BY id",
+        )
+        .bind(task_id)
+        .fetch_all(&self.db.pool)
+        .await?)
+    }
+}
@@ -0,0 +1,137 @@
+//! Import generations from a session's upstream jimeng history as tasks.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::TaskQueue;
+use crate::jimeng::model_registry::ModelRegistry;
+use crate::jimeng::poll::{HistoryRecord, STATUS_FAILED, STATUS_SUCCEEDED};
+
+/// What happened to the records of one import.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HistoryImportCounts {
+    pub imported: usize,
+    /// Already known by `history_record_id`.
+    pub existing: usize,
+    /// Still generating upstream; a later import picks them up.
+    pub unfinished: usize,
+}
+
+impl TaskQueue {
+    /// Store finished history records of `session_id` as `imported` tasks,
+    /// skipping any whose `history_record_id` is already known.
+    pub async fn import_history(
+        &self,
+        session_id: &str,
+        records: &[HistoryRecord],
+        models: &ModelRegistry,
+    ) -> Result<HistoryImportCounts> {
+        let mut counts = HistoryImportCounts::default();
+        let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+
+        for record in records {
+            let result = &record.result;
+            let (status, output, error_message) = match result.status {
+                STATUS_SUCCEEDED => {
+                    let output = result.video_url.clone()
+                        .or_else(|| (!result.image_urls.is_empty()).then(|| result.image_urls.join(",")));
+                    ("succeeded", output, None)
+                }
+                STATUS_FAILED => {
+                    let message = result.fail_msg.clone()
+                        .or_else(|| result.fail_code.clone())
+                        .unwrap_or_else(|| "generation failed upstream".to_string());
+                    ("failed", None, Some(message))
+                }
+                _ => {
+                    counts.unfinished += 1;
+                    continue;
+                }
+            };
+            let error_kind = result.failure().map(|f| f.code().as_str());
+            let model = record.model.as_deref()
+                .map(|key| models.model_for_key(key).unwrap_or_else(|| key.to_string()))
+                .unwrap_or_else(|| "unknown".to_string());
+            let created_at = record.created_time
+                .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                .map_or_else(|| now.clone(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string());
+
+            let task_id = uuid::Uuid::new_v4().to_string();
+            // `history_record_id` is unique, so known records are ignored.
+            let inserted = sqlx::query(
+                "INSERT OR IGNORE INTO tasks (id, session_pool_id, status, model, prompt, history_record_id, \
+                 video_url, error_message, error_kind, source, created_at, updated_at, finished_at) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 'imported', ?, ?, ?)",
+            )
+            .bind(&task_id)
+            .bind(session_id)
+            .bind(status)
+            .bind(&model)
+            .bind(&record.prompt)
+            .bind(&record.history_record_id)
+            .bind(&output)
+            .bind(&error_message)
+            .bind(error_kind)
+            .bind(&created_at)
+            .bind(&now)
+            .bind(&created_at)
+            .execute(&self.db.pool)
+            .await?
+            .rows_affected() > 0;
+
+            if inserted {
+                crate::pool::stats::record_task(&self.db.pool, session_id, &task_id, error_kind).await;
+                counts.imported += 1;
+            } else {
+                counts.existing += 1;
+            }
+        }
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::jimeng::poll::{PollResult, STATUS_PENDING};
+    use crate::pool::{Pacing, SessionPool};
+    use crate::queue::UploadSpool;
+
+    fn record(id: &str, status: i64) -> HistoryRecord {
+        HistoryRecord {
+            history_record_id: id.into(),
+            prompt: "a cat".into(),
+            model: None,
+            created_time: Some(1_760_000_000),
+            result: PollResult {
+                status,
+                fail_code: None,
+                fail_msg: None,
+                video_url: Some(format!("https://v/{id}.mp4")),
+                image_urls: Vec::new(),
+                queue_position: None,
+                queue_total: None,
+                queue_eta: None,
+                item_id: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_skips_known_records() {
+        let db = Database::memory().await;
+        let queue = TaskQueue::new(db.clone(), SessionPool::new(db, Pacing::default()), 1, UploadSpool::new("unused"));
+        let models = ModelRegistry::load(None).unwrap();
+        let records = [record("h1", STATUS_SUCCEEDED), record("h2", STATUS_FAILED), record("h3", STATUS_PENDING)];
+
+        let counts = queue.import_history("s1", &records, &models).await.unwrap();
+        assert_eq!((counts.imported, counts.existing, counts.unfinished), (2, 0, 1));
+
+        let counts = queue.import_history("s1", &records[..2], &models).await.unwrap();
+        assert_eq!((counts.imported, counts.existing), (0, 2));
+        let tasks = queue.list_tasks(None, 10).await.unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().all(|t| t.source == "imported"));
+    }
+}
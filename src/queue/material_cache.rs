@@ -0,0 +1,213 @@
+//! Uploaded materials by content, per session.
+//!
+//! A storyboard sends the same reference image with every shot; instead of
+//! uploading it to ImageX again each time, tasks of the same session reuse
+//! the `uri`/`vid` of an earlier upload of identical bytes until the entry
+//! is older than `MATERIAL_CACHE_TTL_SECS`.
+
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use super::TaskQueue;
+use crate::jimeng::error::JimengError;
+use crate::jimeng::models::{MaterialType, UploadedMaterial};
+
+/// Cache key of a file's content, read in 1 MiB blocks.
+pub(super) async fn content_hash(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(hex::encode(hasher.finalize()));
+        }
+        hasher.update(&buf[..n]);
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct CachedRow {
+    uri: Option<String>,
+    vid: Option<String>,
+    width: u32,
+    height: u32,
+    duration: u32,
+    fps: u32,
+}
+
+/// The cache as seen by one task: its session's entries.
+pub(super) struct MaterialCache<'a> {
+    pub(super) queue: &'a TaskQueue,
+    pub(super) session_id: &'a str,
+    /// 0 disables the cache.
+    pub(super) ttl_secs: u64,
+}
+
+impl MaterialCache<'_> {
+    fn max_age(&self) -> String {
+        format!("-{} seconds", self.ttl_secs)
+    }
+
+    /// An upload of the same content younger than the TTL, named `name`.
+    pub(super) async fn get(&self, sha256: &str, material_type: MaterialType, name: &str) -> Option<UploadedMaterial> {
+        if self.ttl_secs == 0 {
+            return None;
+        }
+        let row = sqlx::query_as::<_, CachedRow>(
+            "SELECT uri, vid, width, height, duration, fps FROM material_cache \
+             WHERE session_id = ? AND sha256 = ? AND material_type = ? AND created_at > datetime('now', ?)",
+        )
+        .bind(self.session_id)
+        .bind(sha256)
+        .bind(material_type.as_str())
+        .bind(self.max_age())
+        .fetch_optional(&self.queue.db.pool)
+        .await;
+        match row {
+            Ok(row) => row.map(|row| UploadedMaterial {
+                material_type,
+                uri: row.uri,
+                vid: row.vid,
+                width: row.width,
+                height: row.height,
+                duration: row.duration,
+                fps: row.fps,
+                name: name.to_string(),
+            }),
+            Err(e) => {
+                tracing::warn!(session_id = self.session_id, error = %e, "Failed to read material cache");
+                None
+            }
+        }
+    }
+
+    /// Remember an upload, dropping the session's expired entries.
+    pub(super) async fn put(&self, sha256: &str, material: &UploadedMaterial) {
+        if self.ttl_secs == 0 {
+            return;
+        }
+        let _ = sqlx::query("DELETE FROM material_cache WHERE session_id = ? AND created_at <= datetime('now', ?)")
+            .bind(self.session_id)
+            .bind(self.max_age())
+            .execute(&self.queue.db.pool)
+            .await;
+        let result = sqlx::query(
+            "INSERT OR REPLACE INTO material_cache \
+             (session_id, sha256, material_type, uri, vid, width, height, duration, fps) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.session_id)
+        .bind(sha256)
+        .bind(material.material_type.as_str())
+        .bind(&material.uri)
+        .bind(&material.vid)
+        .bind(material.width)
+        .bind(material.height)
+        .bind(material.duration)
+        .bind(material.fps)
+        .execute(&self.queue.db.pool)
+        .await;
+        if let Err(e) = result {
+            tracing::warn!(session_id = self.session_id, error = %e, "Failed to cache material");
+        }
+    }
+
+    /// If a submit failed on a material and `reused` (content hashes of
+    /// cached materials it carried) is not empty, drop those entries and
+    /// return true: the task should upload them again and resubmit.
+    pub(super) async fn drop_rejected(&self, reused: &[String], error: &anyhow::Error) -> bool {
+        if reused.is_empty() || !error.downcast_ref::<JimengError>().is_some_and(JimengError::rejects_material) {
+            return false;
+        }
+        tracing::warn!(session_id = self.session_id, error = %error, "Submit rejected cached materials, uploading again");
+        for sha256 in reused {
+            let result = sqlx::query("DELETE FROM material_cache WHERE session_id = ? AND sha256 = ?")
+                .bind(self.session_id)
+                .bind(sha256)
+                .execute(&self.queue.db.pool)
+                .await;
+            if let Err(e) = result {
+                tracing::warn!(session_id = self.session_id, error = %e, "Failed to drop cached material");
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::pool::{Pacing, SessionPool};
+    use crate::queue::UploadSpool;
+
+    async fn queue() -> TaskQueue {
+        let db = Database::memory().await;
+        TaskQueue::new(db.clone(), SessionPool::new(db, Pacing::default()), 1, UploadSpool::new("unused"))
+    }
+
+    fn image(uri: &str) -> UploadedMaterial {
+        UploadedMaterial {
+            material_type: MaterialType::Image,
+            uri: Some(uri.into()),
+            vid: None,
+            width: 640,
+            height: 480,
+            duration: 0,
+            fps: 0,
+            name: "first.png".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_put_expire() {
+        let queue = queue().await;
+        let cache = MaterialCache { queue: &queue, session_id: "s1", ttl_secs: 3600 };
+        assert!(cache.get("abc", MaterialType::Image, "a.png").await.is_none());
+
+        cache.put("abc", &image("tos-cn-i/1")).await;
+        let hit = cache.get("abc", MaterialType::Image, "b.png").await.unwrap();
+        assert_eq!((hit.uri.as_deref(), hit.width, hit.name.as_str()), (Some("tos-cn-i/1"), 640, "b.png"));
+        // Entries are per session and per material type.
+        assert!(cache.get("abc", MaterialType::Video, "b.png").await.is_none());
+        let other = MaterialCache { session_id: "s2", ..cache };
+        assert!(other.get("abc", MaterialType::Image, "b.png").await.is_none());
+
+        // Past the TTL the entry is ignored, and dropped by the next put.
+        sqlx::query("UPDATE material_cache SET created_at = datetime('now', '-2 hours')")
+            .execute(&queue.db.pool)
+            .await
+            .unwrap();
+        assert!(cache.get("abc", MaterialType::Image, "b.png").await.is_none());
+        cache.put("def", &image("tos-cn-i/2")).await;
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM material_cache").fetch_one(&queue.db.pool).await.unwrap();
+        assert_eq!(count, 1);
+
+        // A TTL of 0 disables the cache.
+        let disabled = MaterialCache { ttl_secs: 0, ..cache };
+        assert!(disabled.get("def", MaterialType::Image, "c.png").await.is_none());
+        disabled.put("ghi", &image("tos-cn-i/3")).await;
+        assert!(cache.get("ghi", MaterialType::Image, "c.png").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_drop_rejected() {
+        let queue = queue().await;
+        let cache = MaterialCache { queue: &queue, session_id: "s1", ttl_secs: 3600 };
+        cache.put("abc", &image("tos-cn-i/1")).await;
+        let rejected = |errmsg: &str| anyhow::Error::new(JimengError::Api {
+            what: "Submit", ret: "1000".into(), errmsg: errmsg.into(), transport: None,
+        });
+
+        // Nothing reused, or a failure unrelated to materials: keep the entry.
+        assert!(!cache.drop_rejected(&[], &rejected("素材不存在")).await);
+        assert!(!cache.drop_rejected(&["abc".into()], &rejected("积分不足")).await);
+        assert!(cache.get("abc", MaterialType::Image, "a.png").await.is_some());
+
+        assert!(cache.drop_rejected(&["abc".into()], &rejected("素材不存在")).await);
+        assert!(cache.get("abc", MaterialType::Image, "a.png").await.is_none());
+    }
+}
@@ -1,5 +1,12 @@
+mod events;
+mod import;
+mod material_cache;
+mod spool;
 mod worker;
 
+pub use import::HistoryImportCounts;
+pub use spool::{FilesRemoved, SpooledFile, UploadSpool};
+
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -7,8 +14,18 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::{Notify, RwLock};
 
 use crate::db::Database;
+use crate::jimeng::model_registry::ModelRegistry;
+use crate::jimeng::media::{self, MediaInfo, ProbeError};
+use crate::jimeng::models::detect_material_type_from_mime;
+use crate::jimeng::submit::check_placeholders;
 use crate::pool::SessionPool;
 
+/// Columns selected whenever a full `TaskRecord` row is read.
+const TASK_COLUMNS: &str = "id, status, model, prompt, duration, ratio, session_pool_id, \
+     history_record_id, queue_position, queue_total, queue_eta, \
+     video_url, error_message, error_kind, queue_reason, source, \
+     created_at, updated_at, started_at, finished_at";
+
 /// Task status in the gateway's lifecycle.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
@@ -58,6 +75,12 @@ pub struct TaskRecord {
     pub video_url: Option<String>,
     pub error_message: Option<String>,
     pub error_kind: Option<String>,
+    /// Why a queued task is still waiting (e.g. no session with the
+    /// capabilities its model requires).
+    pub queue_reason: Option<String>,
+    /// `gateway` for tasks submitted here, `imported` for generations read
+    /// from a session's upstream history.
+    pub source: String,
     pub created_at: String,
     pub updated_at: String,
     pub started_at: Option<String>,
@@ -75,6 +98,59 @@ pub struct CreateTaskRequest {
     pub files: Option<Vec<FileInput>>,
     pub webhook_url: Option<String>,
     pub webhook_secret: Option<String>,
+    /// Submit even if reference files fail to upload or prompt placeholders
+    /// name missing materials (they are dropped). Off by default: such
+    /// tasks are rejected or fail before submitting.
+    #[serde(default)]
+    pub best_effort: bool,
+}
+
+impl CreateTaskRequest {
+    /// Check the request against its model in the registry: the model name
+    /// becomes the canonical one, missing duration/ratio/resolution take the
+    /// model's defaults, and `uploads` (reference files sent outside
+    /// `files`) plus `files` must be of a supported type and fit the model's
+    /// material limits. Unless `best_effort`, video prompt placeholders must
+    /// name one of them; with it, unusable files are left out instead.
+    pub fn apply_model(&mut self, models: &ModelRegistry, uploads: &[ProbedUpload]) -> Result<(), String> {
+        let model = models.resolve(self.model.as_deref())?;
+        let mut materials = Vec::new();
+        for (i, (filename, media)) in uploads.iter().enumerate() {
+            let checked = media.as_ref().map_err(ToString::to_string)
+                .and_then(|media| model.check_media(media).map(|()| media.material_type));
+            match checked {
+                Ok(material_type) => materials.push(material_type),
+                Err(_) if self.best_effort => {}
+                Err(e) => return Err(format!("material {} ({filename}): {e}", i + 1)),
+            }
+        }
+        for file in self.files.iter().flatten() {
+            match detect_material_type_from_mime(&file.mime_type) {
+                Some(material_type) => materials.push(material_type),
+                None if self.best_effort => {}
+                None => return Err(format!("{}: unsupported file type {}", file.filename, file.mime_type)),
+            }
+        }
+        model.apply(&mut self.duration, &mut self.ratio, &mut self.resolution, &materials)?;
+        if !self.best_effort && !model.is_image() {
+            check_placeholders(&self.prompt, materials.len()).map_err(|e| e.to_string())?;
+        }
+        self.model = Some(model.id.clone());
+        Ok(())
+    }
+}
+
+/// A reference file of a multipart request: its name and what probing its
+/// bytes found.
+pub type ProbedUpload = (String, Result<MediaInfo, ProbeError>);
+
+/// Probe spooled reference files, for `apply_model`.
+pub async fn probe_spooled(files: &[SpooledFile]) -> Vec<ProbedUpload> {
+    let mut probed = Vec::with_capacity(files.len());
+    for file in files {
+        probed.push((file.filename.clone(), media::probe_file(&file.path).await));
+    }
+    probed
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -93,20 +169,44 @@ pub struct TaskQueue {
     concurrency: usize,
     notify: Arc<Notify>,
     running: Arc<RwLock<usize>>,
+    uploads: UploadSpool,
 }
 
 impl TaskQueue {
-    pub fn new(db: Database, pool: SessionPool, concurrency: usize) -> Self {
+    pub fn new(db: Database, pool: SessionPool, concurrency: usize, uploads: UploadSpool) -> Self {
         Self {
             db,
             pool,
             concurrency,
+            uploads,
             notify: Arc::new(Notify::new()),
             running: Arc::new(RwLock::new(0)),
         }
     }
 
+    /// Where tasks' reference files are spooled.
+    pub fn uploads(&self) -> &UploadSpool {
+        &self.uploads
+    }
+
+    /// Enqueue a task whose reference files were already spooled under `id`
+    /// (see `UploadSpool::store_stream`).
+    pub async fn enqueue_spooled(
+        &self,
+        id: String,
+        req: CreateTaskRequest,
+        content_type: String,
+        files: &[SpooledFile],
+    ) -> Result<TaskRecord> {
+        let manifest = serde_json::to_string(files)?;
+        self.insert_task(id, req, None, Some(content_type), Some(manifest)).await
+    }
+
     /// Enqueue a new video generation task.
+    ///
+    /// The files of a multipart `request_body` are spooled to disk; the
+    /// body itself is only stored if they cannot be read from it, so the
+    /// worker fails (or, with `best_effort`, skips) them as before.
     pub async fn enqueue(
         &self,
         req: CreateTaskRequest,
@@ -114,6 +214,28 @@ impl TaskQueue {
         request_content_type: Option<String>,
     ) -> Result<TaskRecord> {
         let id = uuid::Uuid::new_v4().to_string();
+        let mut request_body = request_body.filter(|b| !b.is_empty());
+        let mut material_files = None;
+        if let (Some(body), Some(ct)) = (&request_body, &request_content_type) {
+            match self.uploads.store(&id, ct, body).await {
+                Ok(files) => {
+                    material_files = Some(serde_json::to_string(&files)?);
+                    request_body = None;
+                }
+                Err(e) => tracing::warn!(task_id = %id, error = %e, "Failed to spool reference files, storing the request body"),
+            }
+        }
+        self.insert_task(id, req, request_body, request_content_type, material_files).await
+    }
+
+    async fn insert_task(
+        &self,
+        id: String,
+        req: CreateTaskRequest,
+        request_body: Option<Vec<u8>>,
+        request_content_type: Option<String>,
+        material_files: Option<String>,
+    ) -> Result<TaskRecord> {
         let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
 
         let model = req.model.unwrap_or_else(|| "jimeng-video-seedance-2.0".to_string());
@@ -122,8 +244,8 @@ impl TaskQueue {
         let resolution = req.resolution;
 
         sqlx::query(
-            "INSERT INTO tasks (id, status, model, prompt, duration, ratio, resolution, request_body, request_content_type, webhook_url, webhook_secret, created_at, updated_at) \
-             VALUES (?, 'queued', ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            "INSERT INTO tasks (id, status, model, prompt, duration, ratio, resolution, request_body, request_content_type, material_files, webhook_url, webhook_secret, best_effort, created_at, updated_at) \
+             VALUES (?, 'queued', ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(&id)
         .bind(&model)
@@ -133,8 +255,10 @@ impl TaskQueue {
         .bind(&resolution)
         .bind(&request_body)
         .bind(&request_content_type)
+        .bind(&material_files)
         .bind(&req.webhook_url)
-        .bind(&req.webhook_secret)
+        .bind(crate::crypto::seal_opt(req.webhook_secret.as_deref())?)
+        .bind(req.best_effort)
         .bind(&now)
         .bind(&now)
         .execute(&self.db.pool)
@@ -155,6 +279,8 @@ impl TaskQueue {
             video_url: None,
             error_message: None,
             error_kind: None,
+            queue_reason: None,
+            source: "gateway".to_string(),
             created_at: now.clone(),
             updated_at: now,
             started_at: None,
@@ -172,11 +298,7 @@ impl TaskQueue {
     pub async fn list_tasks(&self, status: Option<&str>, limit: i64) -> Result<Vec<TaskRecord>> {
         let tasks = if let Some(status) = status {
             sqlx::query_as::<_, TaskQueryRow>(
-                "SELECT id, status, model, prompt, duration, ratio, session_pool_id, \
-                 history_record_id, queue_position, queue_total, queue_eta, \
-                 video_url, error_message, error_kind, \
-                 created_at, updated_at, started_at, finished_at \
-                 FROM tasks WHERE status = ? ORDER BY created_at DESC LIMIT ?",
+                &format!("SELECT {TASK_COLUMNS} FROM tasks WHERE status = ? ORDER BY created_at DESC LIMIT ?"),
             )
             .bind(status)
             .bind(limit)
@@ -184,11 +306,7 @@ impl TaskQueue {
             .await?
         } else {
             sqlx::query_as::<_, TaskQueryRow>(
-                "SELECT id, status, model, prompt, duration, ratio, session_pool_id, \
-                 history_record_id, queue_position, queue_total, queue_eta, \
-                 video_url, error_message, error_kind, \
-                 created_at, updated_at, started_at, finished_at \
-                 FROM tasks ORDER BY created_at DESC LIMIT ?",
+                &format!("SELECT {TASK_COLUMNS} FROM tasks ORDER BY created_at DESC LIMIT ?"),
             )
             .bind(limit)
             .fetch_all(&self.db.pool)
@@ -201,11 +319,7 @@ impl TaskQueue {
     /// Get a single task by ID.
     pub async fn get_task(&self, id: &str) -> Result<Option<TaskRecord>> {
         let row = sqlx::query_as::<_, TaskQueryRow>(
-            "SELECT id, status, model, prompt, duration, ratio, session_pool_id, \
-             history_record_id, queue_position, queue_total, queue_eta, \
-             video_url, error_message, error_kind, \
-             created_at, updated_at, started_at, finished_at \
-             FROM tasks WHERE id = ?",
+            &format!("SELECT {TASK_COLUMNS} FROM tasks WHERE id = ?"),
         )
         .bind(id)
         .fetch_optional(&self.db.pool)
@@ -224,13 +338,17 @@ impl TaskQueue {
         .execute(&self.db.pool)
         .await?;
 
-        Ok(result.rows_affected() > 0)
+        let cancelled = result.rows_affected() > 0;
+        if cancelled {
+            self.uploads.remove(id).await;
+        }
+        Ok(cancelled)
     }
 
     /// Retry a task by cloning its original payload into a new queued record.
     pub async fn retry_task(&self, id: &str) -> Result<Option<TaskRecord>> {
         let src = sqlx::query_as::<_, RetryTaskRow>(
-            "SELECT model, prompt, duration, ratio, resolution, request_body, request_content_type \
+            "SELECT model, prompt, duration, ratio, resolution, request_body, request_content_type, material_files, best_effort \
              FROM tasks WHERE id = ?",
         )
         .bind(id)
@@ -250,11 +368,18 @@ impl TaskQueue {
             files: None,
             webhook_url: None,
             webhook_secret: None,
+            best_effort: src.best_effort,
         };
 
-        let task = self
-            .enqueue(req, src.request_body, src.request_content_type)
-            .await?;
+        let task = match src.material_files {
+            Some(manifest) => {
+                let new_id = uuid::Uuid::new_v4().to_string();
+                let files = self.uploads.open(id, &manifest)?;
+                self.uploads.copy(id, &new_id, &files).await?;
+                self.insert_task(new_id, req, None, src.request_content_type, Some(manifest)).await?
+            }
+            None => self.enqueue(req, src.request_body, src.request_content_type).await?,
+        };
         Ok(Some(task))
     }
 
@@ -263,19 +388,22 @@ impl TaskQueue {
         let row = sqlx::query_as::<_, StatsRow>(
             "SELECT \
                COALESCE(COUNT(*), 0) as total, \
-               COALESCE(SUM(CASE WHEN status = 'queued' THEN 1 ELSE 0 END), 0) as queued, \
+               COALESCE(SUM(CASE WHEN status = 'queued' AND COALESCE(queue_reason, '') != ?1 THEN 1 ELSE 0 END), 0) as queued, \
+               COALESCE(SUM(CASE WHEN status = 'queued' AND queue_reason = ?1 THEN 1 ELSE 0 END), 0) as quota_blocked, \
                COALESCE(SUM(CASE WHEN status IN ('submitting', 'polling', 'downloading') THEN 1 ELSE 0 END), 0) as running, \
                COALESCE(SUM(CASE WHEN status = 'succeeded' THEN 1 ELSE 0 END), 0) as succeeded, \
                COALESCE(SUM(CASE WHEN status = 'failed' THEN 1 ELSE 0 END), 0) as failed, \
                COALESCE(SUM(CASE WHEN status = 'cancelled' THEN 1 ELSE 0 END), 0) as cancelled \
              FROM tasks",
         )
+        .bind(crate::pool::QUOTA_BLOCKED_REASON)
         .fetch_one(&self.db.pool)
         .await?;
 
         Ok(serde_json::json!({
             "total": row.total,
             "queued": row.queued,
+            "quota_blocked": row.quota_blocked,
             "running": row.running,
             "succeeded": row.succeeded,
             "failed": row.failed,
@@ -313,6 +441,8 @@ struct TaskQueryRow {
     video_url: Option<String>,
     error_message: Option<String>,
     error_kind: Option<String>,
+    queue_reason: Option<String>,
+    source: String,
     created_at: String,
     updated_at: String,
     started_at: Option<String>,
@@ -345,6 +475,8 @@ impl From<TaskQueryRow> for TaskRecord {
             video_url: row.video_url,
             error_message: row.error_message,
             error_kind: row.error_kind,
+            queue_reason: row.queue_reason,
+            source: row.source,
             created_at: row.created_at,
             updated_at: row.updated_at,
             started_at: row.started_at,
@@ -357,6 +489,7 @@ impl From<TaskQueryRow> for TaskRecord {
 struct StatsRow {
     total: i32,
     queued: i32,
+    quota_blocked: i32,
     running: i32,
     succeeded: i32,
     failed: i32,
@@ -372,4 +505,6 @@ struct RetryTaskRow {
     resolution: Option<String>,
     request_body: Option<Vec<u8>>,
     request_content_type: Option<String>,
+    material_files: Option<String>,
+    best_effort: bool,
 }
@@ -0,0 +1,183 @@
+//! Reference files of tasks, kept on disk.
+//!
+//! A multipart request's files are streamed to `UPLOAD_DIR/<task id>/<n>`
+//! as the request is read; the task row only keeps their manifest. The
+//! worker probes, hashes and uploads them from there, so a large reference
+//! clip is never held in SQLite or read into memory whole. The directory is
+//! removed once the task succeeds, fails or is cancelled.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use super::worker::extract_multipart_files;
+
+/// A spooled reference file, as listed in `tasks.material_files`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpooledFile {
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    /// Filled in from the task's directory when the manifest is read.
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// A finished task's files are removed, so it cannot be retried with them.
+#[derive(Debug, thiserror::Error)]
+#[error("reference files of task {0} were removed when it finished; submit it again")]
+pub struct FilesRemoved(pub String);
+
+#[derive(Debug, Clone)]
+pub struct UploadSpool {
+    dir: PathBuf,
+}
+
+impl UploadSpool {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn task_dir(&self, task_id: &str) -> PathBuf {
+        self.dir.join(task_id)
+    }
+
+    /// Write file `index` of a task as its chunks arrive.
+    pub async fn store_stream<E>(
+        &self,
+        task_id: &str,
+        index: usize,
+        filename: String,
+        content_type: String,
+        chunks: impl Stream<Item = Result<Bytes, E>>,
+    ) -> Result<SpooledFile>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let dir = self.task_dir(task_id);
+        tokio::fs::create_dir_all(&dir).await
+            .with_context(|| format!("creating {}", dir.display()))?;
+        let path = dir.join(index.to_string());
+        let mut file = tokio::fs::File::create(&path).await
+            .with_context(|| format!("creating {}", path.display()))?;
+        let mut size = 0;
+        let mut chunks = std::pin::pin!(chunks);
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await
+                .with_context(|| format!("writing {}", path.display()))?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+        Ok(SpooledFile { filename, content_type, size, path })
+    }
+
+    /// Write the files of a buffered multipart body (tasks queued before
+    /// files were spooled) to the task's directory.
+    pub(super) async fn store(&self, task_id: &str, content_type: &str, body: &[u8]) -> Result<Vec<SpooledFile>> {
+        let parts = extract_multipart_files(content_type, body)?;
+        let dir = self.task_dir(task_id);
+        tokio::fs::create_dir_all(&dir).await
+            .with_context(|| format!("creating {}", dir.display()))?;
+        let mut files = Vec::with_capacity(parts.len());
+        for (i, part) in parts.into_iter().enumerate() {
+            let path = dir.join(i.to_string());
+            tokio::fs::write(&path, &part.data).await
+                .with_context(|| format!("writing {}", path.display()))?;
+            files.push(SpooledFile {
+                filename: part.filename,
+                content_type: part.content_type,
+                size: part.data.len() as u64,
+                path,
+            });
+        }
+        Ok(files)
+    }
+
+    /// Copy another task's files, for a retry.
+    pub(super) async fn copy(&self, from_task: &str, to_task: &str, files: &[SpooledFile]) -> Result<()> {
+        if !tokio::fs::try_exists(self.task_dir(from_task)).await.unwrap_or(false) {
+            return Err(FilesRemoved(from_task.to_string()).into());
+        }
+        let dir = self.task_dir(to_task);
+        tokio::fs::create_dir_all(&dir).await
+            .with_context(|| format!("creating {}", dir.display()))?;
+        for i in 0..files.len() {
+            let from = self.task_dir(from_task).join(i.to_string());
+            tokio::fs::copy(&from, dir.join(i.to_string())).await
+                .with_context(|| format!("copying {}", from.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Delete a task's files, if it has any.
+    pub async fn remove(&self, task_id: &str) {
+        let dir = self.task_dir(task_id);
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => tracing::debug!(task_id, "Removed spooled reference files"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(task_id, error = %e, "Failed to remove spooled reference files"),
+        }
+    }
+
+    /// Parse a task's manifest, pointing each file at its spooled copy.
+    pub(super) fn open(&self, task_id: &str, manifest: &str) -> Result<Vec<SpooledFile>> {
+        let mut files: Vec<SpooledFile> = serde_json::from_str(manifest).context("invalid material_files")?;
+        let dir = self.task_dir(task_id);
+        for (i, file) in files.iter_mut().enumerate() {
+            file.path = dir.join(i.to_string());
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_open_copy_remove() {
+        let spool = UploadSpool::new(std::env::temp_dir().join(format!("spool-{}", uuid::Uuid::new_v4())));
+
+        let body = b"--XB\r\n\
+            Content-Disposition: form-data; name=\"prompt\"\r\n\r\nhi\r\n\
+            --XB\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\x89PNG\r\n\
+            --XB--\r\n";
+        let stored = spool.store("t1", "multipart/form-data; boundary=XB", body).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!((stored[0].filename.as_str(), stored[0].content_type.as_str(), stored[0].size), ("a.png", "image/png", 4));
+
+        let chunks = futures::stream::iter([Ok::<_, std::io::Error>(Bytes::from_static(b"ab")), Ok(Bytes::from_static(b"cd"))]);
+        let streamed = spool.store_stream("t1", 1, "b.mp4".into(), "video/mp4".into(), chunks).await.unwrap();
+        assert_eq!(streamed.size, 4);
+
+        let manifest = serde_json::to_string(&[stored[0].clone(), streamed]).unwrap();
+        assert!(!manifest.contains("path"));
+        let files = spool.open("t1", &manifest).unwrap();
+        assert_eq!(tokio::fs::read(&files[0].path).await.unwrap(), b"\x89PNG");
+        assert_eq!(tokio::fs::read(&files[1].path).await.unwrap(), b"abcd");
+
+        spool.copy("t1", "t2", &files).await.unwrap();
+        let copies = spool.open("t2", &manifest).unwrap();
+        assert_eq!(tokio::fs::read(&copies[1].path).await.unwrap(), b"abcd");
+
+        spool.remove("t1").await;
+        assert!(!files[0].path.exists());
+        let err = spool.copy("t1", "t3", &files).await.unwrap_err();
+        assert!(err.is::<FilesRemoved>());
+        // Removing twice is fine.
+        spool.remove("t1").await;
+
+        // A failing stream fails the file.
+        let broken = futures::stream::iter([Err(std::io::Error::other("reset"))]);
+        assert!(spool.store_stream("t2", 2, "c".into(), "x".into(), broken).await.is_err());
+
+        tokio::fs::remove_dir_all(&spool.dir).await.unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -5,23 +6,22 @@ use anyhow::Result;
 use reqwest::Client;
 
 use super::TaskQueue;
+use super::material_cache::{MaterialCache, content_hash};
+use super::spool::SpooledFile;
 use crate::AppState;
-use crate::jimeng::{models, poll, submit, upload};
+use crate::cookie_refresh::Trigger;
+use crate::jimeng::{media, models, poll, proxy, submit};
+use crate::jimeng::client::JimengClient;
+use crate::jimeng::error::{ErrorCode, JimengError, error_code};
+use crate::jimeng::model_registry::ModelSpec;
 use crate::jimeng::models::{MaterialType, UploadedMaterial};
+use crate::jimeng::upload::{UploadProgress, VodUploadResult};
+use crate::pool::QUOTA_BLOCKED_REASON;
 
 /// Background worker: dequeue tasks, submit to jimeng, poll for results.
 pub async fn worker_loop(queue: TaskQueue, state: Arc<AppState>) {
-    // Don't enable auto decompression — it adds Accept-Encoding headers
-    // that conflict with ByteDance's fingerprint validation.
-    let client = Client::builder()
-        .timeout(Duration::from_secs(120))
-        .no_proxy()
-        // Suppress reqwest's default "reqwest/x.y.z" User-Agent header.
-        // When using a full cookie jar, any UA mismatch triggers ByteDance 4013.
-        // build_headers_with_cookies() sets the appropriate UA per-request.
-        .user_agent("")
-        .build()
-        .expect("Failed to build HTTP client");
+    // One HTTP client per outbound proxy (None = direct), built lazily.
+    let mut clients: HashMap<Option<String>, Client> = HashMap::new();
 
     loop {
         tokio::select! {
@@ -29,17 +29,21 @@ pub async fn worker_loop(queue: TaskQueue, state: Arc<AppState>) {
             () = tokio::time::sleep(Duration::from_secs(5)) => {},
         }
 
-        let task_row = sqlx::query_as::<_, TaskIdRow>(
+        // Tasks re-queued for lack of a session carry `not_before`, so
+        // other queued tasks get a chance instead of head-of-line blocking.
+        let task_row = sqlx::query_as::<_, ClaimedTaskRow>(
             "UPDATE tasks SET status = 'submitting', started_at = datetime('now'), \
              updated_at = datetime('now') \
-             WHERE id = (SELECT id FROM tasks WHERE status = 'queued' ORDER BY created_at LIMIT 1) \
-             RETURNING id",
+             WHERE id = (SELECT id FROM tasks WHERE status = 'queued' \
+                         AND (not_before IS NULL OR not_before <= datetime('now')) \
+                         ORDER BY created_at LIMIT 1) \
+             RETURNING id, model, resolution",
         )
         .fetch_optional(&queue.db.pool)
         .await;
 
-        let task_id = match task_row {
-            Ok(Some(row)) => row.id,
+        let claimed = match task_row {
+            Ok(Some(row)) => row,
             Ok(None) => continue,
             Err(e) => {
                 tracing::error!("Failed to claim task: {e}");
@@ -47,26 +51,54 @@ pub async fn worker_loop(queue: TaskQueue, state: Arc<AppState>) {
                 continue;
             }
         };
+        let task_id = claimed.id;
+        // The model may have been dropped from the registry since the task
+        // was queued.
+        let Some(model) = state.models.get(&claimed.model) else {
+            let message = format!("unknown model: {}", claimed.model);
+            tracing::error!(task_id, model = claimed.model, "Task model is not in the registry");
+            if let Err(e) = sqlx::query(
+                "UPDATE tasks SET status = 'failed', error_message = ?, error_kind = ?, \
+                 finished_at = datetime('now'), updated_at = datetime('now') WHERE id = ?",
+            )
+            .bind(&message)
+            .bind(ErrorCode::Unknown.as_str())
+            .bind(&task_id)
+            .execute(&queue.db.pool)
+            .await {
+                tracing::warn!(task_id, error = %e, "Failed to mark task failed");
+            }
+            queue.uploads.remove(&task_id).await;
+            crate::webhook::enqueue_delivery(&queue.db.pool, &task_id).await;
+            continue;
+        };
+        let required = model.required_capabilities(claimed.resolution.as_deref());
 
-        let session = match queue.pool.pick_session().await {
+        let session = match queue.pool.pick_session(&required).await {
             Some(s) => s,
             None => {
-                tracing::warn!(task_id, "No available session, re-queuing task");
+                let reason = queue.pool.unavailable_reason(&required).await;
+                tracing::warn!(task_id, reason, "No available session, re-queuing task");
+                // Quota only comes back at the daily reset; check less often.
+                let delay = if reason == QUOTA_BLOCKED_REASON { "+60 seconds" } else { "+10 seconds" };
                 if let Err(e) = sqlx::query(
-                    "UPDATE tasks SET status = 'queued', updated_at = datetime('now') WHERE id = ?",
+                    "UPDATE tasks SET status = 'queued', queue_reason = ?, \
+                     not_before = datetime('now', ?), updated_at = datetime('now') WHERE id = ?",
                 )
+                .bind(&reason)
+                .bind(delay)
                 .bind(&task_id)
                 .execute(&queue.db.pool)
                 .await {
                     tracing::warn!(task_id, error = %e, "Failed to re-queue task");
                 }
-                tokio::time::sleep(Duration::from_secs(10)).await;
                 continue;
             }
         };
 
         if let Err(e) = sqlx::query(
-            "UPDATE tasks SET session_pool_id = ?, updated_at = datetime('now') WHERE id = ?",
+            "UPDATE tasks SET session_pool_id = ?, queue_reason = NULL, not_before = NULL, \
+             updated_at = datetime('now') WHERE id = ?",
         )
         .bind(&session.id)
         .bind(&task_id)
@@ -79,10 +111,22 @@ pub async fn worker_loop(queue: TaskQueue, state: Arc<AppState>) {
 
         tracing::info!(task_id, session_id = session.id, "Processing task");
 
-        let result = execute_task(
-            &queue, &state, &client, &task_id,
-            &session.session_id, session.cookie_jar.as_deref(),
-        ).await;
+        let mut submitted = false;
+        let result = match client_for(&mut clients, session.proxy_url.as_deref()) {
+            Ok(http) => {
+                let mut jimeng = state.jimeng_client(&session, http);
+                let result = execute_task(&queue, &state, &mut jimeng, &task_id, &session.id, &model, &mut submitted).await;
+                queue.record_transport_events(&task_id, &jimeng.take_attempts()).await;
+                queue.pool.save_client_state(&session, &jimeng).await;
+                result
+            }
+            Err(e) => Err(e.context("invalid session proxy")),
+        };
+        if !submitted
+            && let Err(e) = queue.pool.unreserve_submit(&session).await
+        {
+            tracing::warn!(task_id, error = %e, "Failed to give back the session's submit slot");
+        }
 
         *queue.running.write().await -= 1;
 
@@ -100,38 +144,61 @@ pub async fn worker_loop(queue: TaskQueue, state: Arc<AppState>) {
                     tracing::warn!(task_id, error = %e, "Failed to mark task succeeded");
                 }
 
+                queue.uploads.remove(&task_id).await;
                 let _ = queue.pool.release_session(&session.id, true, None).await;
+                crate::pool::stats::record_task(&queue.db.pool, &session.id, &task_id, None).await;
                 tracing::info!(task_id, "Task succeeded");
                 crate::webhook::enqueue_delivery(&queue.db.pool, &task_id).await;
             }
             Err(e) => {
                 if is_task_cancelled(&queue, &task_id).await {
+                    queue.uploads.remove(&task_id).await;
                     let _ = queue.pool.release_session(&session.id, false, Some("cancelled by user")).await;
                     tracing::info!(task_id, "Task cancelled by user");
                     continue;
                 }
 
                 let err_msg = e.to_string();
-                let err_kind = classify_error(&err_msg);
+                let code = error_code(&e);
+                let err_kind = code.as_str();
+
+                let daily_limit = e.downcast_ref::<JimengError>().is_some_and(JimengError::is_daily_limit);
+                if daily_limit && requeue_on_daily_limit(&queue, &task_id, &session.id, &required).await {
+                    let _ = queue.pool.release_session(&session.id, false, Some(&err_msg)).await;
+                    crate::pool::stats::record_task(&queue.db.pool, &session.id, &task_id, Some(err_kind)).await;
+                    queue.notify.notify_one();
+                    continue;
+                }
 
                 if let Err(e) = sqlx::query(
                     "UPDATE tasks SET status = 'failed', error_message = ?, error_kind = ?, \
                      finished_at = datetime('now'), updated_at = datetime('now') WHERE id = ?",
                 )
                 .bind(&err_msg)
-                .bind(&err_kind)
+                .bind(err_kind)
                 .bind(&task_id)
                 .execute(&queue.db.pool)
                 .await {
                     tracing::warn!(task_id, error = %e, "Failed to mark task failed");
                 }
+                queue.uploads.remove(&task_id).await;
+
+                if code == ErrorCode::InvalidInput {
+                    // The task's own input is at fault, not the session.
+                    let _ = queue.pool.release_neutral(&session.id).await;
+                } else {
+                    let _ = queue.pool.release_session(&session.id, false, Some(&err_msg)).await;
+                    crate::pool::stats::record_task(&queue.db.pool, &session.id, &task_id, Some(err_kind)).await;
+                }
 
-                let _ = queue.pool.release_session(&session.id, false, Some(&err_msg)).await;
-
-                if err_kind == "auth" || err_kind == "account_blocked" {
+                if matches!(code, ErrorCode::Auth | ErrorCode::AccountBlocked) {
                     let _ = queue.pool.mark_unhealthy(&session.id).await;
                     tracing::warn!(task_id, session = session.id, kind = err_kind, "Session marked unhealthy");
                 }
+                if matches!(code, ErrorCode::Auth | ErrorCode::RiskControl) {
+                    // Stale or mismatched cookies are a common cause; re-harvest.
+                    state.refresh.trigger(&session.id, Trigger::AuthFailure);
+                }
 
                 tracing::error!(task_id, error = %e, "Task failed");
                 crate::webhook::enqueue_delivery(&queue.db.pool, &task_id).await;
@@ -140,71 +207,126 @@ pub async fn worker_loop(queue: TaskQueue, state: Arc<AppState>) {
     }
 }
 
+/// Times a task goes back to the queue because its session hit the daily
+/// usage limit before it fails with the quota error instead.
+const MAX_QUOTA_REQUEUES: i64 = 3;
+
+/// The account is out for today, not the task: park the session until the
+/// daily reset and queue the task again, unless it was re-queued for quota
+/// `MAX_QUOTA_REQUEUES` times already. Returns whether it was re-queued.
+async fn requeue_on_daily_limit(queue: &TaskQueue, task_id: &str, session_id: &str, required: &[&str]) -> bool {
+    match queue.pool.mark_quota_exhausted(session_id).await {
+        Ok(until) => tracing::warn!(task_id, session = session_id, until, "Session out of daily quota"),
+        Err(e) => tracing::warn!(task_id, session = session_id, error = %e, "Failed to mark session quota exhausted"),
+    }
+    // Only blocked on quota if no other capable session is left with some.
+    let reason = queue.pool.unavailable_reason(required).await;
+    let reason = (reason == QUOTA_BLOCKED_REASON).then_some(reason);
+    let result = sqlx::query(
+        "UPDATE tasks SET status = 'queued', session_pool_id = NULL, \
+         quota_requeues = quota_requeues + 1, queue_reason = ?, not_before = NULL, updated_at = datetime('now') \
+         WHERE id = ? AND status != 'cancelled' AND quota_requeues < ?",
+    )
+    .bind(&reason)
+    .bind(task_id)
+    .bind(MAX_QUOTA_REQUEUES)
+    .execute(&queue.db.pool)
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() > 0 => true,
+        Ok(_) => {
+            tracing::warn!(task_id, "Task hit the daily usage limit too often, failing it");
+            false
+        }
+        Err(e) => {
+            tracing::warn!(task_id, error = %e, "Failed to re-queue task");
+            false
+        }
+    }
+}
+
+/// Get (or build) the HTTP client for a session's proxy.
+fn client_for(clients: &mut HashMap<Option<String>, Client>, proxy_url: Option<&str>) -> Result<Client> {
+    let key = proxy_url.map(str::to_string);
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.clone());
+    }
+    let client = proxy::http_client(proxy_url, Duration::from_secs(120))?;
+    clients.insert(key, client.clone());
+    Ok(client)
+}
+
 /// Execute the full video generation pipeline via direct jimeng API.
+/// `submitted` is set once a submit has been sent on the session.
 async fn execute_task(
     queue: &TaskQueue,
     state: &AppState,
-    client: &Client,
+    jimeng: &mut JimengClient,
     task_id: &str,
-    session_token: &str,
-    cookie_jar: Option<&str>,
+    session_id: &str,
+    model: &ModelSpec,
+    submitted: &mut bool,
 ) -> Result<String> {
     let task_meta = sqlx::query_as::<_, TaskMetaRow>(
-        "SELECT prompt, duration, ratio, model, resolution, request_body, request_content_type FROM tasks WHERE id = ?",
+        "SELECT prompt, duration, ratio, resolution, request_body, request_content_type, material_files, best_effort \
+         FROM tasks WHERE id = ?",
     )
     .bind(task_id)
     .fetch_one(&queue.db.pool)
     .await?;
 
-    let model_name = &task_meta.model;
-    let is_image = models::is_image_model(model_name);
+    let is_image = model.is_image();
+    let cache = MaterialCache { queue, session_id, ttl_secs: state.config.material_cache_ttl_secs };
 
     // Poll for results
     let poll_interval = Duration::from_secs(state.config.poll_interval_secs.max(1));
     let deadline = Instant::now() + Duration::from_secs(state.config.max_poll_duration_secs.max(60));
 
     if is_image {
-        let resolution_str = task_meta.resolution.as_deref().unwrap_or("2k");
+        let resolution_str = task_meta.resolution.as_deref().unwrap_or(&model.default_resolution);
         let image_res = models::resolve_image_resolution(resolution_str, &task_meta.ratio)
             .map_err(|e| anyhow::anyhow!("{e}"))?;
 
         update_status(queue, task_id, "submitting").await;
 
-        // Process uploaded reference images from multipart body
-        let materials = process_materials(
-            client,
-            session_token,
-            task_meta.request_body.as_deref(),
-            task_meta.request_content_type.as_deref(),
-        ).await;
-
-        let reference_uris: Vec<String> = materials.iter()
-            .filter_map(|m| m.uri.clone())
-            .collect();
-
-        tracing::info!(task_id, ref_images = reference_uris.len(), "Submitting image generation task via direct HTTP");
-        let submit_result = submit::submit_image_generation(
-            client,
-            session_token,
-            &task_meta.prompt,
-            model_name,
-            image_res.width,
-            image_res.height,
-            image_res.ratio_code,
-            resolution_str,
-            0.5,
-            "",
-            &reference_uris,
-            cookie_jar,
-        ).await?;
+        // Process uploaded reference images from multipart body; if jimeng
+        // rejects cached ones, upload them again once.
+        let mut retried = false;
+        let submit_result = loop {
+            let materials = process_materials(jimeng, &task_meta, task_id, &cache, model).await?;
+
+            let reference_images: Vec<UploadedMaterial> = materials.uploaded.into_iter()
+                .filter(|m| m.uri.is_some())
+                .collect();
+
+            tracing::info!(task_id, ref_images = reference_images.len(), "Submitting image generation task via direct HTTP");
+            submit_slot(queue, session_id, submitted).await?;
+            match jimeng.submit_image(
+                &task_meta.prompt,
+                model,
+                image_res.width,
+                image_res.height,
+                image_res.ratio_code,
+                resolution_str,
+                0.5,
+                "",
+                &reference_images,
+            ).await {
+                Err(e) if !retried && cache.drop_rejected(&materials.reused, &e).await => retried = true,
+                result => break result?,
+            }
+        };
 
+        queue.record_transport_events(task_id, &jimeng.take_attempts()).await;
         let history_record_id = submit_result.history_record_id;
         tracing::info!(task_id, %history_record_id, "Image task submitted, starting poll");
 
         let _ = sqlx::query(
-            "UPDATE tasks SET status = 'polling', history_record_id = ?, updated_at = datetime('now') WHERE id = ?",
+            "UPDATE tasks SET status = 'polling', history_record_id = ?, credits = ?, \
+             updated_at = datetime('now') WHERE id = ?",
         )
         .bind(&history_record_id)
+        .bind(submit_result.credits)
         .bind(task_id)
         .execute(&queue.db.pool)
         .await;
@@ -214,10 +336,12 @@ async fn execute_task(
                 anyhow::bail!("Task cancelled");
             }
             if Instant::now() >= deadline {
-                anyhow::bail!("Polling timed out after {}s", state.config.max_poll_duration_secs);
+                return Err(JimengError::PollTimeout { secs: state.config.max_poll_duration_secs }.into());
             }
 
-            let poll_result = poll::poll_status(client, session_token, &history_record_id, cookie_jar).await?;
+            let poll_result = jimeng.poll(&history_record_id).await;
+            queue.record_transport_events(task_id, &jimeng.take_attempts()).await;
+            let poll_result = poll_result?;
 
             let _ = sqlx::query(
                 "UPDATE tasks SET status = 'polling', queue_position = ?, queue_total = ?, \
@@ -230,10 +354,8 @@ async fn execute_task(
             .execute(&queue.db.pool)
             .await;
 
-            if poll_result.status == poll::STATUS_FAILED {
-                let fail_code = poll_result.fail_code.as_deref().unwrap_or("unknown");
-                let fail_msg = poll_result.fail_msg.as_deref().unwrap_or("");
-                anyhow::bail!("{fail_code}: {fail_msg}");
+            if let Some(failure) = poll_result.failure() {
+                return Err(failure.into());
             }
 
             if !poll_result.image_urls.is_empty() {
@@ -246,41 +368,48 @@ async fn execute_task(
         }
     } else {
         // Video generation path
-        let res = models::resolve_video_resolution("720p", &task_meta.ratio)
+        let resolution_str = task_meta.resolution.as_deref().unwrap_or(&model.default_resolution);
+        let res = models::resolve_video_resolution(resolution_str, &task_meta.ratio)
             .map_err(|e| anyhow::anyhow!("{e}"))?;
 
         update_status(queue, task_id, "submitting").await;
 
-        // Process uploaded materials from multipart body
-        let materials = process_materials(
-            client,
-            session_token,
-            task_meta.request_body.as_deref(),
-            task_meta.request_content_type.as_deref(),
-        ).await;
-
-        // Submit task via browser proxy (a_bogus signing)
-        tracing::info!(task_id, materials_count = materials.len(), "Submitting Seedance task via browser proxy");
-        let submit_result = submit::submit_seedance_video(
-            client,
-            &state.browser,
-            session_token,
-            &task_meta.prompt,
-            model_name,
-            res.width,
-            res.height,
-            task_meta.duration as u32,
-            &materials,
-            cookie_jar,
-        ).await?;
+        // Process uploaded materials from multipart body; if jimeng rejects
+        // cached ones, upload them again once.
+        let mut retried = false;
+        let submit_result = loop {
+            let materials = process_materials(jimeng, &task_meta, task_id, &cache, model).await?;
 
+            if !task_meta.best_effort {
+                submit::check_placeholders(&task_meta.prompt, materials.uploaded.len())?;
+            }
+
+            // Submit task via browser proxy (a_bogus signing)
+            tracing::info!(task_id, materials_count = materials.uploaded.len(), "Submitting Seedance task via browser proxy");
+            submit_slot(queue, session_id, submitted).await?;
+            match jimeng.submit_video(
+                &task_meta.prompt,
+                model,
+                res.width,
+                res.height,
+                task_meta.duration as u32,
+                &materials.uploaded,
+            ).await {
+                Err(e) if !retried && cache.drop_rejected(&materials.reused, &e).await => retried = true,
+                result => break result?,
+            }
+        };
+
+        queue.record_transport_events(task_id, &jimeng.take_attempts()).await;
         let history_record_id = submit_result.history_record_id;
         tracing::info!(task_id, %history_record_id, "Task submitted, starting poll");
 
         let _ = sqlx::query(
-            "UPDATE tasks SET status = 'polling', history_record_id = ?, updated_at = datetime('now') WHERE id = ?",
+            "UPDATE tasks SET status = 'polling', history_record_id = ?, credits = ?, \
+             updated_at = datetime('now') WHERE id = ?",
         )
         .bind(&history_record_id)
+        .bind(submit_result.credits)
         .bind(task_id)
         .execute(&queue.db.pool)
         .await;
@@ -291,10 +420,12 @@ async fn execute_task(
             }
 
             if Instant::now() >= deadline {
-                anyhow::bail!("Polling timed out after {}s", state.config.max_poll_duration_secs);
+                return Err(JimengError::PollTimeout { secs: state.config.max_poll_duration_secs }.into());
             }
 
-            let poll_result = poll::poll_status(client, session_token, &history_record_id, cookie_jar).await?;
+            let poll_result = jimeng.poll(&history_record_id).await;
+            queue.record_transport_events(task_id, &jimeng.take_attempts()).await;
+            let poll_result = poll_result?;
 
             // Update queue progress
             let _ = sqlx::query(
@@ -308,37 +439,34 @@ async fn execute_task(
             .execute(&queue.db.pool)
             .await;
 
-            if poll_result.status == poll::STATUS_FAILED {
-                let fail_code = poll_result.fail_code.as_deref().unwrap_or("unknown");
-                let fail_msg = poll_result.fail_msg.as_deref().unwrap_or("");
-                anyhow::bail!("{fail_code}: {fail_msg}");
+            if let Some(failure) = poll_result.failure() {
+                return Err(failure.into());
             }
 
             // Check for completed task (status=50 or video_url present)
-            if poll_result.status == poll::STATUS_SUCCEEDED || poll_result.video_url.is_some() {
-                if let Some(ref video_url) = poll_result.video_url {
-                    if !video_url.is_empty() {
-                        update_status(queue, task_id, "downloading").await;
-
-                        // Try to get high-quality URL
-                        if let Some(ref item_id) = poll_result.item_id {
-                            match poll::fetch_hq_video_url(client, session_token, item_id, cookie_jar).await {
-                                Ok(Some(hq_url)) => {
-                                    tracing::info!(task_id, "Got HQ video URL");
-                                    return Ok(hq_url);
-                                }
-                                Ok(None) => {}
-                                Err(e) => {
-                                    tracing::warn!(task_id, error = %e, "Failed to get HQ video URL, using preview");
-                                }
-                            }
+            if (poll_result.status == poll::STATUS_SUCCEEDED || poll_result.video_url.is_some())
+                && let Some(ref video_url) = poll_result.video_url
+                && !video_url.is_empty()
+            {
+                update_status(queue, task_id, "downloading").await;
+
+                // Try to get high-quality URL
+                if let Some(ref item_id) = poll_result.item_id {
+                    match jimeng.fetch_hq(item_id).await {
+                        Ok(Some(hq_url)) => {
+                            tracing::info!(task_id, "Got HQ video URL");
+                            return Ok(hq_url);
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            tracing::warn!(task_id, error = %e, "Failed to get HQ video URL, using preview");
                         }
-
-                        return Ok(video_url.clone());
                     }
                 }
-                // status=50 but no video_url yet — keep polling
+
+                return Ok(video_url.clone());
             }
+            // status=50 but no video_url yet — keep polling
 
             if poll_result.status != poll::STATUS_PENDING && poll_result.status != poll::STATUS_SUCCEEDED {
                 anyhow::bail!("Unexpected status {} without video_url", poll_result.status);
@@ -359,62 +487,21 @@ async fn update_status(queue: &TaskQueue, task_id: &str, status: &str) {
     .await;
 }
 
-fn classify_error(msg: &str) -> &'static str {
-    let msg_lower = msg.to_lowercase();
-
-    // Content risk: fail_starling_key patterns from jimeng frontend i18n
-    if msg_lower.contains("violates_community_guidelines")
-        || msg_lower.contains("violate_guidelines")
-        || msg_lower.contains("sensitive_text")
-        || msg_lower.contains("fail2generate_input")
-        || msg_lower.contains("inputtextrisk") || msg_lower.contains("inputimagerisk")
-        || msg_lower.contains("outputimagerisk") || msg_lower.contains("outputvideorisk")
-        || msg_lower.contains("content_violation")
-        || msg_lower.contains("平台规则") || msg_lower.contains("内容违规")
-        || msg_lower.contains("不符合") || msg_lower.contains("未通过审核")
-        || msg_lower.contains("不合适内容")
-        || msg.contains("2038") || msg.contains("2039") || msg.contains("2040")
-    {
-        "content_risk"
-    // Account blocked/banned
-    } else if msg_lower.contains("account_block")
-        || msg_lower.contains("risk_notification")
-        || msg_lower.contains("risk_control")
-        || msg_lower.contains("账号已被封禁") || msg_lower.contains("异常行为")
-        || msg_lower.contains("风控失败")
-    {
-        "account_blocked"
-    // Auth errors
-    } else if msg_lower.contains("authorization") || msg_lower.contains("unauthorized")
-        || msg_lower.contains("login") || msg_lower.contains("token")
-    {
-        "auth"
-    // Rate limit / quota
-    } else if msg_lower.contains("daily_usage_limit")
-        || msg_lower.contains("每日使用上限")
-        || msg_lower.contains("积分不足")
-    {
-        "quota"
-    // Timeout
-    } else if msg_lower.contains("timeout") || msg_lower.contains("timed out") {
-        "timeout"
-    // Generation failed (generic)
-    } else if msg.contains("100402")
-        || msg_lower.contains("generation_failed")
-        || msg_lower.contains("生成失败")
-    {
-        "generation_failed"
-    // Network
-    } else if msg_lower.contains("network") || msg_lower.contains("econnrefused") {
-        "network"
-    } else {
-        "unknown"
+/// Take the session's pacing slot for a submit: the first one was reserved
+/// when the session was picked; a resubmit waits for and counts another.
+async fn submit_slot(queue: &TaskQueue, session_id: &str, submitted: &mut bool) -> Result<()> {
+    if *submitted {
+        queue.pool.reserve_submit(session_id).await?;
     }
+    *submitted = true;
+    Ok(())
 }
 
 #[derive(sqlx::FromRow)]
-struct TaskIdRow {
+struct ClaimedTaskRow {
     id: String,
+    model: String,
+    resolution: Option<String>,
 }
 
 #[derive(sqlx::FromRow)]
@@ -422,99 +509,200 @@ struct TaskMetaRow {
     prompt: String,
     duration: i32,
     ratio: String,
-    model: String,
     resolution: Option<String>,
     request_body: Option<Vec<u8>>,
     request_content_type: Option<String>,
+    material_files: Option<String>,
+    best_effort: bool,
 }
 
-/// Process uploaded materials from a stored multipart request body.
-/// Returns empty Vec on any failure (graceful degradation).
-async fn process_materials(
-    client: &Client,
-    session_token: &str,
-    request_body: Option<&[u8]>,
-    request_content_type: Option<&str>,
-) -> Vec<UploadedMaterial> {
-    let (body, ct) = match (request_body, request_content_type) {
-        (Some(b), Some(ct)) if !b.is_empty() => (b, ct),
-        _ => return Vec::new(),
-    };
+/// Reference files of a task, on disk. Tasks queued before files were
+/// spooled keep the multipart body in the row; those are spooled now.
+async fn task_files(queue: &TaskQueue, task_id: &str, meta: &TaskMetaRow) -> Result<Vec<SpooledFile>> {
+    if let Some(manifest) = &meta.material_files {
+        return queue.uploads.open(task_id, manifest);
+    }
+    match (&meta.request_body, &meta.request_content_type) {
+        (Some(body), Some(ct)) if !body.is_empty() => queue.uploads.store(task_id, ct, body).await,
+        _ => Ok(Vec::new()),
+    }
+}
 
-    let files = match extract_multipart_files(ct, body) {
+/// Process the reference files of a task.
+///
+/// Each file is probed first: its type, dimensions and duration come from
+/// its bytes and must suit `model`. A file that cannot be read, probed or
+/// uploaded fails the task, so it is not generated without the reference.
+/// With `best_effort` it is skipped instead (and unreadable files mean no
+/// materials). Files the session uploaded before are taken from `cache`;
+/// progress of chunked uploads goes to the task's events.
+async fn process_materials(
+    jimeng: &mut JimengClient,
+    task: &TaskMetaRow,
+    task_id: &str,
+    cache: &MaterialCache<'_>,
+    model: &ModelSpec,
+) -> Result<Materials> {
+    let mut materials = Materials::default();
+    let best_effort = task.best_effort;
+    let files = match task_files(cache.queue, task_id, task).await {
         Ok(f) if !f.is_empty() => f,
-        Ok(_) => return Vec::new(),
+        Ok(_) => return Ok(materials),
+        Err(e) if best_effort => {
+            tracing::warn!(error = %e, "Failed to read uploaded files");
+            return Ok(materials);
+        }
         Err(e) => {
-            tracing::warn!(error = %e, "Failed to parse multipart files");
-            return Vec::new();
+            let message = format!("Failed to read uploaded files: {e}");
+            return Err(e.context(message));
         }
     };
 
     tracing::info!(file_count = files.len(), "Processing uploaded materials");
-    let mut materials = Vec::new();
 
-    for file in files {
-        let material_type = models::detect_material_type_from_mime(&file.content_type);
+    for (i, file) in files.into_iter().enumerate() {
+        let media = match media::probe_file(&file.path).await.map_err(|e| e.to_string()).and_then(|media| {
+            model.check_media(&media).map(|()| media)
+        }) {
+            Ok(media) => media,
+            Err(e) if best_effort => {
+                tracing::warn!(filename = file.filename, error = %e, "Unusable material, skipping");
+                continue;
+            }
+            Err(e) => {
+                let message = format!("Material {} ({}) rejected: {e}", i + 1, file.filename);
+                return Err(JimengError::InvalidInput(message).into());
+            }
+        };
+        let material_type = media.material_type;
+        let sha256 = match content_hash(&file.path).await {
+            Ok(hash) => hash,
+            Err(e) if best_effort => {
+                tracing::warn!(filename = file.filename, error = %e, "Unreadable material, skipping");
+                continue;
+            }
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("Reading material {} ({})", i + 1, file.filename))),
+        };
+        if let Some(cached) = cache.get(&sha256, material_type, &file.filename).await {
+            tracing::info!(filename = file.filename, sha256, "Reusing uploaded material");
+            materials.uploaded.push(cached);
+            materials.reused.push(sha256);
+            continue;
+        }
         tracing::info!(
             filename = file.filename,
             mime = file.content_type,
-            size = file.data.len(),
-            ?material_type,
+            size = file.size,
+            format = media.format.as_str(),
             "Uploading material"
         );
 
-        match material_type {
-            MaterialType::Image => {
-                match upload::upload_image(client, session_token, &file.data).await {
-                    Ok(uri) => {
-                        tracing::info!(filename = file.filename, %uri, "Image uploaded");
-                        materials.push(UploadedMaterial {
-                            material_type,
-                            uri: Some(uri),
-                            vid: None,
-                            width: 0,
-                            height: 0,
-                            duration: 0,
-                            fps: 0,
-                            name: file.filename,
-                        });
+        let uploaded = match material_type {
+            MaterialType::Image => match tokio::fs::read(&file.path).await {
+                Ok(data) => jimeng.upload_image(&data).await.map(|uri| {
+                    tracing::info!(filename = file.filename, %uri, "Image uploaded");
+                    UploadedMaterial {
+                        material_type,
+                        uri: Some(uri),
+                        vid: None,
+                        width: media.width,
+                        height: media.height,
+                        duration: 0,
+                        fps: 0,
+                        name: file.filename.clone(),
                     }
-                    Err(e) => tracing::warn!(filename = file.filename, error = %e, "Image upload failed"),
-                }
-            }
+                }),
+                Err(e) => Err(e.into()),
+            },
+            // Local probe values first; VOD's metadata covers what the
+            // headers did not say.
             MaterialType::Video | MaterialType::Audio => {
-                match upload::upload_media(client, session_token, &file.data, material_type).await {
-                    Ok(result) => {
-                        tracing::info!(filename = file.filename, vid = %result.vid, "Media uploaded");
-                        materials.push(UploadedMaterial {
-                            material_type,
-                            uri: None,
-                            vid: Some(result.vid),
-                            width: result.width,
-                            height: result.height,
-                            duration: result.duration,
-                            fps: result.fps,
-                            name: file.filename,
-                        });
+                let result = upload_with_progress(jimeng, cache.queue, task_id, &file).await;
+                result.map(|result| {
+                    tracing::info!(filename = file.filename, vid = %result.vid, "Media uploaded");
+                    let or_vod = |local: u32, vod: u32| if local > 0 { local } else { vod };
+                    UploadedMaterial {
+                        material_type,
+                        uri: None,
+                        vid: Some(result.vid),
+                        width: or_vod(media.width, result.width),
+                        height: or_vod(media.height, result.height),
+                        duration: or_vod(media.duration_ms, result.duration),
+                        fps: or_vod(media.fps, result.fps),
+                        name: file.filename.clone(),
                     }
-                    Err(e) => tracing::warn!(filename = file.filename, error = %e, "Media upload failed"),
-                }
+                })
+            }
+        };
+
+        match uploaded {
+            Ok(material) => {
+                cache.put(&sha256, &material).await;
+                materials.uploaded.push(material);
+            }
+            Err(e) if best_effort => {
+                tracing::warn!(filename = file.filename, error = %e, "Material upload failed, skipping");
+            }
+            Err(e) => {
+                // Keep the cause in the task's error message; the code still
+                // comes from the wrapped error.
+                let message = format!("Upload of material {} ({}) failed: {e}", i + 1, file.filename);
+                return Err(e.context(message));
             }
         }
     }
 
-    materials
+    Ok(materials)
+}
+
+/// Upload a video/audio file to VOD, logging each part of a chunked upload
+/// as an `upload` event of the task.
+async fn upload_with_progress(
+    jimeng: &mut JimengClient,
+    queue: &TaskQueue,
+    task_id: &str,
+    file: &SpooledFile,
+) -> Result<VodUploadResult> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+    let recorder = {
+        let queue = queue.clone();
+        let task_id = task_id.to_string();
+        tokio::spawn(async move {
+            while let Some(detail) = rx.recv().await {
+                queue.record_task_event(&task_id, "upload", &detail).await;
+            }
+        })
+    };
+    let filename = file.filename.clone();
+    let report = move |p: UploadProgress| {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let _ = tx.send(format!(
+            "{filename}: part {}/{} ({:.1}/{:.1} MiB)", p.part, p.parts, mib(p.bytes), mib(p.total),
+        ));
+    };
+    let result = jimeng.upload_media(&file.path, &report).await;
+    drop(report);
+    let _ = recorder.await;
+    result
+}
+
+/// Materials of a task, ready to submit.
+#[derive(Debug, Default)]
+struct Materials {
+    uploaded: Vec<UploadedMaterial>,
+    /// Content hashes of those taken from the material cache.
+    reused: Vec<String>,
 }
 
 /// A file part extracted from multipart form data.
-struct MultipartFile {
-    filename: String,
-    content_type: String,
-    data: Vec<u8>,
+pub(super) struct MultipartFile {
+    pub(super) filename: String,
+    pub(super) content_type: String,
+    pub(super) data: Vec<u8>,
 }
 
 /// Extract binary file parts from a raw multipart body.
-fn extract_multipart_files(content_type: &str, body: &[u8]) -> Result<Vec<MultipartFile>> {
+pub(super) fn extract_multipart_files(content_type: &str, body: &[u8]) -> Result<Vec<MultipartFile>> {
     let boundary = content_type
         .split("boundary=")
         .nth(1)
@@ -606,3 +794,124 @@ async fn is_task_cancelled(queue: &TaskQueue, task_id: &str) -> bool {
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::pool::{NewSession, Pacing, SessionPool};
+    use crate::queue::UploadSpool;
+
+    async fn add_session(pool: &SessionPool) -> String {
+        let new = NewSession { session_id: "sid".into(), enabled: true, ..Default::default() };
+        pool.add_session(new).await.unwrap().id
+    }
+
+    #[derive(sqlx::FromRow)]
+    struct Requeued {
+        status: String,
+        queue_reason: Option<String>,
+        quota_requeues: i64,
+    }
+
+    async fn requeued(queue: &TaskQueue, task_id: &str) -> Requeued {
+        sqlx::query_as("SELECT status, queue_reason, quota_requeues FROM tasks WHERE id = ?")
+            .bind(task_id)
+            .fetch_one(&queue.db.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requeue_on_daily_limit() {
+        let db = Database::memory().await;
+        let pool = SessionPool::new(db.clone(), Pacing::default());
+        let queue = TaskQueue::new(db.clone(), pool.clone(), 1, UploadSpool::new("unused"));
+        let first = add_session(&pool).await;
+        let second = add_session(&pool).await;
+        sqlx::query("INSERT INTO tasks (id, prompt, status) VALUES ('t1', 'p', 'submitting')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        // Another session still has quota: queued, but not blocked on quota.
+        assert!(requeue_on_daily_limit(&queue, "t1", &first, &[]).await);
+        let task = requeued(&queue, "t1").await;
+        assert_eq!(task.status, "queued");
+        assert_eq!(task.queue_reason, None);
+        assert_eq!(task.quota_requeues, 1);
+
+        assert!(requeue_on_daily_limit(&queue, "t1", &second, &[]).await);
+        assert_eq!(requeued(&queue, "t1").await.queue_reason.as_deref(), Some(QUOTA_BLOCKED_REASON));
+
+        assert!(requeue_on_daily_limit(&queue, "t1", &second, &[]).await);
+        // Past the cap the task is left to fail.
+        assert!(!requeue_on_daily_limit(&queue, "t1", &second, &[]).await);
+        assert_eq!(requeued(&queue, "t1").await.quota_requeues, MAX_QUOTA_REQUEUES);
+    }
+
+    fn task_meta(best_effort: bool) -> TaskMetaRow {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 4, 0, 0, 0, 2, 0x40]);
+        let mut body = b"--XB\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+            Content-Type: image/png\r\n\r\nnot an image\r\n\
+            --XB\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"ref.png\"\r\n\
+            Content-Type: image/png\r\n\r\n".to_vec();
+        body.extend_from_slice(&png);
+        body.extend_from_slice(b"\r\n--XB--\r\n");
+        TaskMetaRow {
+            prompt: "p".into(),
+            duration: 5,
+            ratio: "16:9".into(),
+            resolution: None,
+            request_body: Some(body),
+            request_content_type: Some("multipart/form-data; boundary=XB".into()),
+            material_files: None,
+            best_effort,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_materials_strict_and_best_effort() {
+        let db = Database::memory().await;
+        let dir = std::env::temp_dir().join(format!("spool-{}", uuid::Uuid::new_v4()));
+        let queue = TaskQueue::new(db.clone(), SessionPool::new(db, Pacing::default()), 1, UploadSpool::new(&dir));
+        let cache = MaterialCache { queue: &queue, session_id: "s1", ttl_secs: 3600 };
+        let model = crate::jimeng::model_registry::ModelRegistry::load(None).unwrap().get("seedance-2.0").unwrap();
+        // Never reached: the usable file is served from the cache.
+        let mut jimeng = JimengClient::new(
+            Client::new(),
+            Default::default(),
+            "sid",
+            None,
+            Default::default(),
+            None,
+        );
+
+        // Strict: the unusable file fails the task as invalid input.
+        let err = process_materials(&mut jimeng, &task_meta(false), "t1", &cache, &model).await.unwrap_err();
+        assert_eq!(error_code(&err), ErrorCode::InvalidInput);
+        assert!(err.to_string().contains("Material 1 (notes.txt) rejected"), "{err}");
+
+        // Best effort: it is skipped and the usable one is kept.
+        let sha256 = content_hash(&dir.join("t1").join("1")).await.unwrap();
+        cache.put(&sha256, &UploadedMaterial {
+            material_type: MaterialType::Image,
+            uri: Some("tos-cn-i/1".into()),
+            vid: None,
+            width: 1024,
+            height: 576,
+            duration: 0,
+            fps: 0,
+            name: "ref.png".into(),
+        }).await;
+        let materials = process_materials(&mut jimeng, &task_meta(true), "t2", &cache, &model).await.unwrap();
+        assert_eq!(materials.uploaded.len(), 1);
+        assert_eq!(materials.uploaded[0].uri.as_deref(), Some("tos-cn-i/1"));
+        assert_eq!(materials.reused, vec![sha256]);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -3,28 +3,30 @@ use std::time::Duration;
 
 use axum::{
     Extension, Json, Router,
-    body::Bytes,
-    extract::State,
-    http::{HeaderMap, StatusCode},
+    extract::{DefaultBodyLimit, FromRequest, Multipart, Request, State, multipart::MultipartError},
+    http::StatusCode,
     routing::{get, post},
 };
 
 use crate::AppState;
 use crate::auth::middleware::{Caller, require_scope};
 use crate::auth::usage as usage_tracker;
-use crate::queue::{CreateTaskRequest, TaskStatus};
+use crate::queue::{CreateTaskRequest, SpooledFile, TaskStatus, probe_spooled};
+
+/// Largest JSON body of a create request; multipart ones, which carry
+/// reference files, are limited by `MAX_UPLOAD_MB`.
+const JSON_BODY_LIMIT: usize = 2 * 1024 * 1024;
 
 /// Compatibility layer: accepts the same API format as jimeng-free-api-all
 /// but converts to async task model internally.
 ///
 /// `POST /v1/videos/generations` → enqueue task, return task info.
-/// `GET /v1/models` → proxy to upstream.
+/// `GET /v1/models` → models from the registry.
 /// `GET /ping` → health check.
 async fn compat_video_generations(
     State(state): State<Arc<AppState>>,
     Extension(caller): Extension<Caller>,
-    headers: HeaderMap,
-    body: Bytes,
+    request: Request,
 ) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
     // Scope check
     if let Err(resp) = require_scope(&caller, "video:create") {
@@ -35,49 +37,43 @@ async fn compat_video_generations(
     }
 
     // Daily quota check for API key callers
-    if let Caller::ApiKey { ref key_id, daily_quota, .. } = caller {
-        if daily_quota > 0 {
-            let today_tasks = usage_tracker::today_task_count(&state.db.pool, key_id)
-                .await
-                .unwrap_or(0);
-            if today_tasks >= daily_quota {
-                return Err((
-                    StatusCode::TOO_MANY_REQUESTS,
-                    Json(serde_json::json!({
-                        "error": "Daily quota exceeded",
-                        "daily_quota": daily_quota,
-                        "used": today_tasks,
-                    })),
-                ));
-            }
+    if let Caller::ApiKey { ref key_id, daily_quota, .. } = caller
+        && daily_quota > 0
+    {
+        let today_tasks = usage_tracker::today_task_count(&state.db.pool, key_id)
+            .await
+            .unwrap_or(0);
+        if today_tasks >= daily_quota {
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(serde_json::json!({
+                    "error": "Daily quota exceeded",
+                    "daily_quota": daily_quota,
+                    "used": today_tasks,
+                })),
+            ));
         }
     }
-    // Try to parse the multipart body to extract prompt/model/duration/ratio.
-    // For now, store the raw body and forward it to upstream in the worker.
-    let content_type = headers
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
+    let body = read_create_body(&state, request)
+        .await
+        .map_err(|(status, e)| (status, Json(serde_json::json!({ "error": e }))))?;
 
     // Extract fields from multipart or JSON body
-    let (prompt, model, duration, ratio, webhook_url) = if content_type.contains("multipart") {
-        let f = extract_multipart_fields(content_type, &body);
-        (f.prompt, f.model, f.duration, f.ratio, f.webhook_url)
-    } else {
-        // Try JSON
-        match serde_json::from_slice::<serde_json::Value>(&body) {
-            Ok(v) => (
-                v.get("prompt").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                v.get("model").and_then(|v| v.as_str()).map(String::from),
-                v.get("duration").and_then(|v| v.as_i64()).map(|v| v as i32),
-                v.get("ratio").and_then(|v| v.as_str()).map(String::from),
-                v.get("webhook_url").and_then(|v| v.as_str()).map(String::from),
-            ),
-            Err(_) => ("".to_string(), None, None, None, None),
+    let (prompt, model, duration, ratio, webhook_url, best_effort) = match &body {
+        CreateBody::Multipart { fields: f, .. } => {
+            (f.prompt.clone(), f.model.clone(), f.duration, f.ratio.clone(), f.webhook_url.clone(), f.best_effort)
         }
+        CreateBody::Json(v) => (
+            v.get("prompt").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            v.get("model").and_then(|v| v.as_str()).map(String::from),
+            v.get("duration").and_then(|v| v.as_i64()).map(|v| v as i32),
+            v.get("ratio").and_then(|v| v.as_str()).map(String::from),
+            v.get("webhook_url").and_then(|v| v.as_str()).map(String::from),
+            v.get("best_effort").and_then(|v| v.as_bool()).unwrap_or(false),
+        ),
     };
 
-    let req = CreateTaskRequest {
+    let mut req = CreateTaskRequest {
         prompt,
         duration,
         ratio,
@@ -86,11 +82,21 @@ async fn compat_video_generations(
         files: None,
         webhook_url,
         webhook_secret: None,
+        best_effort,
     };
+    let uploads = probe_spooled(body.files()).await;
+    let checked = req.apply_model(&state.models, &uploads).and_then(|()| {
+        match state.models.get(req.model.as_deref().unwrap_or_default()) {
+            Some(model) if model.is_image() => Err(format!("{} is an image model; use /v1/images/generations", model.id)),
+            _ => Ok(()),
+        }
+    });
+    if let Err(e) = checked {
+        body.discard(&state).await;
+        return Err((StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))));
+    }
 
-    let task = state
-        .queue
-        .enqueue(req, Some(body.to_vec()), Some(content_type.to_string()))
+    let task = body.enqueue(&state, req)
         .await
         .map_err(|e| {
             (
@@ -125,6 +131,7 @@ async fn compat_video_generations(
 }
 
 /// Parsed text fields from a multipart form body.
+#[derive(Default)]
 struct MultipartFields {
     prompt: String,
     model: Option<String>,
@@ -132,72 +139,134 @@ struct MultipartFields {
     ratio: Option<String>,
     resolution: Option<String>,
     webhook_url: Option<String>,
+    best_effort: bool,
 }
 
-/// Parse multipart form data to extract text fields.
-fn extract_multipart_fields(content_type: &str, body: &[u8]) -> MultipartFields {
-    let boundary = content_type
-        .split("boundary=")
-        .nth(1)
-        .unwrap_or("")
-        .trim();
-
-    if boundary.is_empty() {
-        return MultipartFields { prompt: String::new(), model: None, duration: None, ratio: None, resolution: None, webhook_url: None };
+impl MultipartFields {
+    fn set(&mut self, name: &str, value: &str) {
+        match name {
+            "prompt" => self.prompt = value.to_string(),
+            "model" => self.model = Some(value.to_string()),
+            "duration" => self.duration = value.parse().ok(),
+            "ratio" => self.ratio = Some(value.to_string()),
+            "resolution" => self.resolution = Some(value.to_string()),
+            "webhook_url" => self.webhook_url = Some(value.to_string()),
+            "best_effort" => self.best_effort = matches!(value, "true" | "1"),
+            _ => {}
+        }
     }
+}
 
-    let body_str = String::from_utf8_lossy(body);
-    let mut fields = MultipartFields {
-        prompt: String::new(),
-        model: None,
-        duration: None,
-        ratio: None,
-        resolution: None,
-        webhook_url: None,
-    };
+/// Body of a create request.
+enum CreateBody {
+    /// `Null` if it is not valid JSON.
+    Json(serde_json::Value),
+    /// A form whose files were streamed to the upload spool under `task_id`.
+    Multipart {
+        fields: MultipartFields,
+        task_id: String,
+        content_type: String,
+        files: Vec<SpooledFile>,
+    },
+}
 
-    // Simple multipart parser for text fields
-    for part in body_str.split(&format!("--{boundary}")) {
-        if let Some(name_start) = part.find("name=\"") {
-            let name_start = name_start + 6;
-            if let Some(name_end) = part[name_start..].find('"') {
-                let name = &part[name_start..name_start + name_end];
+impl CreateBody {
+    fn files(&self) -> &[SpooledFile] {
+        match self {
+            Self::Json(_) => &[],
+            Self::Multipart { files, .. } => files,
+        }
+    }
 
-                // Skip file fields
-                if part.contains("filename=\"") {
-                    continue;
+    /// Queue the task, with the spooled files if any.
+    async fn enqueue(self, state: &AppState, req: CreateTaskRequest) -> anyhow::Result<crate::queue::TaskRecord> {
+        match self {
+            Self::Json(_) => state.queue.enqueue(req, None, None).await,
+            Self::Multipart { task_id, content_type, files, .. } => {
+                let result = state.queue.enqueue_spooled(task_id.clone(), req, content_type, &files).await;
+                if result.is_err() {
+                    state.queue.uploads().remove(&task_id).await;
                 }
+                result
+            }
+        }
+    }
 
-                // Extract value (after double CRLF)
-                if let Some(value_start) = part.find("\r\n\r\n") {
-                    let value = part[value_start + 4..].trim_end_matches("\r\n").trim();
-                    match name {
-                        "prompt" => fields.prompt = value.to_string(),
-                        "model" => fields.model = Some(value.to_string()),
-                        "duration" => fields.duration = value.parse().ok(),
-                        "ratio" => fields.ratio = Some(value.to_string()),
-                        "resolution" => fields.resolution = Some(value.to_string()),
-                        "webhook_url" => fields.webhook_url = Some(value.to_string()),
-                        _ => {}
-                    }
+    /// Delete the spooled files of a rejected request.
+    async fn discard(self, state: &AppState) {
+        if let Self::Multipart { task_id, .. } = self {
+            state.queue.uploads().remove(&task_id).await;
+        }
+    }
+}
+
+/// Read a create request: a JSON body, or a multipart form whose text
+/// fields are collected and whose files are streamed to disk.
+async fn read_create_body(state: &AppState, request: Request) -> Result<CreateBody, (StatusCode, String)> {
+    let content_type = request.headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    if !content_type.contains("multipart") {
+        let bytes = axum::body::to_bytes(request.into_body(), JSON_BODY_LIMIT)
+            .await
+            .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, format!("Failed to read request body: {e}")))?;
+        return Ok(CreateBody::Json(serde_json::from_slice(&bytes).unwrap_or_default()));
+    }
+
+    let multipart_error = |e: MultipartError| (e.status(), e.body_text());
+    let mut multipart = Multipart::from_request(request, &()).await.map_err(|e| (e.status(), e.body_text()))?;
+    let task_id = uuid::Uuid::new_v4().to_string();
+    let spool = state.queue.uploads();
+    let mut fields = MultipartFields::default();
+    let mut files = Vec::new();
+    let read = async {
+        while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+            let name = field.name().unwrap_or_default().to_string();
+            match field.file_name().map(str::to_string) {
+                // Parts without a file name are ignored, as before.
+                Some(filename) if filename.is_empty() => {}
+                Some(filename) => {
+                    let part_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+                    let file = spool.store_stream(&task_id, files.len(), filename, part_type, field)
+                        .await
+                        .map_err(|e| match e.downcast::<MultipartError>() {
+                            Ok(e) => multipart_error(e),
+                            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to store reference file: {e:#}")),
+                        })?;
+                    files.push(file);
                 }
+                None => fields.set(&name, field.text().await.map_err(multipart_error)?.trim()),
             }
         }
+        Ok(())
+    };
+    if let Err(e) = read.await {
+        spool.remove(&task_id).await;
+        return Err(e);
     }
-
-    fields
+    Ok(CreateBody::Multipart { fields, task_id, content_type, files })
 }
 
-async fn compat_models() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
-        "object": "list",
-        "data": [
-            { "id": "seedance-2.0", "object": "model" },
-            { "id": "seedance-2.0-pro", "object": "model" },
-            { "id": "seedance-2.0-fast", "object": "model" },
-            { "id": "jimeng-5.0", "object": "model" },
-        ]
-    }))
+/// OpenAI-style model list generated from the model registry, with each
+/// model's parameters as extensions.
+async fn compat_models(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let data: Vec<serde_json::Value> = state.models.list().models.into_iter().map(|m| {
+        serde_json::json!({
+            "id": m.id,
+            "object": "model",
+            "owned_by": "jimeng",
+            "kind": m.kind,
+            "aliases": m.aliases,
+            "durations": m.durations,
+            "ratios": m.ratios,
+            "resolutions": m.resolutions,
+            "materials": m.materials,
+            "credits": m.credits,
+        })
+    }).collect();
+    Json(serde_json::json!({ "object": "list", "data": data }))
 }
 
 async fn compat_ping() -> &'static str {
@@ -232,14 +301,13 @@ fn parse_openai_size(size: &str) -> Result<(String, String), String> {
 /// OpenAI-compatible `POST /v1/images/generations`.
 ///
 /// Accepts standard OpenAI fields: `prompt`, `model`, `size`, `n`, `response_format`.
-/// Also accepts extensions: `ratio`, `resolution`.
+/// Also accepts extensions: `ratio`, `resolution`, `best_effort`.
 ///
 /// Synchronous: enqueues task, waits for completion, returns OpenAI response format.
 async fn compat_image_generations(
     State(state): State<Arc<AppState>>,
     Extension(caller): Extension<Caller>,
-    headers: HeaderMap,
-    body: Bytes,
+    request: Request,
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
     // Scope check
     if let Err(resp) = require_scope(&caller, "video:create") {
@@ -250,63 +318,64 @@ async fn compat_image_generations(
     }
 
     // Daily quota check for API key callers
-    if let Caller::ApiKey { ref key_id, daily_quota, .. } = caller {
-        if daily_quota > 0 {
-            let today_tasks = usage_tracker::today_task_count(&state.db.pool, key_id)
-                .await
-                .unwrap_or(0);
-            if today_tasks >= daily_quota {
-                return Err((
-                    StatusCode::TOO_MANY_REQUESTS,
-                    Json(serde_json::json!({
-                        "error": {
-                            "message": "Daily quota exceeded",
-                            "type": "rate_limit_error",
-                            "code": "daily_quota_exceeded"
-                        }
-                    })),
-                ));
-            }
+    if let Caller::ApiKey { ref key_id, daily_quota, .. } = caller
+        && daily_quota > 0
+    {
+        let today_tasks = usage_tracker::today_task_count(&state.db.pool, key_id)
+            .await
+            .unwrap_or(0);
+        if today_tasks >= daily_quota {
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(serde_json::json!({
+                    "error": {
+                        "message": "Daily quota exceeded",
+                        "type": "rate_limit_error",
+                        "code": "daily_quota_exceeded"
+                    }
+                })),
+            ));
         }
     }
 
-    let content_type = headers
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("");
+    let body = read_create_body(&state, request).await.map_err(|(status, e)| {
+        (status, Json(serde_json::json!({
+            "error": { "message": e, "type": "invalid_request_error" }
+        })))
+    })?;
 
     // Parse request fields
-    let (prompt, model, mut ratio, mut resolution, webhook_url) = if content_type.contains("multipart") {
-        let f = extract_multipart_fields(content_type, &body);
-        (f.prompt, f.model, f.ratio, f.resolution, f.webhook_url)
-    } else {
-        match serde_json::from_slice::<serde_json::Value>(&body) {
-            Ok(v) => {
-                // If OpenAI `size` field is provided, parse it into ratio/resolution
-                let (size_ratio, size_resolution) = if let Some(size) = v.get("size").and_then(|v| v.as_str()) {
-                    let (r, res) = parse_openai_size(size).map_err(|e| {
-                        (StatusCode::BAD_REQUEST, Json(serde_json::json!({
-                            "error": { "message": e, "type": "invalid_request_error", "code": "invalid_size" }
-                        })))
-                    })?;
-                    (Some(r), Some(res))
-                } else {
-                    (None, None)
-                };
-
+    let fields = match &body {
+        CreateBody::Multipart { fields: f, .. } => {
+            Ok((f.prompt.clone(), f.model.clone(), f.ratio.clone(), f.resolution.clone(), f.webhook_url.clone(), f.best_effort))
+        }
+        CreateBody::Json(v) => {
+            // If OpenAI `size` field is provided, parse it into ratio/resolution
+            let size = v.get("size").and_then(|v| v.as_str()).map(parse_openai_size).transpose();
+            size.map(|size| {
+                let (size_ratio, size_resolution) = size.unzip();
                 (
                     v.get("prompt").and_then(|v| v.as_str()).unwrap_or("").to_string(),
                     v.get("model").and_then(|v| v.as_str()).map(String::from),
                     v.get("ratio").and_then(|v| v.as_str()).map(String::from).or(size_ratio),
                     v.get("resolution").and_then(|v| v.as_str()).map(String::from).or(size_resolution),
                     v.get("webhook_url").and_then(|v| v.as_str()).map(String::from),
+                    v.get("best_effort").and_then(|v| v.as_bool()).unwrap_or(false),
                 )
-            }
-            Err(_) => ("".to_string(), None, None, None, None),
+            })
+        }
+    };
+    let (prompt, model, ratio, resolution, webhook_url, best_effort) = match fields {
+        Ok(fields) => fields,
+        Err(e) => {
+            return Err((StatusCode::BAD_REQUEST, Json(serde_json::json!({
+                "error": { "message": e, "type": "invalid_request_error", "code": "invalid_size" }
+            }))));
         }
     };
 
     if prompt.is_empty() {
+        body.discard(&state).await;
         return Err((
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
@@ -319,18 +388,7 @@ async fn compat_image_generations(
         ));
     }
 
-    // Apply defaults
-    if ratio.is_none() { ratio = Some("1:1".to_string()); }
-    if resolution.is_none() { resolution = Some("2k".to_string()); }
-
-    // For multipart requests, pass the raw body so the worker can extract reference images
-    let (request_body, request_ct) = if content_type.contains("multipart") {
-        (Some(body.to_vec()), Some(content_type.to_string()))
-    } else {
-        (None, None)
-    };
-
-    let req = CreateTaskRequest {
+    let mut req = CreateTaskRequest {
         prompt,
         duration: None,
         ratio,
@@ -339,11 +397,24 @@ async fn compat_image_generations(
         files: None,
         webhook_url,
         webhook_secret: None,
+        best_effort,
     };
+    let uploads = probe_spooled(body.files()).await;
+    let invalid = |message: String| (StatusCode::BAD_REQUEST, Json(serde_json::json!({
+        "error": { "message": message, "type": "invalid_request_error", "code": "invalid_parameter" }
+    })));
+    let checked = req.apply_model(&state.models, &uploads).and_then(|()| {
+        match state.models.get(req.model.as_deref().unwrap_or_default()) {
+            Some(model) if model.is_image() => Ok(()),
+            _ => Err(format!("{} is not an image model", req.model.as_deref().unwrap_or_default())),
+        }
+    });
+    if let Err(e) = checked {
+        body.discard(&state).await;
+        return Err(invalid(e));
+    }
 
-    let task = state
-        .queue
-        .enqueue(req, request_body, request_ct)
+    let task = body.enqueue(&state, req)
         .await
         .map_err(|e| {
             (
@@ -448,9 +519,10 @@ async fn compat_image_generations(
 }
 
 pub fn compat_router(state: Arc<AppState>) -> Router {
+    let upload_limit = DefaultBodyLimit::max(state.config.max_upload_mb.saturating_mul(1024 * 1024) as usize);
     Router::new()
-        .route("/v1/videos/generations", post(compat_video_generations))
-        .route("/v1/images/generations", post(compat_image_generations))
+        .route("/v1/videos/generations", post(compat_video_generations).layer(upload_limit))
+        .route("/v1/images/generations", post(compat_image_generations).layer(upload_limit))
         .route("/v1/models", get(compat_models))
         .with_state(state)
 }
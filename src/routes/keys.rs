@@ -13,6 +13,7 @@ use crate::AppState;
 use crate::auth::api_key;
 use crate::auth::middleware::{Caller, require_scope};
 
+#[allow(clippy::result_large_err)]
 fn require_admin_if_api_key(caller: Option<Extension<Caller>>) -> Result<(), Response> {
     if let Some(Extension(caller)) = caller {
         require_scope(&caller, "admin")?;
@@ -88,7 +89,10 @@ async fn list_keys(
 ) -> Result<Json<serde_json::Value>, Response> {
     require_admin_if_api_key(caller)?;
 
-    let keys = api_key::list_all(&state.db.pool).await.map_err(|e| {
+    let keys = api_key::list_all(&state.db.pool).await.and_then(|keys| {
+        keys.into_iter().map(api_key::ApiKeyRecord::open_raw_key).collect::<anyhow::Result<Vec<_>>>()
+    });
+    let keys = keys.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": e.to_string() })),
@@ -108,6 +112,7 @@ async fn get_key(
 
     let key = api_key::get_by_id(&state.db.pool, &id)
         .await
+        .and_then(|key| key.map(api_key::ApiKeyRecord::open_raw_key).transpose())
         .map_err(|e| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -3,6 +3,7 @@ pub mod compat;
 mod keys;
 mod logs;
 mod me;
+mod models;
 mod sessions;
 mod tasks;
 mod usage;
@@ -13,10 +14,11 @@ use axum::Router;
 
 use crate::AppState;
 
-/// Admin API routes (sessions + logs + keys + usage management) — protected by auth middleware
+/// Admin API routes (sessions + logs + keys + usage + models management) — protected by auth middleware
 pub fn admin_api_router(state: Arc<AppState>) -> Router {
     Router::new()
         .merge(sessions::router(state.clone()))
+        .merge(models::router(state.clone()))
         .merge(logs::router(state.clone()))
         .merge(keys::router(state.clone()))
         .merge(usage::router(state))
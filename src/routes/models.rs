@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+};
+
+use crate::AppState;
+use crate::jimeng::model_registry::ModelList;
+
+/// Full model registry, including session requirements and benefit types.
+async fn list_models(State(state): State<Arc<AppState>>) -> Json<ModelList> {
+    Json(state.models.list())
+}
+
+/// Re-read `MODELS_FILE`. An invalid file is rejected and the current
+/// registry stays in use.
+async fn reload_models(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ModelList>, (StatusCode, Json<serde_json::Value>)> {
+    state.models.reload().map(Json).map_err(|e| {
+        tracing::error!(error = format!("{e:#}"), "Model registry reload failed");
+        (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": format!("{e:#}") })))
+    })
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/models", get(list_models))
+        .route("/models/reload", post(reload_models))
+        .with_state(state)
+}
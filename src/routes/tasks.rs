@@ -9,7 +9,7 @@ use axum::{
 use serde::Deserialize;
 
 use crate::AppState;
-use crate::queue::CreateTaskRequest;
+use crate::queue::{CreateTaskRequest, FilesRemoved};
 
 #[derive(Deserialize)]
 struct ListParams {
@@ -44,10 +44,32 @@ async fn get_task(
     Ok(Json(serde_json::json!({ "task": task })))
 }
 
+async fn task_events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    state
+        .queue
+        .get_task(&id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let events = state
+        .queue
+        .task_events(&id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(serde_json::json!({ "events": events })))
+}
+
 async fn create_task(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<CreateTaskRequest>,
+    Json(mut req): Json<CreateTaskRequest>,
 ) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
+    req.apply_model(&state.models, &[])
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))))?;
+
     let task = state
         .queue
         .enqueue(req, None, None)
@@ -87,13 +109,16 @@ async fn cancel_task(
 async fn retry_task(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
     let task = state
         .queue
         .retry_task(&id)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .map_err(|e| {
+            let status = if e.is::<FilesRemoved>() { StatusCode::CONFLICT } else { StatusCode::INTERNAL_SERVER_ERROR };
+            (status, Json(serde_json::json!({ "error": e.to_string() })))
+        })?
+        .ok_or((StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": "Task not found" }))))?;
 
     Ok((
         StatusCode::CREATED,
@@ -120,6 +145,7 @@ pub fn router(state: Arc<AppState>) -> Router {
     Router::new()
         .route("/tasks", get(list_tasks).post(create_task))
         .route("/tasks/{id}", get(get_task))
+        .route("/tasks/{id}/events", get(task_events))
         .route("/tasks/{id}/cancel", post(cancel_task))
         .route("/tasks/{id}/retry", post(retry_task))
         .route("/stats", get(get_stats))
@@ -1,4 +1,4 @@
-//! Webhook delivery for task completion notifications.
+//! Webhook delivery for task completion notifications and gateway events.
 //! Uses a SQLite outbox pattern with exponential backoff retries.
 
 use std::time::Duration;
@@ -51,7 +51,7 @@ pub async fn enqueue_delivery(pool: &SqlitePool, task_id: &str) {
             .filter(|s| !s.is_empty())
             .collect();
 
-        let result = if urls.len() > 1 || urls.first().map_or(false, |u| u.contains("image")) {
+        let result = if urls.len() > 1 || urls.first().is_some_and(|u| u.contains("image")) {
             serde_json::json!({ "image_urls": urls, "video_url": null })
         } else {
             serde_json::json!({ "video_url": urls.first(), "image_urls": [] })
@@ -96,6 +96,45 @@ pub async fn enqueue_delivery(pool: &SqlitePool, task_id: &str) {
     }
 }
 
+/// Enqueue a gateway event (not tied to a task) for delivery to `webhook_url`.
+///
+/// `event_key` is unique in the outbox, so the same event is only ever
+/// enqueued once.
+pub async fn enqueue_event(
+    pool: &SqlitePool,
+    webhook_url: &str,
+    webhook_secret: Option<&str>,
+    event_key: &str,
+    event: &str,
+    data: serde_json::Value,
+) -> anyhow::Result<()> {
+    let delivery_id = uuid::Uuid::new_v4().to_string();
+    let mut payload = serde_json::json!({
+        "event": event,
+        "delivery_id": delivery_id,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+    });
+    if let (Some(obj), serde_json::Value::Object(data)) = (payload.as_object_mut(), data) {
+        obj.extend(data);
+    }
+
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO webhook_deliveries (id, event_key, webhook_url, webhook_secret, payload) \
+         VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(&delivery_id)
+    .bind(event_key)
+    .bind(webhook_url)
+    .bind(crate::crypto::seal_opt(webhook_secret)?)
+    .bind(payload.to_string())
+    .execute(pool)
+    .await?;
+    if result.rows_affected() > 0 {
+        tracing::info!(event, event_key, "Event webhook enqueued");
+    }
+    Ok(())
+}
+
 /// Background worker that dispatches pending webhook deliveries.
 pub async fn dispatcher_loop(pool: SqlitePool) {
     let client = Client::builder()
@@ -118,7 +157,7 @@ pub async fn dispatcher_loop(pool: SqlitePool) {
                WHERE status IN ('pending', 'retrying') \
                  AND next_attempt_at <= datetime('now') \
                ORDER BY next_attempt_at ASC LIMIT 1 \
-             ) RETURNING id, task_id, webhook_url, webhook_secret, payload, attempt_count"
+             ) RETURNING id, task_id, event_key, webhook_url, webhook_secret, payload, attempt_count"
         )
         .fetch_optional(&pool)
         .await;
@@ -137,6 +176,7 @@ pub async fn dispatcher_loop(pool: SqlitePool) {
         tracing::info!(
             delivery_id = delivery.id,
             task_id = delivery.task_id,
+            event_key = delivery.event_key,
             attempt,
             url = &delivery.webhook_url[..delivery.webhook_url.len().min(80)],
             "Sending webhook"
@@ -147,22 +187,31 @@ pub async fn dispatcher_loop(pool: SqlitePool) {
             .post(&delivery.webhook_url)
             .header("Content-Type", "application/json")
             .header("X-Jimeng-Event", extract_event(&delivery.payload))
-            .header("X-Jimeng-Task-Id", &delivery.task_id)
             .header("X-Jimeng-Delivery-Id", &delivery.id)
             .header("X-Jimeng-Attempt", attempt.to_string());
+        if let Some(task_id) = &delivery.task_id {
+            req = req.header("X-Jimeng-Task-Id", task_id);
+        }
 
-        // Sign payload if secret exists
-        if let Some(ref secret) = delivery.webhook_secret {
-            if !secret.is_empty() {
-                use hmac::{Hmac, Mac};
-                use sha2::Sha256;
-                type HmacSha256 = Hmac<Sha256>;
+        // Sign payload if secret exists (stored sealed; opened only here)
+        let webhook_secret = match crate::crypto::open_opt(delivery.webhook_secret.as_deref()) {
+            Ok(secret) => secret,
+            Err(e) => {
+                handle_retry(&pool, &delivery.id, attempt, 0, &format!("cannot decrypt webhook secret: {e}")).await;
+                continue;
+            }
+        };
+        if let Some(ref secret) = webhook_secret
+            && !secret.is_empty()
+        {
+            use hmac::{Hmac, Mac};
+            use sha2::Sha256;
+            type HmacSha256 = Hmac<Sha256>;
 
-                if let Ok(mut mac) = HmacSha256::new_from_slice(secret.as_bytes()) {
-                    mac.update(delivery.payload.as_bytes());
-                    let signature = hex::encode(mac.finalize().into_bytes());
-                    req = req.header("X-Jimeng-Signature", format!("sha256={signature}"));
-                }
+            if let Ok(mut mac) = HmacSha256::new_from_slice(secret.as_bytes()) {
+                mac.update(delivery.payload.as_bytes());
+                let signature = hex::encode(mac.finalize().into_bytes());
+                req = req.header("X-Jimeng-Signature", format!("sha256={signature}"));
             }
         }
 
@@ -253,7 +302,9 @@ struct WebhookTaskRow {
 #[derive(sqlx::FromRow)]
 struct DeliveryRow {
     id: String,
-    task_id: String,
+    /// Set for task deliveries; gateway events have an `event_key` instead.
+    task_id: Option<String>,
+    event_key: Option<String>,
     webhook_url: String,
     webhook_secret: Option<String>,
     payload: String,